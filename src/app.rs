@@ -1,30 +1,57 @@
+use crate::audio_analysis::{Oscilloscope, Spectrum};
 use crate::audio_devices::AudioHandler;
-use crate::owl_control::command_processor::OwlCommandProcessor;
-use crate::owl_control::resources::ResourceState;
-use crate::{
-    grid::Grid,
-    midi_devices::{MidiDeviceSelection, MidiInputHandle, MidiOutputHandle},
+use crate::device_session::DeviceSession;
+use crate::file_io::{self, FileDialogs, FileKind, OpenedFile};
+use crate::grid::Grid;
+use crate::log_view::{LogBuffer, LogFilter};
+use crate::midi_devices::{
+    pair_owl_ports, ControllerInputs, MidiDeviceSelection, MIDI_CLIENT_NAME, MIDI_RESCAN_INTERVAL,
 };
+use crate::midi_monitor::MonitorFilter;
+use crate::notification;
+use crate::owl_control::automation::{Automation, AutomationState};
+use crate::owl_control::command_processor::DeviceIdentity;
+use crate::owl_control::midi_learn::ControllerMappings;
+use crate::owl_control::modulator::ModulationEngine;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::owl_control::modulator::ModulationThread;
+use crate::owl_control::requests::RequestState;
+use crate::owl_control::resources::resource_from_header;
+use crate::owl_control::settings::SettingsProfile;
+use crate::owl_control::snapshot::{Morph, SnapshotLibrary};
+use crate::project::{Project, PROJECT_EXTENSION};
+use crate::sysex_librarian::{SendQueue, SysexFile};
+use crate::wave_capture::WaveCapture;
 use cpal::traits::DeviceTrait;
 use cpal::HostId;
-use eframe::egui::{
-    self,
-    plot::{Bar, BarChart},
-};
+use eframe::egui;
+use eframe::egui::plot::{Bar, BarChart};
 use eframe::epaint::text::LayoutJob;
 use eframe::epaint::{Color32, FontId};
 use egui::plot::{Plot, Points};
 use egui::Ui;
-use itertools::{EitherOrBoth::Both, EitherOrBoth::Left, EitherOrBoth::Right, Itertools};
-use owl_midi::{
-    OpenWareMidiControl, OpenWareMidiSysexCommand, PatchParameterId, SYSEX_CONFIGURATIONS,
-};
+use itertools::EitherOrBoth::{Both, Left, Right};
+use itertools::Itertools;
+use owl_midi::{OpenWareMidiSysexCommand, PatchParameterId};
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::fs::File;
 use std::io::Cursor;
-use std::sync::Mutex;
-use std::{fs::File, sync::Arc};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, warn};
 use wavetable::WavHandler;
-use wmidi::{MidiMessage, U7};
+
+mod automation;
+mod controllers;
+mod librarian;
+mod log;
+mod modulators;
+mod monitor;
+mod parameters;
+mod program;
+mod scope;
+mod settings;
 
 #[derive(PartialEq, Eq)]
 enum MenuPage {
@@ -32,6 +59,12 @@ enum MenuPage {
     Patches,
     Resources,
     Settings,
+    Messages,
+    Stats,
+    Automation,
+    Monitor,
+    Librarian,
+    Scope,
 }
 
 /// We derive Deserialize/Serialize so we can persist app state on shutdown.
@@ -39,19 +72,25 @@ enum MenuPage {
 #[serde(default)] // if we add new fields, give them default values when deserializing old state
 pub struct OwlWaveApp {
     active_wave_id: usize,
-    midi_log: Arc<Mutex<Vec<u8>>>,
+    /// Parameter snapshots for every patch, kept between sessions
+    snapshots: SnapshotLibrary,
+    /// External controller mappings for every patch, kept between sessions
+    controller_mappings: ControllerMappings,
+    /// Show desktop notification when patch reports an error
+    notify_errors: bool,
 
     #[serde(skip)]
     midi_devices: MidiDeviceSelection,
+    /// Connected devices, there's always at least one session
     #[serde(skip)]
-    midi_input: MidiInputHandle<Arc<Mutex<Vec<u8>>>>,
-    #[serde(skip)]
-    owl_command_processor: OwlCommandProcessor,
+    sessions: Vec<DeviceSession>,
     #[serde(skip)]
-    midi_output: MidiOutputHandle,
+    active_session: usize,
     #[serde(skip)]
     midi_loaded: bool,
     #[serde(skip)]
+    midi_scan_time: f64,
+    #[serde(skip)]
     show_about: bool,
     #[serde(skip)]
     grid: Grid,
@@ -65,33 +104,85 @@ pub struct OwlWaveApp {
     selected_audio_output: Option<usize>,
     #[serde(skip)]
     menu_page: MenuPage,
+    #[serde(skip)]
+    file_dialogs: FileDialogs,
+    /// File names for data requested from devices, by session index
+    #[serde(skip)]
+    pending_downloads: HashMap<usize, String>,
+    #[serde(skip)]
+    file_error: Option<String>,
+    /// Patch button pressed in UI, released when pointer is no longer down on it
+    #[serde(skip)]
+    held_button: Option<u8>,
+    #[serde(skip)]
+    snapshot_name: String,
+    #[serde(skip)]
+    morph: Morph,
+    #[serde(skip)]
+    automation: Automation,
+    /// Lane edited on automation page
+    #[serde(skip)]
+    automation_lane: Option<PatchParameterId>,
+    /// Index of automation point that is being dragged
+    #[serde(skip)]
+    dragged_point: Option<usize>,
+    /// Modulators shared with the thread that sends them to active session
+    #[serde(skip)]
+    modulation: Arc<Mutex<ModulationEngine>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    modulation_thread: Option<ModulationThread>,
+    /// Input ports of external controllers, connected while they are needed
+    #[serde(skip)]
+    controller_inputs: Option<ControllerInputs>,
+    /// Clicking a parameter name waits for a controller CC to map to it
+    #[serde(skip)]
+    midi_learn: bool,
+    #[serde(skip)]
+    learning: Option<PatchParameterId>,
+    #[serde(skip)]
+    monitor_filter: MonitorFilter,
+    /// Files loaded into sysex librarian
+    #[serde(skip)]
+    sysex_files: Vec<SysexFile>,
+    /// Librarian messages that are sent to active device
+    #[serde(skip)]
+    sysex_queue: SendQueue,
+    /// Log of last decoded file or capture
+    #[serde(skip)]
+    sysex_decoded: String,
+    /// Records collected by tracing layer
+    #[serde(skip)]
+    log: LogBuffer,
+    #[serde(skip)]
+    log_filter: LogFilter,
+    /// Shows samples captured from audio input
+    #[serde(skip)]
+    oscilloscope: Oscilloscope,
+    #[serde(skip)]
+    spectrum: Spectrum,
+    #[serde(skip)]
+    wave_capture: WaveCapture,
+    /// Result of last wavetable capture
+    #[serde(skip)]
+    wave_capture_status: Option<Result<String, String>>,
 }
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 impl Default for OwlWaveApp {
     fn default() -> Self {
-        let midi_log = Arc::new(Mutex::new(Vec::new()));
-        let midi_input = MidiInputHandle::new(
-            "OWL wave",
-            0,
-            |stamp, message, log| {
-                println!("{}: {:?} (len = {})", stamp, message, message.len());
-                log.lock().unwrap().extend_from_slice(message)
-                //log.extend_from_slice(message);
-            },
-            //move |timestamp, data, _| midi_handler.handle_message(timestamp, data),
-            midi_log.clone(),
-        );
         Self {
             active_wave_id: 0,
-            midi_log,
-            owl_command_processor: OwlCommandProcessor::new(),
+            snapshots: SnapshotLibrary::new(),
+            controller_mappings: ControllerMappings::new(),
+            notify_errors: true,
             midi_devices: MidiDeviceSelection::Owl,
             //midi_in_ports: Arc::new(MidiInputPorts::new()),
-            midi_input,
-            midi_output: MidiOutputHandle::new("OWL Wave", 0),
+            sessions: vec![DeviceSession::new(0, None, 0, None)],
+            active_session: 0,
             midi_loaded: false,
+            midi_scan_time: 0.0,
             show_about: false,
             grid: Grid::new(8, 8, 256),
             //log: String::new(),
@@ -101,6 +192,31 @@ impl Default for OwlWaveApp {
             selected_audio_input: None,
             selected_audio_output: None,
             menu_page: MenuPage::Parameters,
+            file_dialogs: FileDialogs::new(),
+            pending_downloads: HashMap::new(),
+            file_error: None,
+            held_button: None,
+            snapshot_name: String::new(),
+            morph: Morph::new(),
+            automation: Automation::new(),
+            automation_lane: None,
+            dragged_point: None,
+            modulation: Arc::new(Mutex::new(ModulationEngine::new())),
+            #[cfg(not(target_arch = "wasm32"))]
+            modulation_thread: None,
+            controller_inputs: None,
+            midi_learn: false,
+            learning: None,
+            monitor_filter: MonitorFilter::default(),
+            sysex_files: Vec::new(),
+            sysex_queue: SendQueue::new(),
+            sysex_decoded: String::new(),
+            log: LogBuffer::default(),
+            log_filter: LogFilter::default(),
+            oscilloscope: Oscilloscope::new(),
+            spectrum: Spectrum::default(),
+            wave_capture: WaveCapture::new(),
+            wave_capture_status: None,
         }
     }
 }
@@ -113,30 +229,88 @@ impl eframe::App for OwlWaveApp {
 
     /// Called each time the UI needs repainting, which may be many times per second.
     /// Put your widgets into a `SidePanel`, `TopPanel`, `CentralPanel`, `Window` or `Area`.
+    #[cfg_attr(target_arch = "wasm32", allow(unused_variables))]
     fn update(&mut self, ctx: &egui::Context, frame: &mut eframe::Frame) {
-        if self.owl_command_processor.resource_data.state == ResourceState::Success {
-            let data = &self.owl_command_processor.resource_data.data;
-            let reader = Cursor::new(data);
-            if let Ok(wav_content) = WavHandler::read_content(reader) {
-                self.grid.load_waves(&wav_content).unwrap_or(0);
+        let now = ctx.input().time;
+        for session in self.sessions.iter_mut() {
+            session.process_midi(now);
+            for error in session.program_history.take_new_errors() {
+                if self.notify_errors {
+                    notification::notify(&format!("{} patch error", session.name()), &error);
+                }
             }
-        };
+            for warning in session.program_stats.take_new_warnings() {
+                warn!(device = session.name(), "{}", warning.message());
+                if self.notify_errors {
+                    notification::notify(session.name(), warning.message());
+                }
+            }
+            if session.program_stats.polling {
+                ctx.request_repaint_after(Duration::from_secs_f64(session.program_stats.interval));
+            }
+        }
+        self.update_modulation(ctx);
+        self.update_controllers(ctx);
+        if let Some(message) = self.sysex_queue.poll(now) {
+            self.session_mut().send_raw(&message);
+        }
+        if let Some(delay) = self.sysex_queue.pending(now) {
+            ctx.request_repaint_after(Duration::from_secs_f64(delay));
+        }
+        if self.automation.state() != AutomationState::Stopped {
+            // Received CCs are applied before recording, played back values are sent right away
+            let session = &mut self.sessions[self.active_session];
+            session.sync_parameters();
+            self.automation
+                .update(now, &mut session.owl_command_processor.parameters);
+            session.sync_parameters();
+            ctx.request_repaint();
+        }
+        while let Some(file) = self.file_dialogs.poll() {
+            self.open_file(file);
+        }
+        // Downloads can finish on any device, not just the active one
+        for (i, session) in self.sessions.iter_mut().enumerate() {
+            if let Some(data) = session.owl_command_processor.resource_data.take() {
+                if let Some(name) = self.pending_downloads.remove(&i) {
+                    let payload =
+                        resource_from_header(&data).map_or(data.as_slice(), |(_, payload)| payload);
+                    if let Err(err) = file_io::save_file(&name, payload) {
+                        self.file_error = Some(format!("Can't save {name}: {err}"));
+                    }
+                }
+                let reader = Cursor::new(data);
+                if let Ok(wav_content) = WavHandler::read_content(reader) {
+                    self.grid.load_waves(&wav_content);
+                }
+            }
+        }
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             // The top panel is often a good place for a menu bar:
             egui::menu::bar(ui, |ui| {
                 egui::widgets::global_dark_light_mode_switch(ui);
                 ui.menu_button("File", |ui| {
-                    if !frame.is_web() {
-                        #[cfg(not(target_arch = "wasm32"))]
-                        if ui.button("Open").clicked() {
-                            if let Some(path) = rfd::FileDialog::new().pick_file() {
-                                if let Ok(open_file) = File::open(path) {
-                                    if let Ok(wav_content) = WavHandler::read_content(open_file) {
-                                        self.grid.load_waves(&wav_content).unwrap_or(0);
-                                    }
-                                }
+                    if ui.button("Open wavetable").clicked() {
+                        ui.close_menu();
+                        self.file_dialogs.open(ctx, FileKind::Wavetable);
+                    }
+                    if ui.button("Save wavetable").clicked() {
+                        ui.close_menu();
+                        self.save_file("wavetable.wav", &self.grid.to_wav());
+                    }
+                    ui.separator();
+                    if ui.button("Open project").clicked() {
+                        ui.close_menu();
+                        self.file_dialogs.open(ctx, FileKind::Project);
+                    }
+                    if ui.button("Save project").clicked() {
+                        ui.close_menu();
+                        match Project::new(&self.grid, self.active_wave_id).to_json() {
+                            Ok(data) => {
+                                self.save_file(&format!("project.{PROJECT_EXTENSION}"), &data)
                             }
+                            Err(err) => self.file_error = Some(err.to_string()),
                         }
                     }
 
@@ -201,6 +375,16 @@ impl eframe::App for OwlWaveApp {
                 "Source code."
             ));
             egui::warn_if_debug_build(ui);
+            let mut dismissed = false;
+            if let Some(err) = &self.file_error {
+                ui.horizontal(|ui| {
+                    ui.colored_label(Color32::RED, err.as_str());
+                    dismissed = ui.small_button("✖").clicked();
+                });
+            }
+            if dismissed {
+                self.file_error = None;
+            }
         });
 
         egui::Window::new("Wavetable").show(ctx, |ui| {
@@ -361,15 +545,22 @@ impl eframe::App for OwlWaveApp {
                         }
                         if selected_audio_input != self.selected_audio_input {
                             // Connect to a different input
-                            self.audio_handler
-                                .select_input(self.selected_audio_host, selected_audio_input);
+                            if let Err(err) = self
+                                .audio_handler
+                                .select_input(self.selected_audio_host, selected_audio_input)
+                            {
+                                error!(%err, "failed to open audio input");
+                            }
                             self.selected_audio_input = selected_audio_input
                         }
                         if selected_audio_output != self.selected_audio_output {
-                            // Connect to a different input
-                            self.audio_handler
+                            // Connect to a different output
+                            if let Err(err) = self
+                                .audio_handler
                                 .select_output(self.selected_audio_host, selected_audio_output)
-                                .unwrap();
+                            {
+                                error!(%err, "failed to open audio output");
+                            }
                             self.selected_audio_output = selected_audio_output
                         }
                     }
@@ -377,6 +568,10 @@ impl eframe::App for OwlWaveApp {
             });
         });
 
+        egui::Window::new("Devices").show(ctx, |ui| {
+            self.show_devices(ui);
+        });
+
         // MIDI devices window
         egui::Window::new("MIDI Devices").show(ctx, |ui| {
             ui.vertical(|ui| {
@@ -388,23 +583,39 @@ impl eframe::App for OwlWaveApp {
                     ui.selectable_value(&mut self.midi_devices, MidiDeviceSelection::Owl, "OWL");
                 });
                 ui.separator();
+                #[cfg(target_arch = "wasm32")]
+                if let Some(err) = crate::web_midi::access_error() {
+                    ui.colored_label(Color32::RED, format!("Web MIDI unavailable: {err}"));
+                }
 
                 if !self.midi_loaded {
                     // Reconnect
-                    self.update_midi_input();
-                    self.update_midi_output();
+                    let owl_mode = self.midi_devices == MidiDeviceSelection::Owl;
+                    let session = self.session_mut();
+                    session.update_midi_input();
+                    session.update_midi_output();
+                    if owl_mode {
+                        session.pair_ports(None);
+                    }
                     self.midi_loaded = true;
                 }
+                if now - self.midi_scan_time > MIDI_RESCAN_INTERVAL {
+                    // Reconnect by port name when devices are plugged or unplugged
+                    self.midi_scan_time = now;
+                    self.rescan_midi();
+                }
+                ctx.request_repaint_after(Duration::from_secs_f64(MIDI_RESCAN_INTERVAL));
 
                 egui::Grid::new("midi-grid").show(ui, |ui| {
-                    let mut selected_input_port = *self.midi_input.get_selected_port_mut();
-                    let mut selected_output_port = *self.midi_output.get_selected_port_mut();
-                    for pair in self
-                        .midi_input
+                    let session = &mut self.sessions[self.active_session];
+                    let mut selected_input_port = *session.midi_input.get_selected_port_mut();
+                    let mut selected_output_port = *session.midi_output.get_selected_port_mut();
+                    for pair in session
+                        .midi_output
                         .names
                         .iter()
                         .enumerate()
-                        .zip_longest(self.midi_output.names.iter().enumerate())
+                        .zip_longest(session.midi_input.names.iter().enumerate())
                     {
                         match pair {
                             Both((i, out_port_name), (j, in_port_name)) => {
@@ -440,18 +651,52 @@ impl eframe::App for OwlWaveApp {
                             }
                         }
                     }
-                    if selected_input_port != self.midi_input.selected_port {
+                    let owl_mode = self.midi_devices == MidiDeviceSelection::Owl;
+                    if selected_input_port != session.midi_input.selected_port {
                         // Connect to a different input
-                        self.midi_input.selected_port = selected_input_port;
-                        self.update_midi_input()
+                        session.midi_input.selected_port = selected_input_port;
+                        session.midi_input.selected_name =
+                            session.midi_input.names.get(selected_input_port).cloned();
+                        session.update_midi_input();
+                        if owl_mode {
+                            session.pair_ports(Some(true));
+                        }
                     }
-                    if selected_output_port != self.midi_output.selected_port {
+                    if selected_output_port != session.midi_output.selected_port {
                         // Connect to a different output
-                        self.midi_output.selected_port = selected_output_port;
-                        self.update_midi_output()
+                        session.midi_output.selected_port = selected_output_port;
+                        session.midi_output.selected_name =
+                            session.midi_output.names.get(selected_output_port).cloned();
+                        session.update_midi_output();
+                        if owl_mode {
+                            session.pair_ports(Some(false));
+                        }
                     }
                 });
 
+                if self.midi_devices == MidiDeviceSelection::Owl {
+                    match self.session().owl_command_processor.identity() {
+                        DeviceIdentity::Owl(firmware_version) => {
+                            ui.label(format!("✔ {firmware_version}"));
+                        }
+                        DeviceIdentity::Probing => {
+                            ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label("Identifying device");
+                            });
+                        }
+                        DeviceIdentity::NotResponding => {
+                            ui.colored_label(
+                                Color32::DARK_RED,
+                                "⚠ Device is not responding, is it an OWL?",
+                            );
+                        }
+                        DeviceIdentity::Unknown => {
+                            ui.label("No device");
+                        }
+                    }
+                }
+
                 if self.midi_devices == MidiDeviceSelection::Owl {
                     //ui.separator();
 
@@ -481,7 +726,7 @@ impl eframe::App for OwlWaveApp {
                                         ui,
                                         "Program stats",
                                         OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS,
-                                        None,
+                                        Some(MenuPage::Stats),
                                     );
                                     self.request_settings_button(
                                         ui,
@@ -517,7 +762,9 @@ impl eframe::App for OwlWaveApp {
                             } else {
                                 (Color32::DARK_GRAY, Color32::BLACK)
                             };
-                            if let Some(patch_name) = &self.owl_command_processor.patch_name {
+                            if let Some(patch_name) =
+                                &self.session().owl_command_processor.patch_name
+                            {
                                 job.append(
                                     format!("{patch_name}\n").as_str(),
                                     first_row_indentation,
@@ -529,7 +776,7 @@ impl eframe::App for OwlWaveApp {
                                 );
                             }
                             if let Some(firmware_version) =
-                                &self.owl_command_processor.firmware_version
+                                &self.session().owl_command_processor.firmware_version
                             {
                                 job.append(
                                     format!("{firmware_version}\n").as_str(),
@@ -540,7 +787,9 @@ impl eframe::App for OwlWaveApp {
                                     },
                                 );
                             }
-                            if let Some(program_stats) = &self.owl_command_processor.program_stats {
+                            if let Some(program_stats) =
+                                &self.session().owl_command_processor.program_stats
+                            {
                                 job.append(
                                     format!("{program_stats}\n").as_str(),
                                     0.0,
@@ -551,7 +800,7 @@ impl eframe::App for OwlWaveApp {
                                 );
                             }
                             if let Some(program_message) =
-                                &self.owl_command_processor.program_message
+                                &self.session().owl_command_processor.program_message
                             {
                                 job.append(
                                     format!("{program_message}\n").as_str(),
@@ -562,19 +811,55 @@ impl eframe::App for OwlWaveApp {
                                     },
                                 );
                             }
+                            ui.label(job);
+                            for warning in self.session().program_stats.warnings() {
+                                ui.colored_label(Color32::DARK_RED, warning.message());
+                            }
                             if let Some(error_message) =
-                                &self.owl_command_processor.error_message
+                                self.session().owl_command_processor.error_message.clone()
                             {
-                                job.append(
-                                    format!("{error_message}\n").as_str(),
-                                    0.0,
-                                    egui::TextFormat {
-                                        color: Color32::DARK_RED,
-                                        ..Default::default()
-                                    },
-                                );
+                                ui.horizontal(|ui| {
+                                    ui.colored_label(Color32::DARK_RED, error_message);
+                                    if ui.small_button("✖").on_hover_text("Clear error").clicked()
+                                    {
+                                        self.clear_errors();
+                                    }
+                                });
+                            }
+
+                            let mut dismissed = None;
+                            for request in self.session().owl_command_processor.requests.requests()
+                            {
+                                match request.state {
+                                    RequestState::Pending | RequestState::Receiving => {
+                                        ui.horizontal(|ui| {
+                                            ui.spinner();
+                                            ui.label(format!("{:?}", request.command));
+                                        });
+                                    }
+                                    RequestState::TimedOut => {
+                                        ui.horizontal(|ui| {
+                                            ui.colored_label(
+                                                Color32::DARK_RED,
+                                                format!(
+                                                    "{:?}: device not responding",
+                                                    request.command
+                                                ),
+                                            );
+                                            if ui.small_button("✖").clicked() {
+                                                dismissed = Some(request.command);
+                                            }
+                                        });
+                                    }
+                                    RequestState::Complete => {}
+                                }
+                            }
+                            if let Some(command) = dismissed {
+                                self.session_mut()
+                                    .owl_command_processor
+                                    .requests
+                                    .dismiss(command);
                             }
-                            ui.label(job);
                         });
                     });
 
@@ -585,9 +870,40 @@ impl eframe::App for OwlWaveApp {
                         .show_inside(ui, |ui| {
                             ui.vertical_centered_justified(|ui| {
                                 self.show_menu_page(ui, "Parameters", MenuPage::Parameters, None);
-                                self.show_menu_page(ui, "Patches", MenuPage::Patches, Some(OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND));
-                                self.show_menu_page(ui, "Resources", MenuPage::Resources, Some(OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND));
-                                self.show_menu_page(ui, "Settings", MenuPage::Settings, Some(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND));
+                                self.show_menu_page(
+                                    ui,
+                                    "Patches",
+                                    MenuPage::Patches,
+                                    Some(OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND),
+                                );
+                                self.show_menu_page(
+                                    ui,
+                                    "Resources",
+                                    MenuPage::Resources,
+                                    Some(OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND),
+                                );
+                                self.show_menu_page(
+                                    ui,
+                                    "Settings",
+                                    MenuPage::Settings,
+                                    Some(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND),
+                                );
+                                let errors = self.session().program_history.error_count();
+                                let label = match errors {
+                                    0 => "Patch messages".to_string(),
+                                    errors => format!("Patch messages ({errors} errors)"),
+                                };
+                                self.show_menu_page(ui, &label, MenuPage::Messages, None);
+                                self.show_menu_page(ui, "Program stats", MenuPage::Stats, None);
+                                self.show_menu_page(ui, "Automation", MenuPage::Automation, None);
+                                self.show_menu_page(ui, "MIDI monitor", MenuPage::Monitor, None);
+                                self.show_menu_page(
+                                    ui,
+                                    "Sysex librarian",
+                                    MenuPage::Librarian,
+                                    None,
+                                );
+                                self.show_menu_page(ui, "Oscilloscope", MenuPage::Scope, None);
                             });
                         });
 
@@ -598,148 +914,128 @@ impl eframe::App for OwlWaveApp {
                             ui.vertical_centered(|ui| {
                                 ui.heading("Log");
                             });
-                            egui::ScrollArea::vertical()
-                                .auto_shrink([false, true])
-                                .show(ui, |ui| {
-                                    ui.label(&self.owl_command_processor.log);
-                                });
+                            self.ui_log(ui);
                         });
                     egui::CentralPanel::default().show_inside(ui, |ui| match self.menu_page {
                         MenuPage::Parameters => {
                             ui.vertical_centered(|ui| {
                                 ui.heading("Parameters");
-                                egui::Grid::new("parameters-grid").show(
-                                    ui,
-                                    |ui| {
-                                        for i in 0..40 {
-                                            let pid = PatchParameterId::try_from(i).unwrap();
-                                            if let Some(parameter) = self.owl_command_processor.parameters.get_mut(&pid) {
-                                                ui.label(pid.string_id());
-                                                ui.label(parameter.name.as_str());
-                                                ui.add(egui::Slider::new(&mut parameter.value, 0f32..=1f32));
-                                                ui.end_row();
-                                                let sync = parameter.sync();
-                                                if sync {
-                                                    let cc = U7::try_from(OpenWareMidiControl::from(pid) as u8).unwrap();
-                                                    let cc_val = parameter.midi_value.try_into().unwrap();
-                                                    self.send_cc(MidiMessage::ControlChange(
-                                                        wmidi::Channel::Ch1,
-                                                        wmidi::ControlFunction::from(cc),
-                                                        cc_val
-                                                    ));
-                                                };
-                                            }
-                                        }
-                                    });
-                                }
-                            );
+                            });
+                            self.ui_parameters(ui);
                         }
                         MenuPage::Patches => {
                             ui.vertical_centered(|ui| {
                                 ui.heading("Patches");
                             });
-                            egui::Grid::new("patches-grid").show(
-                                ui,
-                                |ui|
-                                for (i, maybe_patch) in self.owl_command_processor.patches
+                            egui::Grid::new("patches-grid").show(ui, |ui| {
+                                for (i, maybe_patch) in self
+                                    .session()
+                                    .owl_command_processor
+                                    .patches
                                     .clone()
                                     .iter()
                                     .skip(1)
-                                    .enumerate() {
+                                    .enumerate()
+                                {
                                     if let Some(patch) = maybe_patch {
                                         let button_menu = |ui: &mut Ui| {
-                                            if ui.button("Load").clicked(){
-                                                self.send_cc(MidiMessage::ProgramChange(
-                                                            wmidi::Channel::Ch1,
-                                                            U7::try_from(1 + i as u8).unwrap()));
+                                            if ui.button("Load").clicked() {
+                                                self.session_mut().load_patch(1 + i as u8);
                                                 ui.close_menu();
                                             };
-                                            /*
-                                            if ui.button("Download").clicked(){
+                                            if ui.button("Download").clicked() {
+                                                self.download(i + 1, format!("{}.bin", patch.name));
                                                 ui.close_menu();
                                             };
-                                            */
-                                            if ui.button("Delete").clicked(){
-                                                if let Some(connection) =
-                                                &mut self.midi_output.connection {
-                                                    let mut sysex = [0u8; 5];
-                                                    sysex[4] = i as u8 + 1;
-                                                    self.owl_command_processor.send_sysex_string(
-                                                        connection,
-                                                        OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE,
-                                                        &sysex
-                                                    ).unwrap();
-                                                }
+                                            if ui.button("Delete").clicked() {
+                                                self.session_mut().erase(Some(i as u32 + 1));
                                                 ui.close_menu();
                                             };
                                         };
-                                        let button = egui::Button::new(format!("{:>2}. {}", i + 1, patch.name));
-                                        let layout = egui::Layout::from_main_dir_and_cross_align(egui::Direction::TopDown, egui::Align::Min).with_cross_justify(true);
-                                        if ui.allocate_ui_with_layout(egui::Vec2::new(400.0, 20.0), layout, |ui| ui.add(button))
-                                            .inner.context_menu(button_menu).clicked() {
-                                            self.send_cc(MidiMessage::ProgramChange(
-                                                wmidi::Channel::Ch1,
-                                                U7::try_from(1 + i as u8).unwrap()));
+                                        let button = egui::Button::new(format!(
+                                            "{:>2}. {}",
+                                            i + 1,
+                                            patch.name
+                                        ));
+                                        let layout = egui::Layout::from_main_dir_and_cross_align(
+                                            egui::Direction::TopDown,
+                                            egui::Align::Min,
+                                        )
+                                        .with_cross_justify(true);
+                                        if ui
+                                            .allocate_ui_with_layout(
+                                                egui::Vec2::new(400.0, 20.0),
+                                                layout,
+                                                |ui| ui.add(button),
+                                            )
+                                            .inner
+                                            .context_menu(button_menu)
+                                            .clicked()
+                                        {
+                                            self.session_mut().load_patch(1 + i as u8);
                                         };
                                         ui.label(format!("{:X}", patch.checksum).as_str());
                                         ui.label(patch.size_string().as_str());
-                                    }
-                                    else {
+                                    } else {
                                         ui.separator();
                                     }
                                     ui.end_row()
                                 }
-                            );
+                            });
                         }
                         MenuPage::Resources => {
                             ui.vertical_centered(|ui| {
                                 ui.heading("Resources");
                             });
 
-                            egui::Grid::new("patches-grid").show(
-                                ui,
-                                |ui|
+                            egui::Grid::new("patches-grid").show(ui, |ui| {
+                                for (i, maybe_resource) in self
+                                    .session()
+                                    .owl_command_processor
+                                    .resources
+                                    .clone()
+                                    .iter()
+                                    .enumerate()
                                 {
-                                    for (i, maybe_resource) in self.owl_command_processor.resources.clone().iter().enumerate() {
-                                        if let Some(resource) = maybe_resource {
+                                    if let Some(resource) = maybe_resource {
+                                        let slot = i + self
+                                            .session()
+                                            .owl_command_processor
+                                            .resource_offset;
                                         let button_menu = |ui: &mut Ui| {
-                                            if ui.button("Load wavetable").clicked(){
-                                                if let Some(connection) = &mut self.midi_output.connection {
-                                                    let mut sysex = [0u8; 5];
-                                                    sysex[4] = (i + self.owl_command_processor.resource_offset) as u8 + 1;
-                                                    self.owl_command_processor.send_sysex_string(
-                                                        connection,
-                                                        OpenWareMidiSysexCommand::SYSEX_FIRMWARE_SEND,
-                                                        &sysex
-                                                    ).unwrap();
-                                                }
+                                            if ui.button("Load wavetable").clicked() {
+                                                self.session_mut().request_download(slot as u32);
                                                 ui.close_menu();
                                             };
-                                            /*
-                                            if ui.button("Download").clicked(){
+                                            if ui.button("Download").clicked() {
+                                                self.download(slot, resource.name.clone());
                                                 ui.close_menu();
                                             };
-                                            */
-                                            if ui.button("Delete").clicked(){
-                                                /*
-                                                if let Some(connection) =
-                                                &mut self.midi_output.connection {
-                                                    let mut sysex = [0u8; 5];
-                                                    sysex[4] = i as u8 + 1;
-                                                    self.owl_command_processor.send_sysex_string(
-                                                        connection,
-                                                        OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE,
-                                                        &sysex
-                                                    ).unwrap();
-                                                }
-                                                 */
+                                            if ui.button("Delete").clicked() {
+                                                self.session_mut().erase(Some(slot as u32));
                                                 ui.close_menu();
                                             };
                                         };
-                                        let button = egui::Button::new(format!("{:>2}. {}", i + 1, resource.name));
-                                        let layout = egui::Layout::from_main_dir_and_cross_align(egui::Direction::TopDown, egui::Align::Min).with_cross_justify(true);
-                                        if ui.allocate_ui_with_layout(egui::Vec2::new(400.0, 20.0), layout, |ui| ui.add(button))
-                                            .inner.context_menu(button_menu).clicked() {
+                                        let button = egui::Button::new(format!(
+                                            "{:>2}. {}",
+                                            i + 1,
+                                            resource.name
+                                        ));
+                                        let layout = egui::Layout::from_main_dir_and_cross_align(
+                                            egui::Direction::TopDown,
+                                            egui::Align::Min,
+                                        )
+                                        .with_cross_justify(true);
+                                        if ui
+                                            .allocate_ui_with_layout(
+                                                egui::Vec2::new(400.0, 20.0),
+                                                layout,
+                                                |ui| ui.add(button),
+                                            )
+                                            .inner
+                                            .context_menu(button_menu)
+                                            .clicked()
+                                        {
                                             /*
                                             self.send_cc(MidiMessage::ProgramChange(
                                                 wmidi::Channel::Ch1,
@@ -748,138 +1044,58 @@ impl eframe::App for OwlWaveApp {
                                         };
                                         ui.label(format!("{:X}", resource.checksum).as_str());
                                         ui.label(resource.size_string().as_str());
+                                    } else {
+                                        ui.separator();
                                     }
-                                    else {ui.separator();}
                                     ui.end_row()
-                                    }
                                 }
-                            );
+                            });
                         }
                         MenuPage::Settings => {
                             ui.vertical_centered(|ui| {
                                 ui.heading("Settings");
                             });
-                            egui::Grid::new("settings-grid")
-                                .num_columns(2)
-                                .spacing([4.0, 4.0])
-                                .min_col_width(150.0)
-                                .show(ui, |ui| {
-                                    for &config in SYSEX_CONFIGURATIONS.iter() {
-                                        ui.with_layout(
-                                            egui::Layout::top_down_justified(egui::Align::Center),
-                                            |ui| {
-                                                let current_value = self
-                                                    .owl_command_processor
-                                                    .settings
-                                                    .get(&config)
-                                                    .map(|x| x.as_str())
-                                                    .unwrap_or("");
-                                                let parsed_value = current_value.parse::<i64>();
-                                                let valid_value = parsed_value
-                                                    .as_ref()
-                                                    .ok()
-                                                    .and(Some(true))
-                                                    .unwrap_or(false);
-                                                let button = ui.add_enabled(
-                                                    valid_value,
-                                                    egui::Button::new(format!("{config:?}")),
-                                                );
-                                                if valid_value && button.clicked() {
-                                                    if let Some(connection) =
-                                                        &mut self.midi_output.connection
-                                                    {
-                                                        let parsed_int = parsed_value.unwrap();
-                                                        let parsed_str = if parsed_int >= 0 {
-                                                            format!("{parsed_int:x}")
-                                                        }
-                                                        else {
-                                                            format!("-{:x}", -parsed_int)
-                                                        };
-                                                        let data = String::from_utf8(
-                                                            vec![(config as isize >> 8) as u8,
-                                                                 ((config as isize) & 0xff) as u8,]).unwrap() +
-                                                            parsed_str.as_str();
-                                                        let sysex = data.as_bytes();
-                                                        self.owl_command_processor
-                                                            .send_sysex_string(
-                                                                connection,
-                                                                OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
-                                                                sysex
-                                                            )
-                                                            .unwrap();
-                                                    }
-                                                }
-                                            },
-                                        );
-
-                                        ui.add(
-                                            egui::TextEdit::singleline(
-                                                self.owl_command_processor
-                                                    .settings
-                                                    .entry(config)
-                                                    .or_default()
-                                                )
-                                            .hint_text("Edit value"),
-                                        );
-                                        ui.end_row()
-                                    };
-                                    ui.horizontal(|ui|{
-                                            if ui.button("Save ").clicked() {
-                                                if let Some(connection) =
-                                                        &mut self.midi_output.connection
-                                                    {
-                                                self.owl_command_processor.send_sysex_command(
-                                                    connection, OpenWareMidiSysexCommand::SYSEX_SETTINGS_STORE).unwrap();
-                                                    }
-                                            };
-                                            if ui.button("Reset").clicked() {
-                                                if let Some(connection) =
-                                                        &mut self.midi_output.connection
-                                                    {
-                                                self.owl_command_processor.send_sysex_command(
-                                                    connection, OpenWareMidiSysexCommand::SYSEX_SETTINGS_RESET).unwrap();
-                                                    }
-                                            }
-                                    });
-                                });
+                            self.ui_settings(ui);
+                        }
+                        MenuPage::Messages => {
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Patch messages");
+                            });
+                            self.ui_messages(ui);
+                        }
+                        MenuPage::Stats => {
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Program stats");
+                            });
+                            self.ui_stats(ui);
+                        }
+                        MenuPage::Automation => {
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Automation");
+                            });
+                            self.ui_automation(ui);
+                        }
+                        MenuPage::Monitor => {
+                            ui.vertical_centered(|ui| {
+                                ui.heading("MIDI monitor");
+                            });
+                            self.ui_monitor(ui);
+                        }
+                        MenuPage::Librarian => {
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Sysex librarian");
+                            });
+                            self.ui_librarian(ui);
+                        }
+                        MenuPage::Scope => {
+                            ui.vertical_centered(|ui| {
+                                ui.heading("Oscilloscope");
+                            });
+                            self.ui_scope(ui);
                         }
                     });
 
                     //ui.horizontal(|ui|{});
-                    if let Ok(mut data_guard) = self.midi_log.lock() {
-                        //data_guard.iter(|| wmidi::MidiMessage::try_from().unwrap());
-                        let bytes = data_guard.as_mut_slice();
-                        let mut start = 0;
-                        while start < bytes.len() {
-                            let message = wmidi::MidiMessage::try_from(&bytes[start..]).unwrap();
-                            start += message.bytes_size();
-                            if let MidiMessage::SysEx(data) = message {
-                                self.owl_command_processor.handle_sysex(data).expect("Error handling sysex");
-                                /*
-                                let mut buf: [u8; 256] = [0; 256];
-                                if let Ok(size) = message.copy_to_slice(buf.as_mut_slice()) {
-                                    if size > 0 {
-                                        if let Ok(_result) =
-                                            self.owl_command_processor.handle_sysex(&buf, size)
-                                        {
-                                            /*
-                                            if let Some(output) = &self.owl_command_processor.output
-                                            {
-                                                let s = format!("{}\n", output);
-                                                self.log += s.as_str()
-                                            }
-                                            */
-                                        }
-                                    }
-                                }
-                                */
-                            }
-                            else {
-                                self.owl_command_processor.handle_midi_message(message)
-                            }
-                        }
-                        data_guard.clear();
-                    }
                 }
             });
         });
@@ -889,8 +1105,8 @@ impl eframe::App for OwlWaveApp {
 }
 
 impl OwlWaveApp {
-    /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+    /// Called once before the first frame, log view shows records collected in `log`.
+    pub fn new(cc: &eframe::CreationContext<'_>, log: LogBuffer) -> Self {
         /*
         if cc.integration_info.prefer_dark_mode == Some(false) {
             cc.egui_ctx.set_visuals(egui::Visuals::light()); // use light mode if explicitly asked for
@@ -899,10 +1115,11 @@ impl OwlWaveApp {
         }
         */
         // Load previous app state (if any).
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
-        }
-        Self::default()
+        let app: Self = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
+        Self { log, ..app }
     }
 
     fn ui_file_drag_and_drop(&mut self, ctx: &egui::Context) {
@@ -953,14 +1170,14 @@ impl OwlWaveApp {
                 if let Some(path) = &file.path {
                     if let Ok(open_file) = File::open(path) {
                         if let Ok(wav_content) = WavHandler::read_content(open_file) {
-                            self.grid.load_waves(&wav_content).unwrap_or(0);
+                            self.grid.load_waves(&wav_content);
                         }
                     }
                 } else if let Some(bytes) = &file.bytes {
                     let _ = write!(info, " ({} bytes)", bytes.len());
                     let reader = Cursor::new(bytes);
                     if let Ok(wav_content) = WavHandler::read_content(reader) {
-                        self.grid.load_waves(&wav_content).unwrap_or(0);
+                        self.grid.load_waves(&wav_content);
                     }
                 }
             }
@@ -971,19 +1188,173 @@ impl OwlWaveApp {
         self.midi_loaded = false;
         self
     }
-    fn update_midi_input(&mut self) {
-        self.midi_input = MidiInputHandle::new(
-            "OWL wave",
-            self.midi_input.selected_port,
-            |stamp, message, log| {
-                println!("{}: {:x?} (len = {})", stamp, message, message.len());
-                log.lock().unwrap().extend_from_slice(message)
+    fn session(&self) -> &DeviceSession {
+        &self.sessions[self.active_session]
+    }
+    fn session_mut(&mut self) -> &mut DeviceSession {
+        &mut self.sessions[self.active_session]
+    }
+    fn rescan_midi(&mut self) {
+        let owl_mode = self.midi_devices == MidiDeviceSelection::Owl;
+        for (i, session) in self.sessions.iter_mut().enumerate() {
+            // Other sessions reconnect by name only, so that they don't take over active device
+            if session.rescan() && owl_mode && i == self.active_session {
+                session.pair_ports(None);
+            }
+        }
+        let controllers_changed = self
+            .controller_inputs
+            .as_ref()
+            .map_or(false, |inputs| inputs.ports_changed(MIDI_CLIENT_NAME));
+        if controllers_changed {
+            // Reconnected on next update
+            self.controller_inputs = None;
+        }
+    }
+    /// Opens a session for every OWL device that isn't connected yet
+    fn connect_all_owls(&mut self) {
+        let session = self.session();
+        let inputs = session.midi_input.names.clone();
+        let outputs = session.midi_output.names.clone();
+        for (i, j) in pair_owl_ports(&inputs, &outputs) {
+            let connected = self.sessions.iter().any(|s| {
+                s.is_connected() && s.midi_output.selected_name.as_ref() == Some(&outputs[j])
+            });
+            if !connected {
+                self.sessions.push(DeviceSession::new(
+                    i,
+                    Some(inputs[i].clone()),
+                    j,
+                    Some(outputs[j].clone()),
+                ));
+            }
+        }
+    }
+    fn show_devices(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Connect all OWLs").clicked() {
+                self.connect_all_owls();
+            }
+            if ui.button("Add device").clicked() {
+                self.sessions.push(DeviceSession::new(0, None, 0, None));
+                self.active_session = self.sessions.len() - 1;
+            }
+            if ui.button("Add emulator").clicked() {
+                self.sessions.push(DeviceSession::emulator());
+                self.active_session = self.sessions.len() - 1;
+            }
+        });
+        ui.separator();
+        let mut removed = None;
+        egui::Grid::new("devices-grid").show(ui, |ui| {
+            for (i, session) in self.sessions.iter().enumerate() {
+                ui.selectable_value(&mut self.active_session, i, session.name());
+                match session.owl_command_processor.identity() {
+                    DeviceIdentity::Owl(firmware_version) => ui.label(firmware_version),
+                    DeviceIdentity::Probing => ui.spinner(),
+                    DeviceIdentity::NotResponding => ui.colored_label(Color32::DARK_RED, "⚠"),
+                    DeviceIdentity::Unknown => ui.label(""),
+                };
+                if self.sessions.len() > 1 && ui.small_button("✖").clicked() {
+                    removed = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = removed {
+            self.sessions.remove(i);
+            self.pending_downloads = std::mem::take(&mut self.pending_downloads)
+                .into_iter()
+                .filter(|&(session, _)| session != i)
+                .map(|(session, name)| (if session > i { session - 1 } else { session }, name))
+                .collect();
+            if self.active_session >= i && self.active_session > 0 {
+                self.active_session -= 1;
+            }
+        }
+        ui.separator();
+        ui.label("All devices:");
+        ui.horizontal(|ui| {
+            if ui.button("Refresh patches").clicked() {
+                self.request_all(OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND);
+            }
+            if ui.button("Refresh resources").clicked() {
+                self.request_all(OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND);
+            }
+            if ui.button("Upload resource").clicked() {
+                self.file_dialogs.open(ui.ctx(), FileKind::Resource);
+            }
+        });
+    }
+    fn open_file(&mut self, file: OpenedFile) {
+        match file.kind {
+            FileKind::Wavetable => match WavHandler::read_content(Cursor::new(&file.data)) {
+                Ok(wav_content) => {
+                    self.grid.load_waves(&wav_content);
+                }
+                Err(_) => self.file_error = Some(format!("{} is not a valid WAV file", file.name)),
+            },
+            FileKind::Project => match Project::from_json(&file.data) {
+                Ok(project) => {
+                    self.grid = project.to_grid();
+                    self.active_wave_id = project
+                        .active_wave_id
+                        .min(self.grid.get_waves().saturating_sub(1));
+                }
+                Err(err) => self.file_error = Some(format!("Can't open {}: {err}", file.name)),
             },
-            self.midi_log.clone(),
-        );
+            FileKind::Resource => {
+                for session in self.sessions.iter_mut() {
+                    session.send_resource(&file.name, &file.data);
+                }
+            }
+            FileKind::Automation => match Automation::from_smf(&file.data) {
+                Ok(automation) => {
+                    self.automation = automation;
+                    self.menu_page = MenuPage::Automation;
+                }
+                Err(err) => self.file_error = Some(format!("Can't open {}: {err}", file.name)),
+            },
+            FileKind::Settings => {
+                let session = self.session_mut();
+                let result = SettingsProfile::from_json(&file.data)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|profile| {
+                        session
+                            .settings_edits
+                            .load_profile(&session.owl_command_processor.settings, &profile)
+                    });
+                match result {
+                    // Changes are shown on settings page before they're sent
+                    Ok(()) => self.menu_page = MenuPage::Settings,
+                    Err(err) => self.file_error = Some(format!("Can't open {}: {err}", file.name)),
+                }
+            }
+            FileKind::Sysex => {
+                let sysex_file = SysexFile::new(file.name, &file.data);
+                if sysex_file.messages.is_empty() {
+                    self.file_error = Some(format!("No sysex messages in {}", sysex_file.name));
+                } else {
+                    self.sysex_files.push(sysex_file);
+                    self.menu_page = MenuPage::Librarian;
+                }
+            }
+        }
+    }
+    fn save_file(&mut self, name: &str, data: &[u8]) {
+        if let Err(err) = file_io::save_file(name, data) {
+            self.file_error = Some(format!("Can't save {name}: {err}"));
+        }
     }
-    fn update_midi_output(&mut self) {
-        self.midi_output = MidiOutputHandle::new("OWL wave", self.midi_output.selected_port);
+    /// Requests patch or resource from active device, it's saved to a file once received
+    fn download(&mut self, slot: usize, name: String) {
+        self.session_mut().request_download(slot as u32);
+        self.pending_downloads.insert(self.active_session, name);
+    }
+    fn request_all(&mut self, command: OpenWareMidiSysexCommand) {
+        for session in self.sessions.iter_mut() {
+            session.request_settings(command);
+        }
     }
 
     fn request_settings_button(
@@ -994,11 +1365,7 @@ impl OwlWaveApp {
         menu_page: Option<MenuPage>,
     ) {
         if ui.button(label).clicked() {
-            if let Some(connection) = &mut self.midi_output.connection {
-                self.owl_command_processor
-                    .request_settings(connection, sysex)
-                    .unwrap();
-            }
+            self.session_mut().request_settings(sysex);
             if let Some(page) = menu_page {
                 self.menu_page = page;
             }
@@ -1017,28 +1384,15 @@ impl OwlWaveApp {
             .clicked()
         {
             self.menu_page = menu_page;
-            if let (Some(command), Some(connection)) = (sysex, &mut self.midi_output.connection) {
-                self.owl_command_processor
-                    .request_settings(connection, command)
-                    .unwrap();
+            if let Some(command) = sysex {
+                self.session_mut().request_settings(command);
             }
         }
     }
     fn send_sysex_button(&mut self, ui: &mut Ui, label: &str, sysex: OpenWareMidiSysexCommand) {
         if ui.button(label).clicked() {
-            if let Some(connection) = &mut self.midi_output.connection {
-                self.owl_command_processor
-                    .send_sysex_command(connection, sysex)
-                    .unwrap();
-            }
+            self.session_mut().send_sysex_command(sysex);
             ui.close_menu()
         };
     }
-    fn send_cc(&mut self, message: MidiMessage<'_>) {
-        if let Some(connection) = &mut self.midi_output.connection {
-            self.owl_command_processor
-                .send_message(connection, message)
-                .unwrap();
-        }
-    }
 }
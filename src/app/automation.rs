@@ -0,0 +1,167 @@
+//! Recording and playback of parameter automation.
+use super::OwlWaveApp;
+use crate::file_io::FileKind;
+use crate::owl_control::automation::{Automation, AutomationState, MIN_LENGTH};
+use crate::owl_control::parameter::PARAMETER_COUNT;
+use eframe::egui;
+use egui::plot::{Line, Plot, Points, VLine};
+use egui::Ui;
+use owl_midi::PatchParameterId;
+
+impl OwlWaveApp {
+    pub(super) fn ui_automation(&mut self, ui: &mut Ui) {
+        let now = ui.input().time;
+        let state = self.automation.state();
+        ui.horizontal(|ui| {
+            let recording = matches!(state, AutomationState::Recording { .. });
+            let playing = matches!(state, AutomationState::Playing { .. });
+            if ui
+                .add_enabled(!recording, egui::Button::new("⏺ Record"))
+                .clicked()
+            {
+                self.automation.record(now);
+            }
+            let can_play = self.automation.length >= MIN_LENGTH && !self.automation.is_empty();
+            if ui
+                .add_enabled(!playing && can_play, egui::Button::new("▶ Play"))
+                .clicked()
+            {
+                self.automation.play(now);
+            }
+            if ui
+                .add_enabled(
+                    state != AutomationState::Stopped,
+                    egui::Button::new("⏹ Stop"),
+                )
+                .clicked()
+            {
+                self.automation.stop(now);
+            }
+            match self.automation.position(now) {
+                Some(position) => ui.label(format!("{position:.2} s")),
+                None => ui.label(format!("Loop {:.2} s", self.automation.length)),
+            };
+            ui.separator();
+            if ui.button("Import MIDI file").clicked() {
+                self.file_dialogs.open(ui.ctx(), FileKind::Automation);
+            }
+            if ui
+                .add_enabled(
+                    !self.automation.is_empty(),
+                    egui::Button::new("Export MIDI file"),
+                )
+                .clicked()
+            {
+                let data = self.automation.to_smf();
+                self.save_file("automation.mid", &data);
+            }
+            if ui.button("Clear").clicked() {
+                self.automation = Automation::new();
+            }
+        });
+
+        let parameters = &self.session().owl_command_processor.parameters;
+        let lane_name = |pid: PatchParameterId| match parameters.get(&pid) {
+            Some(parameter) => format!("{} {}", pid.string_id(), parameter.name),
+            None => pid.string_id().to_string(),
+        };
+        let mut lane = self.automation_lane;
+        egui::ComboBox::from_label("Edited lane")
+            .selected_text(lane.map_or("None".to_string(), lane_name))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut lane, None, "None");
+                for pid in (0..PARAMETER_COUNT as isize)
+                    .filter_map(|i| PatchParameterId::try_from(i).ok())
+                    .filter(|pid| {
+                        parameters.contains_key(pid) || self.automation.lane(*pid).is_some()
+                    })
+                {
+                    ui.selectable_value(&mut lane, Some(pid), lane_name(pid));
+                }
+            });
+        if lane != self.automation_lane {
+            self.automation_lane = lane;
+            self.dragged_point = None;
+        }
+
+        let length = self.automation.length.max(1.0);
+        let position = self.automation.position(now);
+        let lines: Vec<(bool, Vec<[f64; 2]>)> = self
+            .automation
+            .lanes
+            .iter()
+            .map(|lane| {
+                let points = lane
+                    .points()
+                    .iter()
+                    .map(|point| [point.time, point.value as f64])
+                    .collect();
+                (Some(lane.pid) == self.automation_lane, points)
+            })
+            .collect();
+        let plot = Plot::new("automation-plot")
+            .view_aspect(3.0)
+            .allow_drag(false)
+            .allow_scroll(false)
+            .allow_zoom(false)
+            .allow_boxed_zoom(false)
+            .include_x(0.0)
+            .include_x(length)
+            .include_y(0.0)
+            .include_y(1.0);
+        let response = plot.show(ui, |plot_ui| {
+            for (selected, points) in lines {
+                if selected {
+                    plot_ui.points(Points::new(points.clone()).radius(3.0));
+                }
+                plot_ui.line(Line::new(points).width(if selected { 2.0 } else { 1.0 }));
+            }
+            if let Some(position) = position {
+                plot_ui.vline(VLine::new(position));
+            }
+            plot_ui.pointer_coordinate()
+        });
+
+        // Selected lane is edited with mouse: pressing adds a point or grabs the nearest one,
+        // right click removes it
+        let (pid, pointer) = match (self.automation_lane, response.inner) {
+            (Some(pid), Some(pointer)) => (pid, pointer),
+            _ => return,
+        };
+        let (time, value) = (pointer.x.max(0.0), pointer.y.clamp(0.0, 1.0) as f32);
+        let lane = self.automation.lane_mut(pid);
+        let nearest = lane
+            .points()
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let dx = (point.time - time) / length;
+                let dy = (point.value - value) as f64;
+                (i, dx * dx + dy * dy)
+            })
+            .filter(|&(_, distance)| distance < 0.03 * 0.03)
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i);
+        let response = response.response;
+        let mut edited = false;
+        if response.dragged_by(egui::PointerButton::Primary) {
+            self.dragged_point = Some(match self.dragged_point {
+                Some(index) => lane.move_point(index, time, value),
+                None => nearest.unwrap_or_else(|| lane.insert(time, value)),
+            });
+            edited = true;
+        } else {
+            self.dragged_point = None;
+            if response.secondary_clicked() {
+                if let Some(index) = nearest {
+                    lane.remove(index);
+                    edited = true;
+                }
+            }
+        }
+        // Points added after loop end make it longer
+        if edited && time > self.automation.length {
+            self.automation.length = time;
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! External MIDI controllers mapped to patch parameters.
+use super::OwlWaveApp;
+use crate::midi_devices::{ControllerInputs, MIDI_CLIENT_NAME};
+use crate::owl_control::midi_learn::{control_change, ControlSource};
+use crate::owl_control::snapshot::apply_values;
+use eframe::egui;
+use egui::Ui;
+
+impl OwlWaveApp {
+    // Forwards controller CCs to mapped parameters of active session, or learns a new mapping
+    pub(super) fn update_controllers(&mut self, ctx: &egui::Context) {
+        let patch = self.session().owl_command_processor.patch_name.clone();
+        // Learning listens to every port, otherwise only ports with mappings are connected
+        let requested = match &patch {
+            Some(_) if self.midi_learn => None,
+            Some(patch) => Some(self.controller_mappings.ports(patch)),
+            None => Some(Vec::new()),
+        };
+        let patch = match patch {
+            Some(patch) if requested.as_ref().map_or(true, |ports| !ports.is_empty()) => patch,
+            _ => {
+                self.controller_inputs = None;
+                return;
+            }
+        };
+        let reconnect = self
+            .controller_inputs
+            .as_ref()
+            .map_or(true, |inputs| inputs.requested != requested);
+        if reconnect {
+            let exclude: Vec<String> = self
+                .sessions
+                .iter()
+                .filter(|session| session.midi_input.connection.is_some())
+                .filter_map(|session| session.midi_input.selected_name.clone())
+                .collect();
+            // Controller messages arrive without UI events
+            let ctx = ctx.clone();
+            self.controller_inputs = Some(ControllerInputs::new(
+                MIDI_CLIENT_NAME,
+                &exclude,
+                requested,
+                move || ctx.request_repaint(),
+            ));
+        }
+        let inputs = match &self.controller_inputs {
+            Some(inputs) => inputs,
+            None => return,
+        };
+        let parameters = &mut self.sessions[self.active_session]
+            .owl_command_processor
+            .parameters;
+        let mut changed = false;
+        for (port, frame) in inputs.receive() {
+            let (channel, cc, value) = match control_change(&frame.data) {
+                Some(control) => control,
+                None => continue,
+            };
+            let source = ControlSource {
+                port: port.to_string(),
+                channel,
+                cc,
+            };
+            if let Some(pid) = self.learning.take() {
+                self.controller_mappings.learn(&patch, source, pid);
+                continue;
+            }
+            let values = self
+                .controller_mappings
+                .receive(&patch, &source, value, |pid| {
+                    parameters.get(&pid).map(|parameter| parameter.value)
+                });
+            changed |= !values.is_empty();
+            apply_values(parameters, values);
+        }
+        if changed {
+            self.session_mut().sync_parameters();
+        }
+    }
+    pub(super) fn ui_controllers(&mut self, ui: &mut Ui) {
+        let patch = self.session().owl_command_processor.patch_name.clone();
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut self.midi_learn, "MIDI learn")
+                .on_hover_text("Click a parameter name, then move a control on a controller");
+            if !self.midi_learn {
+                self.learning = None;
+            }
+            match (&patch, self.learning) {
+                (None, _) => {
+                    ui.weak("Controllers can be mapped once patch name is received");
+                }
+                (Some(_), Some(pid)) => {
+                    ui.label(format!("Move a control for {}", pid.string_id()));
+                    if ui.button("Cancel").clicked() {
+                        self.learning = None;
+                    }
+                }
+                (Some(_), None) => {}
+            }
+        });
+        let patch = match patch {
+            Some(patch) => patch,
+            None => return,
+        };
+        if let Some(inputs) = &self.controller_inputs {
+            let names: Vec<&str> = inputs.port_names().collect();
+            if names.is_empty() {
+                ui.weak("No controller ports found");
+            } else {
+                ui.weak(format!("Listening to {}", names.join(", ")));
+            }
+        }
+        let mut remove = None;
+        egui::Grid::new("controllers-grid").show(ui, |ui| {
+            for (i, mapping) in self
+                .controller_mappings
+                .mappings_mut(&patch)
+                .iter_mut()
+                .enumerate()
+            {
+                ui.label(mapping.source.to_string());
+                ui.label(format!("→ {}", mapping.parameter));
+                ui.add(
+                    egui::DragValue::new(&mut mapping.min)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.01)
+                        .prefix("Min "),
+                );
+                ui.add(
+                    egui::DragValue::new(&mut mapping.max)
+                        .clamp_range(0.0..=1.0)
+                        .speed(0.01)
+                        .prefix("Max "),
+                );
+                ui.checkbox(&mut mapping.invert, "Invert");
+                ui.checkbox(&mut mapping.soft_takeover, "Soft takeover");
+                if mapping.is_waiting() {
+                    ui.weak("Pick up")
+                        .on_hover_text("Move control to current parameter value");
+                } else {
+                    ui.label("");
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            self.controller_mappings.remove(&patch, i);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+//! Sysex librarian page.
+use super::OwlWaveApp;
+use crate::file_io::FileKind;
+use crate::sysex_librarian::decode;
+use eframe::egui;
+use egui::Ui;
+
+impl OwlWaveApp {
+    pub(super) fn ui_librarian(&mut self, ui: &mut Ui) {
+        let now = ui.input().time;
+        ui.horizontal(|ui| {
+            if ui.button("Load .syx files").clicked() {
+                self.file_dialogs.open(ui.ctx(), FileKind::Sysex);
+            }
+            let has_messages = self
+                .sysex_files
+                .iter()
+                .any(|file| !file.messages.is_empty());
+            if ui
+                .add_enabled(has_messages, egui::Button::new("Send all"))
+                .clicked()
+            {
+                let all = self
+                    .sysex_files
+                    .iter()
+                    .flat_map(|file| file.messages.iter().cloned());
+                self.sysex_queue.start(all, now);
+            }
+            let mut delay_ms = self.sysex_queue.delay * 1000.0;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut delay_ms)
+                        .clamp_range(0.0..=5000.0)
+                        .prefix("Delay ")
+                        .suffix(" ms"),
+                )
+                .changed()
+            {
+                self.sysex_queue.delay = delay_ms / 1000.0;
+            }
+            if ui.button("Clear files").clicked() {
+                self.sysex_files.clear();
+            }
+        });
+        if !self.sysex_queue.is_empty() {
+            let (sent, total) = self.sysex_queue.progress();
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::ProgressBar::new(sent as f32 / total as f32)
+                        .text(format!("Sent {sent} of {total} messages"))
+                        .desired_width(300.0),
+                );
+                if ui.button("Cancel").clicked() {
+                    self.sysex_queue.cancel();
+                }
+            });
+        }
+        let mut send = None;
+        let mut remove = None;
+        egui::Grid::new("sysex-files-grid").show(ui, |ui| {
+            for (i, file) in self.sysex_files.iter().enumerate() {
+                ui.label(file.name.as_str());
+                ui.label(format!("{} messages", file.messages.len()));
+                ui.label(format!("{} bytes", file.size()));
+                if ui.button("Send").clicked() {
+                    send = Some(i);
+                }
+                if ui.button("Decode").clicked() {
+                    self.sysex_decoded = decode(&file.messages);
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = send {
+            let messages = self.sysex_files[i].messages.clone();
+            self.sysex_queue.start(messages, now);
+        }
+        if let Some(i) = remove {
+            self.sysex_files.remove(i);
+        }
+        ui.separator();
+        let capture = &mut self.sessions[self.active_session].sysex_capture;
+        let mut save = None;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut capture.active, "Capture received sysex");
+            ui.label(format!("{} messages", capture.messages().len()));
+            let captured = !capture.messages().is_empty();
+            if ui
+                .add_enabled(captured, egui::Button::new("Save .syx"))
+                .clicked()
+            {
+                save = Some(capture.to_syx());
+            }
+            if ui
+                .add_enabled(captured, egui::Button::new("Decode"))
+                .clicked()
+            {
+                self.sysex_decoded = decode(capture.messages());
+            }
+            if ui.button("Clear").clicked() {
+                capture.clear();
+            }
+        });
+        if let Some(data) = save {
+            self.save_file("capture.syx", &data);
+        }
+        if !self.sysex_decoded.is_empty() {
+            ui.separator();
+            egui::ScrollArea::vertical()
+                .id_source("sysex-decoded")
+                .auto_shrink([false, true])
+                .show(ui, |ui| {
+                    ui.monospace(self.sysex_decoded.as_str());
+                });
+        }
+    }
+}
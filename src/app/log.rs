@@ -0,0 +1,64 @@
+//! Log records view.
+use super::OwlWaveApp;
+use crate::log_view::LEVELS;
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui::Ui;
+use tracing::Level;
+
+impl OwlWaveApp {
+    /// Clears current error of active device with errors in its history
+    pub(super) fn clear_errors(&mut self) {
+        let session = self.session_mut();
+        session.owl_command_processor.error_message = None;
+        session.program_history.clear_errors();
+    }
+    pub(super) fn ui_log(&mut self, ui: &mut Ui) {
+        const MAX_HEIGHT: f32 = 150.0;
+        let filter = &mut self.log_filter;
+        let mut export = None;
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("log-level")
+                .selected_text(filter.level.as_str())
+                .show_ui(ui, |ui| {
+                    for level in LEVELS {
+                        ui.selectable_value(&mut filter.level, level, level.as_str());
+                    }
+                });
+            ui.add(egui::TextEdit::singleline(&mut filter.text).hint_text("Filter"));
+            if ui.button("Copy").clicked() {
+                ui.output().copied_text = self.log.to_text(filter);
+            }
+            if ui.button("Export").clicked() {
+                export = Some(self.log.to_text(filter));
+            }
+            if ui.button("Clear").clicked() {
+                self.log.clear();
+            }
+            if self.log.dropped() > 0 {
+                ui.weak(format!("{} dropped", self.log.dropped()));
+            }
+        });
+        let records = self.log.filtered(filter);
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, true])
+            .max_height(MAX_HEIGHT)
+            .stick_to_bottom(true)
+            .show_rows(ui, row_height, records.len(), |ui, rows| {
+                for record in &records[rows] {
+                    let text = egui::RichText::new(record.to_string()).monospace();
+                    let text = match record.level {
+                        Level::ERROR => text.color(Color32::RED),
+                        Level::WARN => text.color(Color32::YELLOW),
+                        Level::INFO => text,
+                        _ => text.weak(),
+                    };
+                    ui.label(text).on_hover_text(&record.target);
+                }
+            });
+        if let Some(text) = export {
+            self.save_file("owl-wave.log", text.as_bytes());
+        }
+    }
+}
@@ -0,0 +1,180 @@
+//! LFOs and envelopes that modulate patch parameters.
+use super::{MenuPage, OwlWaveApp};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::owl_control::modulator::ModulationThread;
+use crate::owl_control::modulator::{LfoShape, Modulator, ModulatorKind};
+use crate::owl_control::parameter::PARAMETER_COUNT;
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui::Ui;
+use owl_midi::PatchParameterId;
+use std::time::Duration;
+
+impl OwlWaveApp {
+    // Runs modulators for active session while any of them is enabled
+    pub(super) fn update_modulation(&mut self, ctx: &egui::Context) {
+        let active = self
+            .modulation
+            .lock()
+            .map_or(false, |engine| engine.is_active());
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let connection = self.sessions[self.active_session]
+                .midi_output
+                .connection
+                .as_ref()
+                .filter(|_| active);
+            let running = match (&self.modulation_thread, connection) {
+                (Some(thread), Some(connection)) => thread.connection().same(connection),
+                (None, None) => true,
+                _ => false,
+            };
+            if !running {
+                self.modulation_thread = connection.map(|connection| {
+                    ModulationThread::spawn(self.modulation.clone(), connection.clone())
+                });
+            }
+        }
+        #[cfg(target_arch = "wasm32")]
+        {
+            // There are no threads in browser, so modulation is sent on every frame
+            let connection = &mut self.sessions[self.active_session].midi_output.connection;
+            if let (true, Some(connection), Ok(mut engine)) =
+                (active, connection, self.modulation.lock())
+            {
+                engine.send(connection, ctx.input().unstable_dt as f64);
+            }
+        }
+        if active && self.menu_page == MenuPage::Parameters {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+    }
+    pub(super) fn ui_modulators(&mut self, ui: &mut Ui) {
+        let parameters = &self.sessions[self.active_session]
+            .owl_command_processor
+            .parameters;
+        let pids: Vec<PatchParameterId> = (0..PARAMETER_COUNT as isize)
+            .filter_map(|i| PatchParameterId::try_from(i).ok())
+            .filter(|pid| parameters.get(pid).map_or(false, |p| !p.output))
+            .collect();
+        let label = |pid: &PatchParameterId| match parameters.get(pid) {
+            Some(parameter) => format!("{} {}", pid.string_id(), parameter.name),
+            None => pid.string_id().to_string(),
+        };
+        let mut engine = match self.modulation.lock() {
+            Ok(engine) => engine,
+            Err(_) => return,
+        };
+        ui.horizontal(|ui| {
+            ui.label("Modulators");
+            if ui
+                .add_enabled(!pids.is_empty(), egui::Button::new("Add"))
+                .clicked()
+            {
+                let pid = pids[0];
+                engine.modulators.push(Modulator::new(
+                    pid,
+                    ModulatorKind::Lfo(LfoShape::Sine),
+                    parameters[&pid].value,
+                ));
+            }
+            ui.add(
+                egui::DragValue::new(&mut engine.send_rate)
+                    .clamp_range(1.0..=200.0)
+                    .suffix(" CCs/s"),
+            )
+            .on_hover_text("Maximum number of CCs sent per second for each parameter");
+            if let Some(err) = &engine.error {
+                ui.colored_label(Color32::RED, err.to_string());
+            }
+        });
+        let kinds: Vec<ModulatorKind> = LfoShape::ALL
+            .iter()
+            .map(|&shape| ModulatorKind::Lfo(shape))
+            .chain([ModulatorKind::envelope(), ModulatorKind::RandomWalk])
+            .collect();
+        let mut remove = None;
+        egui::Grid::new("modulators-grid").show(ui, |ui| {
+            for (i, modulator) in engine.modulators.iter_mut().enumerate() {
+                ui.checkbox(&mut modulator.enabled, "");
+                egui::ComboBox::from_id_source(("modulator-parameter", i))
+                    .selected_text(label(&modulator.pid))
+                    .show_ui(ui, |ui| {
+                        for pid in pids.iter() {
+                            ui.selectable_value(&mut modulator.pid, *pid, label(pid));
+                        }
+                    });
+                egui::ComboBox::from_id_source(("modulator-kind", i))
+                    .selected_text(modulator.kind.name())
+                    .show_ui(ui, |ui| {
+                        for kind in kinds.iter() {
+                            // Keeps envelope times when envelope is selected again
+                            let selected = kind.name() == modulator.kind.name();
+                            if ui.selectable_label(selected, kind.name()).clicked() && !selected {
+                                modulator.kind = *kind;
+                            }
+                        }
+                    });
+                ui.horizontal(|ui| match &mut modulator.kind {
+                    ModulatorKind::Envelope {
+                        attack,
+                        decay,
+                        sustain,
+                        release,
+                    } => {
+                        for (time, prefix) in [(attack, "A "), (decay, "D ")] {
+                            ui.add(
+                                egui::DragValue::new(time)
+                                    .clamp_range(0.0..=10.0)
+                                    .speed(0.01)
+                                    .prefix(prefix)
+                                    .suffix(" s"),
+                            );
+                        }
+                        ui.add(
+                            egui::DragValue::new(sustain)
+                                .clamp_range(0.0..=1.0)
+                                .speed(0.01)
+                                .prefix("S "),
+                        );
+                        ui.add(
+                            egui::DragValue::new(release)
+                                .clamp_range(0.0..=10.0)
+                                .speed(0.01)
+                                .prefix("R ")
+                                .suffix(" s"),
+                        );
+                        // Gate is held while pointer is down, like patch buttons
+                        modulator.gate = ui.button("Gate").is_pointer_button_down_on();
+                    }
+                    _ => {
+                        ui.add(
+                            egui::DragValue::new(&mut modulator.rate)
+                                .clamp_range(0.01..=50.0)
+                                .speed(0.01)
+                                .suffix(" Hz"),
+                        );
+                    }
+                });
+                ui.add(
+                    egui::Slider::new(&mut modulator.depth, 0.0..=1.0)
+                        .text("Depth")
+                        .fixed_decimals(2),
+                );
+                ui.add(
+                    egui::Slider::new(&mut modulator.center, 0.0..=1.0)
+                        .text("Center")
+                        .fixed_decimals(2),
+                );
+                ui.add(egui::ProgressBar::new(modulator.output()).desired_width(60.0));
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = remove {
+            engine.modulators.remove(i);
+        }
+    }
+}
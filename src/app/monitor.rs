@@ -0,0 +1,133 @@
+//! MIDI monitor page.
+use super::OwlWaveApp;
+use crate::midi_monitor::{command_name, Direction, MessageKind, MonitorEntry};
+use eframe::egui;
+use egui::Ui;
+use owl_midi::OpenWareMidiSysexCommand;
+use std::time::Duration;
+
+impl OwlWaveApp {
+    pub(super) fn ui_monitor(&mut self, ui: &mut Ui) {
+        // Long sysex messages are cut in the list, full dump is shown on hover
+        const MAX_HEX_LENGTH: usize = 3 * 24;
+        let filter = &mut self.monitor_filter;
+        let monitor = &mut self.sessions[self.active_session].monitor;
+        let mut export = None;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut monitor.paused, "Pause");
+            if ui.button("Clear").clicked() {
+                monitor.clear();
+            }
+            if ui.button("Export text").clicked() {
+                export = Some(("midi-monitor.txt", monitor.to_text(filter).into_bytes()));
+            }
+            if ui.button("Export .syx").clicked() {
+                export = Some(("midi-monitor.syx", monitor.to_syx(filter)));
+            }
+            ui.add(
+                egui::DragValue::new(&mut monitor.capacity)
+                    .clamp_range(100..=100_000)
+                    .prefix("Keep ")
+                    .suffix(" messages"),
+            );
+            ui.label(format!("{} messages", monitor.len()));
+            if monitor.dropped() > 0 {
+                ui.weak(format!("{} dropped", monitor.dropped()));
+            }
+        });
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_id_source("monitor-direction")
+                .selected_text(match filter.direction {
+                    None => "Sent and received",
+                    Some(Direction::In) => "Received",
+                    Some(Direction::Out) => "Sent",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut filter.direction, None, "Sent and received");
+                    ui.selectable_value(&mut filter.direction, Some(Direction::In), "Received");
+                    ui.selectable_value(&mut filter.direction, Some(Direction::Out), "Sent");
+                });
+            egui::ComboBox::from_id_source("monitor-channel")
+                .selected_text(match filter.channel {
+                    None => "All channels".to_string(),
+                    Some(channel) => format!("Channel {}", channel + 1),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut filter.channel, None, "All channels");
+                    for channel in 0..16 {
+                        ui.selectable_value(
+                            &mut filter.channel,
+                            Some(channel),
+                            format!("Channel {}", channel + 1),
+                        );
+                    }
+                });
+            egui::ComboBox::from_id_source("monitor-command")
+                .selected_text(
+                    filter
+                        .command
+                        .map_or("All commands".to_string(), command_name),
+                )
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut filter.command, None, "All commands");
+                    for command in (0..0x80u8)
+                        .filter(|&c| OpenWareMidiSysexCommand::try_from(c as isize).is_ok())
+                    {
+                        ui.selectable_value(
+                            &mut filter.command,
+                            Some(command),
+                            command_name(command),
+                        );
+                    }
+                });
+        });
+        ui.horizontal_wrapped(|ui| {
+            for kind in MessageKind::ALL {
+                let mut shown = !filter.hidden_kinds.contains(&kind);
+                if ui.checkbox(&mut shown, kind.name()).changed() {
+                    if shown {
+                        filter.hidden_kinds.remove(&kind);
+                    } else {
+                        filter.hidden_kinds.insert(kind);
+                    }
+                }
+            }
+        });
+        ui.separator();
+        let entries: Vec<&MonitorEntry> = monitor.filtered(filter).collect();
+        let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+        egui::ScrollArea::vertical()
+            .auto_shrink([false, false])
+            .stick_to_bottom(true)
+            .show_rows(ui, row_height, entries.len(), |ui, rows| {
+                for entry in &entries[rows] {
+                    let mut hex = entry.hex();
+                    let full_hex = (hex.len() > MAX_HEX_LENGTH).then(|| hex.clone());
+                    hex.truncate(MAX_HEX_LENGTH);
+                    let response = ui.monospace(format!(
+                        "{:10.4} {} {:<40} {hex}{}",
+                        entry.time,
+                        entry.direction.symbol(),
+                        entry.describe(),
+                        if full_hex.is_some() { "…" } else { "" }
+                    ));
+                    let timestamp = entry
+                        .timestamp
+                        .map(|timestamp| format!("Input timestamp {timestamp} µs\n"));
+                    if timestamp.is_some() || full_hex.is_some() {
+                        response.on_hover_text(format!(
+                            "{}{}",
+                            timestamp.unwrap_or_default(),
+                            full_hex.unwrap_or_default()
+                        ));
+                    }
+                }
+            });
+        if !monitor.paused {
+            ui.ctx().request_repaint_after(Duration::from_millis(100));
+        }
+        if let Some((name, data)) = export {
+            self.save_file(name, &data);
+        }
+    }
+}
@@ -0,0 +1,191 @@
+//! Parameter and button controls with snapshots of their values.
+use super::OwlWaveApp;
+use crate::owl_control::parameter::{
+    button_name, BANK_SIZE, BUTTON_A, BUTTON_COUNT, PARAMETER_COUNT,
+};
+use crate::owl_control::snapshot::{apply_values, Snapshot};
+use eframe::egui;
+use egui::Ui;
+use owl_midi::PatchParameterId;
+use std::time::Duration;
+
+impl OwlWaveApp {
+    pub(super) fn ui_parameters(&mut self, ui: &mut Ui) {
+        let processor = &mut self.sessions[self.active_session].owl_command_processor;
+        let learning = &mut self.learning;
+        let midi_learn = self.midi_learn;
+        for bank in 0..PARAMETER_COUNT / BANK_SIZE {
+            let pids: Vec<PatchParameterId> = (bank * BANK_SIZE..(bank + 1) * BANK_SIZE)
+                .filter_map(|i| PatchParameterId::try_from(i as isize).ok())
+                .collect();
+            if !pids
+                .iter()
+                .any(|pid| processor.parameters.contains_key(pid))
+            {
+                continue;
+            }
+            let title = match (pids.first(), pids.last()) {
+                (Some(first), Some(last)) => format!("{}–{}", first.string_id(), last.string_id()),
+                _ => continue,
+            };
+            egui::CollapsingHeader::new(title)
+                .default_open(true)
+                .show(ui, |ui| {
+                    egui::Grid::new(("parameters-grid", bank))
+                        .num_columns(3)
+                        .min_col_width(40.0)
+                        .show(ui, |ui| {
+                            for pid in pids.iter() {
+                                if let Some(parameter) = processor.parameters.get_mut(pid) {
+                                    ui.label(pid.string_id());
+                                    if midi_learn {
+                                        let selected = *learning == Some(*pid);
+                                        if ui
+                                            .selectable_label(selected, parameter.name.as_str())
+                                            .clicked()
+                                        {
+                                            *learning = (!selected).then_some(*pid);
+                                        }
+                                    } else {
+                                        ui.label(parameter.name.as_str());
+                                    }
+                                    if parameter.output {
+                                        ui.add(
+                                            egui::ProgressBar::new(parameter.value)
+                                                .text(format!("{:.3}", parameter.value)),
+                                        );
+                                    } else {
+                                        ui.add(
+                                            egui::Slider::new(&mut parameter.value, 0f32..=1f32)
+                                                .fixed_decimals(3),
+                                        );
+                                    }
+                                    ui.end_row();
+                                }
+                            }
+                        });
+                });
+        }
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Buttons");
+            for id in BUTTON_A..BUTTON_A + BUTTON_COUNT {
+                let pressed = self.session().owl_command_processor.buttons.get(&id) == Some(&true);
+                let fill = if pressed {
+                    ui.visuals().selection.bg_fill
+                } else {
+                    ui.visuals().widgets.inactive.bg_fill
+                };
+                let response = ui.add(egui::Button::new(button_name(id)).fill(fill));
+                // Button is held while pointer is down, so that momentary buttons work
+                let down = response.is_pointer_button_down_on();
+                if down && self.held_button != Some(id) {
+                    self.held_button = Some(id);
+                    self.session_mut().set_button(id, true);
+                } else if !down && self.held_button == Some(id) {
+                    self.held_button = None;
+                    self.session_mut().set_button(id, false);
+                }
+            }
+        });
+        ui.separator();
+        self.ui_snapshots(ui);
+        ui.separator();
+        self.ui_modulators(ui);
+        ui.separator();
+        self.ui_controllers(ui);
+        self.session_mut().sync_parameters();
+    }
+    pub(super) fn ui_snapshots(&mut self, ui: &mut Ui) {
+        let patch = match self.session().owl_command_processor.patch_name.clone() {
+            Some(patch) => patch,
+            None => {
+                ui.weak("Snapshots are available once patch name is received");
+                return;
+            }
+        };
+        ui.horizontal(|ui| {
+            ui.label("Snapshot");
+            ui.text_edit_singleline(&mut self.snapshot_name);
+            let name = self.snapshot_name.trim().to_string();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Save"))
+                .clicked()
+            {
+                let snapshot =
+                    Snapshot::capture(name, &self.session().owl_command_processor.parameters);
+                self.snapshots.store(&patch, snapshot);
+                self.snapshot_name.clear();
+            }
+        });
+        let mut recall = None;
+        let mut remove = None;
+        egui::Grid::new("snapshots-grid").show(ui, |ui| {
+            for (i, snapshot) in self.snapshots.snapshots(&patch).iter().enumerate() {
+                ui.label(snapshot.name.as_str());
+                if ui.button("Recall").clicked() {
+                    recall = Some(i);
+                }
+                if ui.small_button("✖").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+            }
+        });
+        if let Some(i) = recall {
+            let values: Vec<_> = self.snapshots.snapshots(&patch)[i].values().collect();
+            apply_values(
+                &mut self.session_mut().owl_command_processor.parameters,
+                values,
+            );
+        }
+        if let Some(i) = remove {
+            self.snapshots.remove(&patch, i);
+        }
+
+        let snapshots = self.snapshots.snapshots(&patch);
+        if snapshots.len() < 2 {
+            return;
+        }
+        let morph = &mut self.morph;
+        morph.from = morph.from.min(snapshots.len() - 1);
+        morph.to = morph.to.min(snapshots.len() - 1);
+        ui.horizontal(|ui| {
+            ui.label("Morph");
+            egui::ComboBox::from_id_source("morph-from")
+                .selected_text(snapshots[morph.from].name.as_str())
+                .show_ui(ui, |ui| {
+                    for (i, snapshot) in snapshots.iter().enumerate() {
+                        ui.selectable_value(&mut morph.from, i, snapshot.name.as_str());
+                    }
+                });
+            ui.add(egui::Slider::new(&mut morph.amount, 0.0..=1.0).show_value(false));
+            egui::ComboBox::from_id_source("morph-to")
+                .selected_text(snapshots[morph.to].name.as_str())
+                .show_ui(ui, |ui| {
+                    for (i, snapshot) in snapshots.iter().enumerate() {
+                        ui.selectable_value(&mut morph.to, i, snapshot.name.as_str());
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut morph.rate)
+                    .clamp_range(1.0..=100.0)
+                    .suffix(" updates/s"),
+            );
+        });
+        let now = ui.input().time;
+        let values = morph
+            .poll(now)
+            .map(|amount| snapshots[morph.from].morph(&snapshots[morph.to], amount));
+        if let Some(delay) = morph.pending(now) {
+            ui.ctx()
+                .request_repaint_after(Duration::from_secs_f64(delay));
+        }
+        if let Some(values) = values {
+            apply_values(
+                &mut self.session_mut().owl_command_processor.parameters,
+                values,
+            );
+        }
+    }
+}
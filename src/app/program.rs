@@ -0,0 +1,158 @@
+//! Messages and resource usage reported by running patch.
+use super::OwlWaveApp;
+use crate::notification;
+use crate::owl_control::program_history::ProgramMessageKind;
+use crate::owl_control::program_stats::StatsWarning;
+use byte_unit::Byte;
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui::plot::{HLine, Legend, Line, Plot};
+use egui::Ui;
+
+impl OwlWaveApp {
+    pub(super) fn ui_messages(&mut self, ui: &mut Ui) {
+        let mut export = None;
+        ui.horizontal(|ui| {
+            if ui.button("Clear").clicked() {
+                self.session_mut().program_history.clear();
+            }
+            if ui.button("Clear errors").clicked() {
+                self.clear_errors();
+            }
+            if ui.button("Export text").clicked() {
+                export = Some(self.session().program_history.to_text());
+            }
+            if ui
+                .checkbox(&mut self.notify_errors, "Notify on errors")
+                .changed()
+                && self.notify_errors
+            {
+                notification::request_permission();
+            }
+        });
+        let history = &self.sessions[self.active_session].program_history;
+        egui::ScrollArea::vertical()
+            .id_source("program-messages")
+            .auto_shrink([false, true])
+            .max_height(200.0)
+            .stick_to_bottom(true)
+            .show(ui, |ui| {
+                egui::Grid::new("program-messages-grid")
+                    .num_columns(3)
+                    .striped(true)
+                    .show(ui, |ui| {
+                        for entry in history.entries() {
+                            ui.monospace(format!("{:10.3}", entry.time));
+                            match entry.kind {
+                                ProgramMessageKind::Message => ui.label(&entry.text),
+                                ProgramMessageKind::Error => {
+                                    ui.colored_label(Color32::RED, &entry.text)
+                                }
+                            };
+                            if entry.count > 1 {
+                                ui.weak(format!("×{}", entry.count)).on_hover_text(format!(
+                                    "Last received at {:.3}",
+                                    entry.last_time
+                                ));
+                            }
+                            ui.end_row();
+                        }
+                    });
+            });
+        if !history.series().is_empty() {
+            ui.separator();
+            Plot::new("program-values")
+                .legend(Legend::default())
+                .show(ui, |plot_ui| {
+                    for (name, points) in history.series() {
+                        let points: Vec<[f64; 2]> = points.iter().copied().collect();
+                        plot_ui.line(Line::new(points).name(name));
+                    }
+                });
+        }
+        if let Some(text) = export {
+            self.save_file("patch-messages.txt", text.as_bytes());
+        }
+    }
+    pub(super) fn ui_stats(&mut self, ui: &mut Ui) {
+        const PLOT_HEIGHT: f32 = 150.0;
+        let stats = &mut self.sessions[self.active_session].program_stats;
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut stats.polling, "Poll");
+            ui.add(
+                egui::DragValue::new(&mut stats.interval)
+                    .clamp_range(0.1..=10.0)
+                    .speed(0.1)
+                    .prefix("every ")
+                    .suffix(" s"),
+            );
+            ui.add(
+                egui::DragValue::new(&mut stats.cpu_warning)
+                    .clamp_range(1.0..=100.0)
+                    .prefix("Warn at ")
+                    .suffix("% CPU"),
+            );
+            let mut limit_kb = stats.memory_limit / 1024;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut limit_kb)
+                        .prefix("Memory limit ")
+                        .suffix(" KiB"),
+                )
+                .on_hover_text(
+                    "Warns when patch uses most of it, 0 disables warning. \
+                    Limit reported by device is used instead when available",
+                )
+                .changed()
+            {
+                stats.memory_limit = limit_kb * 1024;
+            }
+            if ui.button("Clear").clicked() {
+                stats.clear();
+            }
+        });
+        if let Some(latest) = stats.latest() {
+            ui.horizontal(|ui| {
+                if let Some(cpu) = latest.cpu {
+                    ui.label(format!("CPU {cpu:.0}%"));
+                }
+                if let Some(memory) = latest.memory {
+                    let memory = Byte::from_bytes(memory.into()).get_appropriate_unit(true);
+                    ui.label(format!("Memory {memory}"));
+                }
+            });
+        }
+        for warning in stats.warnings() {
+            ui.colored_label(Color32::RED, warning.message());
+        }
+        let warning_color = |warning| {
+            if stats.warnings().contains(&warning) {
+                Color32::RED
+            } else {
+                Color32::GRAY
+            }
+        };
+        ui.label("CPU load, %");
+        Plot::new("stats-cpu")
+            .height(PLOT_HEIGHT)
+            .include_y(0.0)
+            .include_y(100.0)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(stats.cpu_points()).name("CPU"));
+                plot_ui
+                    .hline(HLine::new(stats.cpu_warning).color(warning_color(StatsWarning::Cpu)));
+            });
+        ui.label("Memory, bytes");
+        Plot::new("stats-memory")
+            .height(PLOT_HEIGHT)
+            .include_y(0.0)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(stats.memory_points()).name("Memory"));
+                let limit = stats.memory_limit();
+                if limit > 0 {
+                    plot_ui
+                        .hline(HLine::new(limit as f64).color(warning_color(StatsWarning::Memory)));
+                }
+            });
+    }
+}
@@ -0,0 +1,168 @@
+//! Oscilloscope and spectrum of audio input, with wavetable capture.
+use super::OwlWaveApp;
+use crate::audio_analysis::{Spectrum, TriggerMode, CAPTURE_CAPACITY, FFT_SIZES, MIN_DB};
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui::plot::{HLine, Line, Plot};
+use egui::Ui;
+use std::time::Duration;
+
+impl OwlWaveApp {
+    pub(super) fn ui_scope(&mut self, ui: &mut Ui) {
+        const PLOT_HEIGHT: f32 = 200.0;
+        let capture = &self.audio_handler.capture;
+        let sample_rate = capture.sample_rate();
+        let scope = &mut self.oscilloscope;
+        if !self.audio_handler.input_active() {
+            ui.label("Select an audio input to capture it");
+        }
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Trigger")
+                .selected_text(scope.trigger.name())
+                .show_ui(ui, |ui| {
+                    for mode in TriggerMode::ALL {
+                        ui.selectable_value(&mut scope.trigger, mode, mode.name());
+                    }
+                });
+            ui.add(
+                egui::DragValue::new(&mut scope.level)
+                    .clamp_range(-1.0..=1.0)
+                    .speed(0.01)
+                    .prefix("Level "),
+            );
+            let mut span_ms = scope.span * 1000.0;
+            if ui
+                .add(
+                    egui::DragValue::new(&mut span_ms)
+                        .clamp_range(1.0..=500.0)
+                        .prefix("Span ")
+                        .suffix(" ms"),
+                )
+                .changed()
+            {
+                scope.span = span_ms / 1000.0;
+            }
+            ui.checkbox(&mut scope.frozen, "Freeze");
+            let mut channel = capture.channel();
+            let channel_name = |channel: Option<usize>| match channel {
+                Some(channel) => format!("Channel {}", channel + 1),
+                None => "Mix".to_string(),
+            };
+            egui::ComboBox::from_id_source("scope-channel")
+                .selected_text(channel_name(channel))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut channel, None, channel_name(None));
+                    for i in 0..self.audio_handler.input_channels {
+                        ui.selectable_value(&mut channel, Some(i), channel_name(Some(i)));
+                    }
+                });
+            if channel != capture.channel() {
+                capture.set_channel(channel);
+            }
+        });
+        let samples = capture.latest(scope.capture_len(sample_rate).max(self.spectrum.size()));
+        scope.update(&samples, sample_rate);
+        if !scope.frozen {
+            self.spectrum.update(&samples);
+        }
+        ui.label(if scope.is_triggered() {
+            "Triggered"
+        } else {
+            "Free running"
+        });
+        Plot::new("scope")
+            .height(PLOT_HEIGHT)
+            .include_y(-1.0)
+            .include_y(1.0)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(scope.points(sample_rate)));
+                if scope.trigger != TriggerMode::Off {
+                    plot_ui.hline(HLine::new(scope.level).color(Color32::GRAY));
+                }
+            });
+        ui.horizontal(|ui| {
+            let mut size = self.spectrum.size();
+            egui::ComboBox::from_label("FFT size")
+                .selected_text(size.to_string())
+                .show_ui(ui, |ui| {
+                    for fft_size in FFT_SIZES {
+                        ui.selectable_value(&mut size, fft_size, fft_size.to_string());
+                    }
+                });
+            if size != self.spectrum.size() {
+                let averaging = self.spectrum.averaging;
+                self.spectrum = Spectrum::new(size);
+                self.spectrum.averaging = averaging;
+            }
+            ui.add(egui::Slider::new(&mut self.spectrum.averaging, 0.0..=0.95).text("Averaging"));
+            if let Some(peak) = self.spectrum.peak(sample_rate) {
+                ui.label(format!("Peak {peak:.1} Hz"));
+            }
+        });
+        Plot::new("spectrum")
+            .height(PLOT_HEIGHT)
+            .include_x(0.0)
+            .include_y(MIN_DB)
+            .include_y(0.0)
+            .show(ui, |plot_ui| {
+                plot_ui.line(Line::new(self.spectrum.points(sample_rate)).name("dB"));
+            });
+        ui.separator();
+        self.ui_wave_capture(ui);
+        if self.audio_handler.input_active() && !self.oscilloscope.frozen {
+            ui.ctx().request_repaint_after(Duration::from_millis(30));
+        }
+    }
+    /// Captures cycles from audio input into grid, starting from active wave
+    pub(super) fn ui_wave_capture(&mut self, ui: &mut Ui) {
+        let capture = &mut self.wave_capture;
+        let free_waves = self.grid.get_waves().saturating_sub(self.active_wave_id);
+        ui.horizontal(|ui| {
+            ui.label("Capture wavetable");
+            ui.add(
+                egui::DragValue::new(&mut capture.cycles)
+                    .clamp_range(1..=free_waves.max(1))
+                    .suffix(" cycles"),
+            );
+            let mut detect = capture.frequency.is_none();
+            if ui.checkbox(&mut detect, "Detect pitch").changed() {
+                capture.frequency = (!detect).then_some(440.0);
+            }
+            if let Some(frequency) = &mut capture.frequency {
+                ui.add(
+                    egui::DragValue::new(frequency)
+                        .clamp_range(1.0..=20000.0)
+                        .suffix(" Hz"),
+                );
+            }
+            let button = ui.add_enabled(
+                self.audio_handler.input_active(),
+                egui::Button::new("Capture"),
+            );
+            if button.clicked() {
+                let samples = self.audio_handler.capture.latest(CAPTURE_CAPACITY);
+                let sample_rate = self.audio_handler.capture.sample_rate();
+                let result = capture.capture(&samples, sample_rate, self.grid.get_samples());
+                self.wave_capture_status = Some(match result {
+                    Ok((frequency, waves)) => {
+                        let count = self.grid.set_waves(self.active_wave_id, &waves);
+                        Ok(format!(
+                            "Captured {count} waves at {frequency:.1} Hz from wave {}",
+                            self.active_wave_id + 1
+                        ))
+                    }
+                    Err(err) => Err(err.to_string()),
+                });
+            }
+        });
+        match &self.wave_capture_status {
+            Some(Ok(status)) => {
+                ui.label(status.as_str());
+            }
+            Some(Err(err)) => {
+                ui.colored_label(Color32::RED, err.as_str());
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Device settings editor with profiles.
+use super::OwlWaveApp;
+use crate::device_session::DeviceSession;
+use crate::file_io::FileKind;
+use crate::owl_control::settings::{
+    setting_code, setting_info, setting_name, SettingKind, SettingsProfile, PROFILE_EXTENSION,
+};
+use eframe::egui;
+use eframe::epaint::Color32;
+use egui::Ui;
+use owl_midi::{OpenWareMidiSysexCommand, SYSEX_CONFIGURATIONS};
+
+impl OwlWaveApp {
+    pub(super) fn ui_settings(&mut self, ui: &mut Ui) {
+        let session = self.session_mut();
+        let device = &session.owl_command_processor.settings;
+        let edits = &mut session.settings_edits;
+        egui::Grid::new("settings-grid")
+            .num_columns(3)
+            .spacing([8.0, 4.0])
+            .min_col_width(150.0)
+            .striped(true)
+            .show(ui, |ui| {
+                for &config in SYSEX_CONFIGURATIONS.iter() {
+                    let info = setting_info(config);
+                    ui.label(setting_name(config)).on_hover_text(format!(
+                        "{} ({})",
+                        info.description,
+                        setting_code(config)
+                    ));
+                    let mut value = match edits.value(device, config) {
+                        Some(value) => value,
+                        None => {
+                            ui.weak("Not received");
+                            ui.end_row();
+                            continue;
+                        }
+                    };
+                    let changed = match info.kind {
+                        SettingKind::Toggle => {
+                            let mut enabled = value != 0;
+                            let changed = ui.checkbox(&mut enabled, "").changed();
+                            value = enabled as i64;
+                            changed
+                        }
+                        SettingKind::Choice(choices) => {
+                            let mut changed = false;
+                            egui::ComboBox::from_id_source(config as isize)
+                                .selected_text(info.format(value))
+                                .show_ui(ui, |ui| {
+                                    for &(choice, label) in choices {
+                                        changed |= ui
+                                            .selectable_value(&mut value, choice, label)
+                                            .changed();
+                                    }
+                                });
+                            changed
+                        }
+                        SettingKind::Range { min, max }
+                            if max.checked_sub(min).map_or(false, |span| span <= 1024) =>
+                        {
+                            ui.add(
+                                egui::Slider::new(&mut value, min..=max)
+                                    .suffix(format!(" {}", info.unit)),
+                            )
+                            .changed()
+                        }
+                        SettingKind::Range { min, max } => ui
+                            .add(egui::DragValue::new(&mut value).clamp_range(min..=max))
+                            .changed(),
+                    };
+                    if changed {
+                        edits.set(device, config, value);
+                    }
+                    match device.get(&config) {
+                        Some(&device_value) if device_value != value => {
+                            if ui
+                                .small_button("↺")
+                                .on_hover_text(format!(
+                                    "Device value {}",
+                                    info.format(device_value)
+                                ))
+                                .clicked()
+                            {
+                                edits.revert(config);
+                            }
+                        }
+                        _ => {
+                            ui.label("");
+                        }
+                    }
+                    ui.end_row()
+                }
+            });
+
+        ui.separator();
+        let changes = edits.changes(device);
+        if changes.is_empty() {
+            ui.label("No changes");
+        } else {
+            egui::Grid::new("settings-changes")
+                .num_columns(3)
+                .spacing([8.0, 4.0])
+                .show(ui, |ui| {
+                    for change in changes.iter() {
+                        let info = setting_info(change.config);
+                        ui.label(setting_name(change.config));
+                        ui.label(
+                            change
+                                .device_value
+                                .map_or("Not received".to_string(), |value| info.format(value)),
+                        );
+                        let value = format!("→ {}", info.format(change.value));
+                        match change.validate() {
+                            Ok(_) => ui.colored_label(Color32::YELLOW, value),
+                            Err(err) => ui
+                                .colored_label(Color32::RED, value)
+                                .on_hover_text(format!("Outside known values: {err}")),
+                        };
+                        ui.end_row()
+                    }
+                });
+        }
+        ui.horizontal(|ui| {
+            let has_changes = !changes.is_empty();
+            let send_changes = |session: &mut DeviceSession| {
+                for change in changes.iter() {
+                    session.send_setting(change.config, change.value);
+                }
+                session.settings_edits.clear();
+            };
+            if ui
+                .add_enabled(has_changes, egui::Button::new("Apply"))
+                .on_hover_text("Send changes without storing them")
+                .clicked()
+            {
+                send_changes(self.session_mut());
+            }
+            if ui
+                .button("Save")
+                .on_hover_text("Send changes and store settings on device")
+                .clicked()
+            {
+                send_changes(self.session_mut());
+                self.session_mut()
+                    .send_sysex_command(OpenWareMidiSysexCommand::SYSEX_SETTINGS_STORE);
+            }
+            if ui
+                .add_enabled(has_changes, egui::Button::new("Revert"))
+                .clicked()
+            {
+                self.session_mut().settings_edits.clear();
+            }
+            if ui.button("Reload").clicked() {
+                self.session_mut()
+                    .request_settings(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND);
+            }
+            if ui.button("Reset").clicked() {
+                let session = self.session_mut();
+                session.settings_edits.clear();
+                session.send_sysex_command(OpenWareMidiSysexCommand::SYSEX_SETTINGS_RESET);
+                session.request_settings(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND);
+            }
+            ui.separator();
+            if ui
+                .button("Import profile")
+                .on_hover_text("Load settings profile, changes can be reviewed before sending")
+                .clicked()
+            {
+                self.file_dialogs.open(ui.ctx(), FileKind::Settings);
+            }
+            let session = self.session();
+            let has_settings = !session.owl_command_processor.settings.is_empty();
+            if ui
+                .add_enabled(has_settings, egui::Button::new("Export profile"))
+                .on_hover_text("Save device settings to a profile")
+                .clicked()
+            {
+                let profile = SettingsProfile::new(
+                    Some(session.name().to_string()),
+                    &session.owl_command_processor.settings,
+                );
+                match profile.to_json() {
+                    Ok(data) => self.save_file(&format!("settings.{PROFILE_EXTENSION}"), &data),
+                    Err(err) => self.file_error = Some(format!("Can't export settings: {err}")),
+                }
+            }
+        });
+    }
+}
@@ -0,0 +1,337 @@
+//! Audio captured from input stream, shown by oscilloscope with trigger and FFT spectrum.
+use rustfft::num_complex::Complex;
+use rustfft::{Fft, FftPlanner};
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+/// Number of captured samples kept, enough for the largest FFT and longest scope span
+pub const CAPTURE_CAPACITY: usize = 1 << 16;
+pub const FFT_SIZES: [usize; 5] = [512, 1024, 2048, 4096, 8192];
+pub const DEFAULT_FFT_SIZE: usize = 2048;
+/// Level shown for silent bins
+pub const MIN_DB: f32 = -120.0;
+
+#[derive(Debug)]
+struct Capture {
+    samples: VecDeque<f32>,
+    sample_rate: u32,
+    /// Input channel that is captured, all channels are mixed if it's not set
+    channel: Option<usize>,
+}
+
+/// Mono samples from input stream, shared with audio thread
+#[derive(Clone, Debug)]
+pub struct CaptureBuffer {
+    inner: Arc<Mutex<Capture>>,
+}
+
+impl Default for CaptureBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CaptureBuffer {
+    pub fn new() -> Self {
+        CaptureBuffer {
+            inner: Arc::new(Mutex::new(Capture {
+                samples: VecDeque::with_capacity(CAPTURE_CAPACITY),
+                sample_rate: 48000,
+                channel: None,
+            })),
+        }
+    }
+    /// Adds interleaved samples from stream callback
+    pub fn push_frames(&self, samples: impl IntoIterator<Item = f32>, channels: usize) {
+        let mut capture = self.inner.lock().unwrap();
+        let channels = channels.max(1);
+        let channel = capture.channel.filter(|&channel| channel < channels);
+        let mut sum = 0.0;
+        for (i, sample) in samples.into_iter().enumerate() {
+            let index = i % channels;
+            let value = match channel {
+                Some(channel) => (index == channel).then_some(sample),
+                None => {
+                    sum += sample;
+                    (index == channels - 1).then_some(sum / channels as f32)
+                }
+            };
+            if index == channels - 1 {
+                sum = 0.0;
+            }
+            if let Some(value) = value {
+                if capture.samples.len() >= CAPTURE_CAPACITY {
+                    capture.samples.pop_front();
+                }
+                capture.samples.push_back(value);
+            }
+        }
+    }
+    pub fn sample_rate(&self) -> u32 {
+        self.inner.lock().unwrap().sample_rate
+    }
+    /// Starts capture of a new stream
+    pub fn reset(&self, sample_rate: u32) {
+        let mut capture = self.inner.lock().unwrap();
+        capture.samples.clear();
+        capture.sample_rate = sample_rate;
+    }
+    pub fn channel(&self) -> Option<usize> {
+        self.inner.lock().unwrap().channel
+    }
+    pub fn set_channel(&self, channel: Option<usize>) {
+        self.inner.lock().unwrap().channel = channel;
+    }
+    /// Last captured samples, fewer than requested if there aren't enough
+    pub fn latest(&self, len: usize) -> Vec<f32> {
+        let capture = self.inner.lock().unwrap();
+        let start = capture.samples.len().saturating_sub(len);
+        capture.samples.range(start..).copied().collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerMode {
+    /// Latest samples are shown
+    Off,
+    Rising,
+    Falling,
+}
+
+impl TriggerMode {
+    pub const ALL: [TriggerMode; 3] = [TriggerMode::Off, TriggerMode::Rising, TriggerMode::Falling];
+    pub fn name(&self) -> &'static str {
+        match self {
+            TriggerMode::Off => "Off",
+            TriggerMode::Rising => "Rising",
+            TriggerMode::Falling => "Falling",
+        }
+    }
+}
+
+/// Index of the last sample that crosses level in trigger direction
+pub fn find_trigger(samples: &[f32], mode: TriggerMode, level: f32) -> Option<usize> {
+    (1..samples.len()).rev().find(|&i| {
+        let (previous, current) = (samples[i - 1], samples[i]);
+        match mode {
+            TriggerMode::Off => false,
+            TriggerMode::Rising => previous < level && current >= level,
+            TriggerMode::Falling => previous > level && current <= level,
+        }
+    })
+}
+
+/// Oscilloscope that shows captured samples starting from trigger point
+#[derive(Debug)]
+pub struct Oscilloscope {
+    pub trigger: TriggerMode,
+    pub level: f32,
+    /// Shown time span in seconds
+    pub span: f64,
+    /// Keeps showing last frame
+    pub frozen: bool,
+    frame: Vec<f32>,
+    triggered: bool,
+}
+
+impl Default for Oscilloscope {
+    fn default() -> Self {
+        Oscilloscope {
+            trigger: TriggerMode::Rising,
+            level: 0.0,
+            span: 0.02,
+            frozen: false,
+            frame: Vec::new(),
+            triggered: false,
+        }
+    }
+}
+
+impl Oscilloscope {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Takes new frame from captured samples, free running when trigger isn't found
+    pub fn update(&mut self, samples: &[f32], sample_rate: u32) {
+        if self.frozen {
+            return;
+        }
+        let window = ((self.span * sample_rate as f64) as usize).clamp(2, CAPTURE_CAPACITY / 2);
+        if samples.len() < window {
+            self.frame = samples.to_vec();
+            self.triggered = false;
+            return;
+        }
+        let searched = &samples[..samples.len() - window + 1];
+        let start = find_trigger(searched, self.trigger, self.level);
+        self.triggered = start.is_some();
+        let start = start.unwrap_or(samples.len() - window);
+        self.frame = samples[start..start + window].to_vec();
+    }
+    /// Number of samples needed to find trigger for current span
+    pub fn capture_len(&self, sample_rate: u32) -> usize {
+        ((self.span * sample_rate as f64) as usize * 2).min(CAPTURE_CAPACITY)
+    }
+    pub fn frame(&self) -> &[f32] {
+        &self.frame
+    }
+    pub fn is_triggered(&self) -> bool {
+        self.triggered
+    }
+    /// Time in seconds and value of every sample in frame
+    pub fn points(&self, sample_rate: u32) -> Vec<[f64; 2]> {
+        self.frame
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| [i as f64 / sample_rate as f64, value as f64])
+            .collect()
+    }
+}
+
+/// Magnitude spectrum of the last captured samples with Hann window
+pub struct Spectrum {
+    fft: Arc<dyn Fft<f32>>,
+    window: Vec<f32>,
+    /// Share of previous levels kept in every update, smooths fluctuations
+    pub averaging: f32,
+    levels: Vec<f32>,
+}
+
+impl Default for Spectrum {
+    fn default() -> Self {
+        Self::new(DEFAULT_FFT_SIZE)
+    }
+}
+
+impl std::fmt::Debug for Spectrum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Spectrum")
+            .field("size", &self.size())
+            .field("averaging", &self.averaging)
+            .finish()
+    }
+}
+
+impl Spectrum {
+    pub fn new(size: usize) -> Self {
+        let window = (0..size)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+            .collect();
+        Spectrum {
+            fft: FftPlanner::new().plan_fft_forward(size),
+            window,
+            averaging: 0.5,
+            levels: Vec::new(),
+        }
+    }
+    pub fn size(&self) -> usize {
+        self.window.len()
+    }
+    /// Updates levels from the last `size` samples, nothing changes if there are fewer samples
+    pub fn update(&mut self, samples: &[f32]) {
+        let size = self.size();
+        if samples.len() < size {
+            return;
+        }
+        let mut buffer: Vec<Complex<f32>> = samples[samples.len() - size..]
+            .iter()
+            .zip(self.window.iter())
+            .map(|(&sample, &window)| Complex::new(sample * window, 0.0))
+            .collect();
+        self.fft.process(&mut buffer);
+        // Full scale sine has 0 dB level
+        let scale = 2.0 / self.window.iter().sum::<f32>();
+        let levels = buffer[..=size / 2]
+            .iter()
+            .map(|bin| (20.0 * (bin.norm() * scale).log10()).max(MIN_DB));
+        if self.levels.len() == size / 2 + 1 {
+            let averaging = self.averaging.clamp(0.0, 0.99);
+            for (level, new_level) in self.levels.iter_mut().zip(levels) {
+                *level = *level * averaging + new_level * (1.0 - averaging);
+            }
+        } else {
+            self.levels = levels.collect();
+        }
+    }
+    /// Levels in dB for bins from DC to Nyquist frequency
+    pub fn levels(&self) -> &[f32] {
+        &self.levels
+    }
+    /// Frequency in Hz and level in dB for every bin above DC
+    pub fn points(&self, sample_rate: u32) -> Vec<[f64; 2]> {
+        let bin_width = sample_rate as f64 / self.size() as f64;
+        self.levels
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map(|(i, &level)| [i as f64 * bin_width, level as f64])
+            .collect()
+    }
+    /// Frequency of the loudest bin above DC
+    pub fn peak(&self, sample_rate: u32) -> Option<f64> {
+        self.points(sample_rate)
+            .into_iter()
+            .max_by(|a, b| a[1].total_cmp(&b[1]))
+            .map(|point| point[0])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_capture() {
+        let buffer = CaptureBuffer::new();
+        buffer.push_frames([1.0, 0.0, 0.5, 0.5], 2);
+        assert_eq!(buffer.latest(4), vec![0.5, 0.5]);
+        buffer.set_channel(Some(1));
+        buffer.push_frames([1.0, 0.25, 1.0, 0.75], 2);
+        assert_eq!(buffer.latest(3), vec![0.5, 0.25, 0.75]);
+        buffer.reset(44100);
+        assert!(buffer.latest(1).is_empty());
+        assert_eq!(buffer.sample_rate(), 44100);
+    }
+
+    #[test]
+    fn test_trigger() {
+        let samples = [-1.0, 1.0, -1.0, 1.0, 0.5];
+        assert_eq!(find_trigger(&samples, TriggerMode::Rising, 0.0), Some(3));
+        assert_eq!(find_trigger(&samples, TriggerMode::Falling, 0.0), Some(2));
+        assert_eq!(find_trigger(&samples, TriggerMode::Rising, 2.0), None);
+        assert_eq!(find_trigger(&samples, TriggerMode::Off, 0.0), None);
+
+        let samples: Vec<f32> = (0..100).map(|i| (i % 10) as f32).collect();
+        let mut scope = Oscilloscope::new();
+        scope.span = 0.2;
+        scope.level = 4.5;
+        scope.update(&samples, 100);
+        assert!(scope.is_triggered());
+        assert_eq!(scope.frame().len(), 20);
+        assert_eq!(scope.frame()[0], 5.0);
+        assert_eq!(scope.points(100)[1], [0.01, 6.0]);
+        scope.trigger = TriggerMode::Off;
+        scope.update(&samples, 100);
+        assert!(!scope.is_triggered());
+        assert_eq!(scope.frame()[19], 9.0);
+    }
+
+    #[test]
+    fn test_spectrum() {
+        let sample_rate = 48000;
+        let mut spectrum = Spectrum::new(1024);
+        // Frequency of bin 64
+        let frequency = 3000.0;
+        let samples: Vec<f32> = (0..2048)
+            .map(|i| (2.0 * PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect();
+        spectrum.update(&samples[..100]);
+        assert!(spectrum.levels().is_empty());
+        spectrum.update(&samples);
+        assert_eq!(spectrum.levels().len(), 513);
+        assert_eq!(spectrum.peak(sample_rate), Some(frequency as f64));
+        assert!(spectrum.levels()[64].abs() < 0.1);
+        assert!(spectrum.levels()[200] < -60.0);
+    }
+}
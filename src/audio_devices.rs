@@ -1,5 +1,8 @@
+//! Audio host and device enumeration, playback streams and input capture.
 use std::collections::HashMap;
 
+use crate::audio_analysis::CaptureBuffer;
+
 use cpal::{
     traits::HostTrait,
     traits::{DeviceTrait, StreamTrait},
@@ -12,6 +15,17 @@ pub struct AudioHandler {
     pub output_devices: HashMap<HostId, Vec<Device>>,
     pub audio_loaded: bool,
     pub output_stream: Option<Stream>,
+    pub input_stream: Option<Stream>,
+    /// Number of channels in input stream
+    pub input_channels: usize,
+    /// Samples captured from input stream
+    pub capture: CaptureBuffer,
+}
+
+impl Default for AudioHandler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl AudioHandler {
@@ -22,6 +36,9 @@ impl AudioHandler {
             output_devices: HashMap::<HostId, Vec<Device>>::new(),
             audio_loaded: false,
             output_stream: None,
+            input_stream: None,
+            input_channels: 0,
+            capture: CaptureBuffer::new(),
         }
     }
     pub fn scan(&mut self) -> &mut Self {
@@ -37,7 +54,9 @@ impl AudioHandler {
                         }
                         #[cfg(feature = "jack")]
                         if host_id == cpal::HostId::Jack
-                            && device.name().unwrap() == "cpal_client_out"
+                            && device
+                                .name()
+                                .map_or(false, |name| name == "cpal_client_out")
                         {
                             continue;
                         }
@@ -51,7 +70,7 @@ impl AudioHandler {
                         }
                         #[cfg(feature = "jack")]
                         if host_id == cpal::HostId::Jack
-                            && device.name().unwrap() == "cpal_client_in"
+                            && device.name().map_or(false, |name| name == "cpal_client_in")
                         {
                             continue;
                         }
@@ -69,52 +88,88 @@ impl AudioHandler {
         maybe_host_id: Option<HostId>,
         maybe_device_id: Option<usize>,
     ) -> Result<(), anyhow::Error> {
-        if let Some(host_id) = maybe_host_id {
-            if let Some(device_id) = maybe_device_id {
-                //let err_fn = |err| print!("an error occurred on the output audio stream: {}", err);
-                let device = self
-                    .output_devices
-                    .get(&host_id)
-                    .unwrap()
-                    .get(device_id)
-                    .unwrap();
-                let mut supported_configs_range = device
-                    .supported_output_configs()
-                    .expect("error while querying configs");
-                let supported_config = supported_configs_range
-                    .next()
-                    .expect("no supported config?!")
-                    .with_max_sample_rate();
-                let sample_format = supported_config.sample_format();
-                let config = supported_config.into();
-                self.output_stream = match sample_format {
-                    cpal::SampleFormat::F32 => Some(self.run::<f32>(device, &config)),
-                    cpal::SampleFormat::I16 => Some(self.run::<i16>(device, &config)),
-                    cpal::SampleFormat::U16 => Some(self.run::<u16>(device, &config)),
-                };
-                self.output_stream.as_ref().unwrap().play()?;
-                /*
-                self.output_stream = device
-                    .build_output_stream(
-                        &config,
-                        move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                            // react to stream events and read or write stream data here.
-                            write_data(data, channels, &mut next_value)
-                        },
-                        move |_err| {
-                            // react to errors here.
-                        },
-                    )
-                    .ok();
-                     */
-            }
+        if let (Some(host_id), Some(device_id)) = (maybe_host_id, maybe_device_id) {
+            let device = self
+                .output_devices
+                .get(&host_id)
+                .and_then(|devices| devices.get(device_id))
+                .ok_or_else(|| anyhow::anyhow!("Output device not found"))?;
+            let supported_config = device
+                .supported_output_configs()?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Output device has no supported configurations"))?
+                .with_max_sample_rate();
+            let sample_format = supported_config.sample_format();
+            let config = supported_config.into();
+            let stream = match sample_format {
+                cpal::SampleFormat::F32 => self.run::<f32>(device, &config)?,
+                cpal::SampleFormat::I16 => self.run::<i16>(device, &config)?,
+                cpal::SampleFormat::U16 => self.run::<u16>(device, &config)?,
+            };
+            stream.play()?;
+            self.output_stream = Some(stream);
+        }
+        Ok(())
+    }
+
+    /// Starts capturing from input device, previous input stream is closed
+    pub fn select_input(
+        &mut self,
+        maybe_host_id: Option<HostId>,
+        maybe_device_id: Option<usize>,
+    ) -> Result<(), anyhow::Error> {
+        self.input_stream = None;
+        self.input_channels = 0;
+        if let (Some(host_id), Some(device_id)) = (maybe_host_id, maybe_device_id) {
+            let device = self
+                .input_devices
+                .get(&host_id)
+                .and_then(|devices| devices.get(device_id))
+                .ok_or_else(|| anyhow::anyhow!("Input device not found"))?;
+            let supported_config = device.default_input_config()?;
+            let sample_format = supported_config.sample_format();
+            let config: cpal::StreamConfig = supported_config.into();
+            self.capture.reset(config.sample_rate.0);
+            let stream = match sample_format {
+                cpal::SampleFormat::F32 => self.capture_input::<f32>(device, &config)?,
+                cpal::SampleFormat::I16 => self.capture_input::<i16>(device, &config)?,
+                cpal::SampleFormat::U16 => self.capture_input::<u16>(device, &config)?,
+            };
+            stream.play()?;
+            self.input_stream = Some(stream);
+            self.input_channels = config.channels as usize;
         }
         Ok(())
     }
+    pub fn input_active(&self) -> bool {
+        self.input_stream.is_some()
+    }
 
-    pub fn select_input(&mut self, _host_id: Option<HostId>, _device: Option<usize>) {}
+    fn capture_input<T>(
+        &self,
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+    ) -> Result<Stream, cpal::BuildStreamError>
+    where
+        T: cpal::Sample,
+    {
+        let capture = self.capture.clone();
+        let channels = config.channels as usize;
+        let err_fn = |err: cpal::StreamError| tracing::error!(%err, "audio input stream error");
+        device.build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                capture.push_frames(data.iter().map(|sample| sample.to_f32()), channels)
+            },
+            err_fn,
+        )
+    }
 
-    fn run<T>(&self, device: &cpal::Device, config: &cpal::StreamConfig) -> Stream
+    fn run<T>(
+        &self,
+        device: &cpal::Device,
+        config: &cpal::StreamConfig,
+    ) -> Result<Stream, cpal::BuildStreamError>
     where
         T: cpal::Sample,
     {
@@ -128,17 +183,13 @@ impl AudioHandler {
             (sample_clock * 440.0 * 2.0 * std::f32::consts::PI / sample_rate).sin()
         };
 
-        let err_fn = |err| print!("an error occurred on stream: {err}");
-
-        let stream = device
-            .build_output_stream(
-                config,
-                move |data: &mut [T], _| write_data(data, channels, &mut next_value),
-                err_fn,
-            )
-            .unwrap();
-        stream.play().unwrap();
-        stream
+        let err_fn = |err: cpal::StreamError| tracing::error!(%err, "audio stream error");
+
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _| write_data(data, channels, &mut next_value),
+            err_fn,
+        )
     }
 }
 /*
@@ -0,0 +1,515 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! Command line tool for scripting device and wavetable operations.
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
+    // Device errors and warnings go to stderr, so they don't mix with command output
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .with_max_level(tracing::Level::WARN)
+        .init();
+    if let Err(err) = cli::run(std::env::args().skip(1).collect()) {
+        eprintln!("Error: {err}");
+        std::process::exit(1);
+    }
+}
+
+// There's no command line in a browser
+#[cfg(target_arch = "wasm32")]
+fn main() {}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod cli {
+    use anyhow::{anyhow, bail, Context, Result};
+    use owl_midi::{OpenWareMidiSysexCommand, SysexConfiguration, SYSEX_CONFIGURATIONS};
+    use owl_wave::device_session::DeviceSession;
+    use owl_wave::grid::Grid;
+    use owl_wave::midi_devices::{
+        device_name, pair_owl_ports, MidiInputHandle, MidiOutputHandle, MIDI_CLIENT_NAME,
+    };
+    use owl_wave::midi_stream::MidiFrameSender;
+    use owl_wave::midi_transport::MidiTransport;
+    use owl_wave::owl_control::command_processor::{DeviceIdentity, OwlCommandProcessor};
+    use owl_wave::owl_control::requests::RequestState;
+    use owl_wave::owl_control::resources::{
+        resource_from_header, Resource, ResourceState, RESOURCE_NAME_SIZE,
+    };
+    use owl_wave::owl_control::settings::{
+        self, setting_code, setting_info, setting_name, SettingsEdits, SettingsProfile,
+    };
+    use owl_wave::project::{Project, PROJECT_EXTENSION};
+    use std::io::Cursor;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+    use wavetable::WavHandler;
+
+    const USAGE: &str = "\
+Usage: owl-cli [--port <name> | --emulator] <command>
+
+Commands:
+    list-ports                   List MIDI input and output ports
+    info                         Show connected device and its firmware version
+    list-patches                 List patches stored on device
+    list-resources               List resources stored on device
+    download <slot> [file]       Save patch or resource from slot to a file
+    upload <file> <slot>         Store file as a resource in slot
+    erase <slot> | erase all     Erase a single slot or all patches and resources
+    settings get [name]          Show device settings, name is a code like FS or full name
+    settings set <name> <value>  Change device setting
+    settings store               Save settings on device
+    settings export <file>       Save device settings to a profile
+    settings diff <file>         Show settings that profile would change
+    settings import <file>       Send settings that differ from profile, use store to keep them
+    convert <input> <output>     Convert between WAV and project files
+
+Device is the first connected OWL unless --port selects a device by name.";
+
+    /// Seconds to wait for device to start responding
+    const RESPONSE_TIMEOUT: f64 = 5.0;
+    /// Seconds to wait for a patch or resource download to complete
+    const DOWNLOAD_TIMEOUT: f64 = 60.0;
+    const POLL_INTERVAL: Duration = Duration::from_millis(10);
+    /// Grid size used for wavetable conversion, same as in the editor
+    const GRID_ROWS: usize = 8;
+    const GRID_COLS: usize = 8;
+    const GRID_SAMPLES: usize = 256;
+
+    pub fn run(args: Vec<String>) -> Result<()> {
+        let mut port = None;
+        let mut emulator = false;
+        let mut command = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--port" => port = Some(args.next().ok_or_else(|| anyhow!("--port needs a name"))?),
+                "--emulator" => emulator = true,
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    return Ok(());
+                }
+                _ => command.push(arg),
+            }
+        }
+        let connect = || Device::connect(port.as_deref(), emulator);
+        let command: Vec<&str> = command.iter().map(String::as_str).collect();
+        match command.as_slice() {
+            ["list-ports"] => list_ports(),
+            ["info"] => connect()?.info(),
+            ["list-patches"] => connect()?.list_patches(),
+            ["list-resources"] => connect()?.list_resources(),
+            ["download", slot] => connect()?.download(parse_slot(slot)?, None),
+            ["download", slot, file] => connect()?.download(parse_slot(slot)?, Some(*file)),
+            ["upload", file, slot] => connect()?.upload(file, parse_slot(slot)?),
+            ["erase", "all"] => connect()?.erase(None),
+            ["erase", slot] => connect()?.erase(Some(parse_slot(slot)?)),
+            ["settings", "get"] => connect()?.get_settings(None),
+            ["settings", "get", name] => connect()?.get_settings(Some(find_setting(name)?)),
+            ["settings", "set", name, value] => {
+                let config = find_setting(name)?;
+                let info = setting_info(config);
+                let value = info
+                    .parse(value)
+                    .map_err(|err| anyhow!("Invalid value for {name}: {err}"))?;
+                // Devices can accept values that schema doesn't know about
+                if let Err(err) = info.validate(value) {
+                    eprintln!("Warning: {name}: {err}");
+                }
+                connect()?.set_setting(config, value)
+            }
+            ["settings", "store"] => connect()?.store_settings(),
+            ["settings", "export", file] => connect()?.export_settings(file),
+            ["settings", "diff", file] => connect()?.import_settings(file, false),
+            ["settings", "import", file] => connect()?.import_settings(file, true),
+            ["convert", input, output] => convert(input, output),
+            _ => bail!("Invalid command\n\n{USAGE}"),
+        }
+    }
+
+    /// Slots are device slot numbers, same as ids shown by list-patches and list-resources
+    fn parse_slot(arg: &str) -> Result<u32> {
+        // Slots are sent as 32 bit numbers and download sends slot + 1
+        match arg.parse::<u32>() {
+            Ok(slot) if slot < u32::MAX => Ok(slot),
+            _ => bail!("Invalid slot {arg}"),
+        }
+    }
+
+    fn find_setting(name: &str) -> Result<SysexConfiguration> {
+        settings::find_setting(name).ok_or_else(|| anyhow!("Unknown setting {name}"))
+    }
+
+    fn list_ports() -> Result<()> {
+        println!("Inputs:");
+        for (i, name) in MidiInputHandle::<MidiFrameSender>::scan(MIDI_CLIENT_NAME)
+            .iter()
+            .enumerate()
+        {
+            println!("{i:>3}. {name}");
+        }
+        println!("Outputs:");
+        for (i, name) in MidiOutputHandle::scan(MIDI_CLIENT_NAME).iter().enumerate() {
+            println!("{i:>3}. {name}");
+        }
+        Ok(())
+    }
+
+    fn is_project(path: &str) -> bool {
+        Path::new(path)
+            .extension()
+            .map_or(false, |extension| extension == PROJECT_EXTENSION)
+    }
+
+    fn convert(input: &str, output: &str) -> Result<()> {
+        let data = std::fs::read(input).with_context(|| format!("Can't read {input}"))?;
+        let (grid, active_wave_id) = if is_project(input) {
+            let project =
+                Project::from_json(&data).with_context(|| format!("Can't open {input}"))?;
+            (project.to_grid(), project.active_wave_id)
+        } else {
+            let wav_content = WavHandler::read_content(Cursor::new(&data))
+                .map_err(|_| anyhow!("{input} is not a valid WAV file"))?;
+            let mut grid = Grid::new(GRID_ROWS, GRID_COLS, GRID_SAMPLES);
+            if grid.load_waves(&wav_content) == 0 {
+                bail!("{input} doesn't contain any waves");
+            }
+            (grid, 0)
+        };
+        let data = if is_project(output) {
+            Project::new(&grid, active_wave_id).to_json()?
+        } else {
+            grid.to_wav()
+        };
+        std::fs::write(output, data).with_context(|| format!("Can't write {output}"))
+    }
+
+    /// Prints entries with device slot numbers, `offset` is added to ids of relative entries
+    fn print_entries(entries: &[Option<Resource>], offset: usize) {
+        for entry in entries.iter().flatten() {
+            println!(
+                "{:>3}. {:<24} {:>10} {:08X}",
+                entry.id as usize + offset,
+                entry.name,
+                entry.size_string(),
+                entry.checksum
+            );
+        }
+    }
+
+    /// Device slot of a listed resource, resource ids are relative to first resource slot
+    fn resource_slot(processor: &OwlCommandProcessor, resource: &Resource) -> u32 {
+        (resource.id as usize + processor.resource_offset) as u32
+    }
+
+    struct Device {
+        session: DeviceSession,
+        start: Instant,
+    }
+
+    impl Device {
+        fn connect(port: Option<&str>, emulator: bool) -> Result<Self> {
+            let session = if emulator {
+                DeviceSession::emulator()
+            } else {
+                let inputs = MidiInputHandle::<MidiFrameSender>::scan(MIDI_CLIENT_NAME);
+                let outputs = MidiOutputHandle::scan(MIDI_CLIENT_NAME);
+                let (i, j) = match port {
+                    Some(name) => {
+                        let i = inputs
+                            .iter()
+                            .position(|input| input.contains(name))
+                            .ok_or_else(|| anyhow!("No input port matches {name}"))?;
+                        let j = outputs
+                            .iter()
+                            .position(|output| device_name(output) == device_name(&inputs[i]))
+                            .or_else(|| outputs.iter().position(|output| output.contains(name)))
+                            .ok_or_else(|| anyhow!("No output port matches {name}"))?;
+                        (i, j)
+                    }
+                    None => pair_owl_ports(&inputs, &outputs)
+                        .first()
+                        .copied()
+                        .ok_or_else(|| anyhow!("No OWL device found"))?,
+                };
+                DeviceSession::new(i, Some(inputs[i].clone()), j, Some(outputs[j].clone()))
+            };
+            if !session.is_connected() {
+                bail!("Can't connect to {}", session.name());
+            }
+            Ok(Device {
+                session,
+                start: Instant::now(),
+            })
+        }
+        fn now(&self) -> f64 {
+            self.start.elapsed().as_secs_f64()
+        }
+        fn processor(&self) -> &OwlCommandProcessor {
+            &self.session.owl_command_processor
+        }
+        // Runs processor command with output connection
+        fn command<F>(&mut self, command: F) -> Result<()>
+        where
+            F: FnOnce(
+                &mut OwlCommandProcessor,
+                &mut dyn MidiTransport,
+            ) -> Result<(), Box<anyhow::Error>>,
+        {
+            let connection = self
+                .session
+                .midi_output
+                .connection
+                .as_mut()
+                .ok_or_else(|| anyhow!("Device is not connected"))?;
+            command(&mut self.session.owl_command_processor, connection).map_err(|err| *err)
+        }
+        /// Handles received messages until condition is met
+        fn wait_until<F>(&mut self, timeout: f64, mut done: F) -> Result<()>
+        where
+            F: FnMut(&OwlCommandProcessor) -> bool,
+        {
+            let deadline = self.now() + timeout;
+            loop {
+                self.session.process_midi(self.now());
+                if done(self.processor()) {
+                    return Ok(());
+                }
+                if self.now() > deadline {
+                    bail!("Device is not responding");
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        }
+        /// Sends request and waits until all responses are received
+        fn request(&mut self, command: OpenWareMidiSysexCommand) -> Result<()> {
+            self.command(|processor, connection| processor.request_settings(connection, command))?;
+            // Request tracker retries and times out on its own, deadline is only a safeguard
+            self.wait_until(RESPONSE_TIMEOUT, |processor| {
+                !matches!(
+                    processor.requests.state(command),
+                    Some(RequestState::Pending | RequestState::Receiving)
+                )
+            })?;
+            if self.processor().requests.state(command) == Some(RequestState::TimedOut) {
+                bail!("{command:?}: device not responding");
+            }
+            Ok(())
+        }
+        fn info(&mut self) -> Result<()> {
+            self.command(|processor, connection| processor.identify(connection))?;
+            self.wait_until(RESPONSE_TIMEOUT, |processor| {
+                processor.identity() != DeviceIdentity::Probing
+            })?;
+            println!("Device: {}", self.session.name());
+            match self.processor().identity() {
+                DeviceIdentity::Owl(firmware_version) => println!("Firmware: {firmware_version}"),
+                _ => bail!("Device didn't report firmware version"),
+            }
+            Ok(())
+        }
+        fn list_patches(&mut self) -> Result<()> {
+            self.request(OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND)?;
+            // First entry is the patch that is currently loaded
+            print_entries(self.processor().patches.get(1..).unwrap_or_default(), 0);
+            Ok(())
+        }
+        fn list_resources(&mut self) -> Result<()> {
+            self.request(OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND)?;
+            // Resource ids are relative to first resource slot
+            let processor = self.processor();
+            print_entries(&processor.resources, processor.resource_offset);
+            Ok(())
+        }
+        fn download(&mut self, slot: u32, file: Option<&str>) -> Result<()> {
+            self.command(|processor, connection| processor.request_download(connection, slot))?;
+            self.wait_until(RESPONSE_TIMEOUT, |processor| {
+                processor.resource_data.state != ResourceState::New
+            })?;
+            self.wait_until(DOWNLOAD_TIMEOUT, |processor| {
+                !matches!(
+                    processor.resource_data.state,
+                    ResourceState::InProgress | ResourceState::Complete
+                )
+            })?;
+            let resource_data = &self.processor().resource_data;
+            match resource_data.state {
+                ResourceState::Success => {}
+                ResourceState::InvalidChecksum => bail!("Downloaded data has invalid checksum"),
+                _ => bail!("Download failed"),
+            }
+            let (name, data) = match resource_from_header(&resource_data.data) {
+                Some((name, data)) => (name, data),
+                None => (String::new(), resource_data.data.as_slice()),
+            };
+            // Name reported by device is only used as a file name in current directory
+            let name = Path::new(&name)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| format!("slot-{slot}.bin"));
+            let path = file.unwrap_or(&name);
+            std::fs::write(path, data).with_context(|| format!("Can't write {path}"))?;
+            println!("Saved {} bytes to {path}", data.len());
+            Ok(())
+        }
+        fn upload(&mut self, file: &str, slot: u32) -> Result<()> {
+            let data = std::fs::read(file).with_context(|| format!("Can't read {file}"))?;
+            let name = Path::new(file)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            self.command(|processor, connection| {
+                processor.store_resource(connection, &name, &data, slot)
+            })?;
+            // Device doesn't acknowledge storing, so check that resource list contains it
+            self.request(OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND)?;
+            let processor = self.processor();
+            let stored = processor
+                .resources
+                .iter()
+                .flatten()
+                .find(|resource| resource_slot(processor, resource) == slot);
+            let resource =
+                stored.ok_or_else(|| anyhow!("Device didn't store {name} in slot {slot}"))?;
+            // Header only has room for a truncated name
+            let header_name = name.get(..RESOURCE_NAME_SIZE - 1).unwrap_or(&name);
+            if resource.name != header_name || resource.size as usize != data.len() {
+                bail!(
+                    "Slot {slot} contains {} ({} bytes) after storing {name}",
+                    resource.name,
+                    resource.size
+                );
+            }
+            println!("Stored {name} ({} bytes) in slot {slot}", data.len());
+            Ok(())
+        }
+        fn erase(&mut self, slot: Option<u32>) -> Result<()> {
+            self.command(|processor, connection| processor.erase(connection, slot))?;
+            // Check that erased entries are no longer listed
+            self.request(OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND)?;
+            self.request(OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND)?;
+            let processor = self.processor();
+            // First patch entry is the patch that is currently loaded
+            let mut patches = processor
+                .patches
+                .get(1..)
+                .unwrap_or_default()
+                .iter()
+                .flatten();
+            let mut resources = processor.resources.iter().flatten();
+            match slot {
+                Some(slot)
+                    if patches.any(|patch| patch.id as u32 == slot)
+                        || resources.any(|resource| resource_slot(processor, resource) == slot) =>
+                {
+                    bail!("Device didn't erase slot {slot}")
+                }
+                None if patches.next().is_some() || resources.next().is_some() => {
+                    bail!("Device didn't erase all slots")
+                }
+                _ => Ok(()),
+            }
+        }
+        fn get_settings(&mut self, config: Option<SysexConfiguration>) -> Result<()> {
+            self.request(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND)?;
+            let settings = &self.processor().settings;
+            for &entry in SYSEX_CONFIGURATIONS.iter() {
+                if config.map_or(true, |config| config == entry) {
+                    if let Some(&value) = settings.get(&entry) {
+                        println!(
+                            "{} {:<40} {}",
+                            setting_code(entry),
+                            setting_name(entry),
+                            setting_info(entry).format(value)
+                        );
+                    }
+                }
+            }
+            Ok(())
+        }
+        fn set_setting(&mut self, config: SysexConfiguration, value: i64) -> Result<()> {
+            self.command(|processor, connection| {
+                processor.send_setting(connection, config, value)
+            })?;
+            self.confirm_settings(&[(config, value)])
+        }
+        /// Reads settings back from device and fails if any differ from expected values
+        fn confirm_settings(&mut self, expected: &[(SysexConfiguration, i64)]) -> Result<()> {
+            // Sent values are stored locally, forget them so only reported values are checked
+            let settings = &mut self.session.owl_command_processor.settings;
+            for (config, _) in expected.iter() {
+                settings.remove(config);
+            }
+            self.request(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND)?;
+            let settings = &self.processor().settings;
+            for &(config, value) in expected.iter() {
+                let info = setting_info(config);
+                match settings.get(&config) {
+                    Some(&reported) if reported == value => {}
+                    Some(&reported) => bail!(
+                        "{} is {} after setting it to {}",
+                        setting_name(config),
+                        info.format(reported),
+                        info.format(value)
+                    ),
+                    None => bail!("Device didn't report {}", setting_name(config)),
+                }
+            }
+            Ok(())
+        }
+        fn export_settings(&mut self, file: &str) -> Result<()> {
+            self.request(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND)?;
+            let profile = SettingsProfile::new(
+                Some(self.session.name().to_string()),
+                &self.processor().settings,
+            );
+            std::fs::write(file, profile.to_json()?).with_context(|| format!("Can't write {file}"))
+        }
+        /// Prints settings that differ from profile and sends them if `apply` is set
+        fn import_settings(&mut self, file: &str, apply: bool) -> Result<()> {
+            let data = std::fs::read(file).with_context(|| format!("Can't read {file}"))?;
+            let profile =
+                SettingsProfile::from_json(&data).with_context(|| format!("Can't open {file}"))?;
+            self.request(OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND)?;
+            let device = &self.processor().settings;
+            let mut edits = SettingsEdits::new();
+            edits.load_profile(device, &profile)?;
+            let changes = edits.changes(device);
+            for change in changes.iter() {
+                let info = setting_info(change.config);
+                println!(
+                    "{} {:<40} {} -> {}",
+                    setting_code(change.config),
+                    setting_name(change.config),
+                    change
+                        .device_value
+                        .map_or("?".to_string(), |value| info.format(value)),
+                    info.format(change.value)
+                );
+                if let Err(err) = change.validate() {
+                    eprintln!("Warning: {}: {err}", setting_name(change.config));
+                }
+            }
+            if changes.is_empty() {
+                println!("No changes");
+            } else if apply {
+                for change in changes.iter() {
+                    self.command(|processor, connection| {
+                        processor.send_setting(connection, change.config, change.value)
+                    })?;
+                }
+                let expected: Vec<_> = changes
+                    .iter()
+                    .map(|change| (change.config, change.value))
+                    .collect();
+                self.confirm_settings(&expected)?;
+            }
+            Ok(())
+        }
+        fn store_settings(&mut self) -> Result<()> {
+            self.command(|processor, connection| {
+                processor
+                    .send_sysex_command(connection, OpenWareMidiSysexCommand::SYSEX_SETTINGS_STORE)
+            })
+        }
+    }
+}
@@ -0,0 +1,338 @@
+//! Connection to a single device: MIDI ports, received message handling and command processor.
+use crate::midi_devices::{
+    device_name, is_owl_port, pair_owl_ports, MidiInputHandle, MidiOutputHandle, MIDI_CLIENT_NAME,
+};
+use crate::midi_monitor::{Direction, MidiMonitor, MonitorEntry};
+use crate::midi_stream::{MidiFrame, MidiFrameSender};
+use crate::midi_transport::MidiTransport;
+use crate::owl_control::command_processor::OwlCommandProcessor;
+use crate::owl_control::emulator::{OwlEmulator, EMULATOR_NAME};
+use crate::owl_control::program_history::ProgramHistory;
+use crate::owl_control::program_stats::StatsMonitor;
+use crate::owl_control::settings::SettingsEdits;
+use crate::sysex_librarian::SysexCapture;
+use anyhow::Error;
+use owl_midi::{OpenWareMidiSysexCommand, SysexConfiguration};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use tracing::{error, info_span, warn, Span};
+use wmidi::{MidiMessage, U7};
+
+/// Connection to a single device with its own command processor and state
+pub struct DeviceSession {
+    pub midi_input: MidiInputHandle<MidiFrameSender>,
+    pub midi_output: MidiOutputHandle,
+    pub owl_command_processor: OwlCommandProcessor,
+    /// Settings changed in UI that haven't been sent yet
+    pub settings_edits: SettingsEdits,
+    /// Messages sent to and received from device
+    pub monitor: MidiMonitor,
+    /// Received sysex saved by librarian
+    pub sysex_capture: SysexCapture,
+    /// Messages and errors from running patch
+    pub program_history: ProgramHistory,
+    /// CPU load and memory usage of running patch
+    pub program_stats: StatsMonitor,
+    midi_sender: Sender<MidiFrame>,
+    midi_receiver: Receiver<MidiFrame>,
+    /// Copies of messages delivered to output
+    monitor_sender: Sender<MidiFrame>,
+    monitor_receiver: Receiver<MidiFrame>,
+    /// Transport doesn't use MIDI ports, so it's never rescanned or reconnected
+    virtual_device: bool,
+}
+
+impl DeviceSession {
+    pub fn new(
+        input_port: usize,
+        input_name: Option<String>,
+        output_port: usize,
+        output_name: Option<String>,
+    ) -> Self {
+        let (midi_sender, midi_receiver) = channel();
+        let (monitor_sender, monitor_receiver) = channel();
+        let midi_input = Self::connect_input(input_port, input_name, midi_sender.clone());
+        let mut session = DeviceSession {
+            midi_input,
+            midi_output: Self::connect_output(output_port, output_name),
+            owl_command_processor: OwlCommandProcessor::new(),
+            settings_edits: SettingsEdits::new(),
+            monitor: MidiMonitor::new(),
+            sysex_capture: SysexCapture::new(),
+            program_history: ProgramHistory::new(),
+            program_stats: StatsMonitor::new(),
+            midi_sender,
+            midi_receiver,
+            monitor_sender,
+            monitor_receiver,
+            virtual_device: false,
+        };
+        session.midi_output.monitor(session.monitor_sender.clone());
+        session.identify();
+        session
+    }
+    /// Session with a virtual device, transport is created with sender for its responses
+    pub fn with_transport<F>(name: &str, transport: F) -> Self
+    where
+        F: FnOnce(Sender<MidiFrame>) -> Box<dyn MidiTransport>,
+    {
+        let (midi_sender, midi_receiver) = channel();
+        let (monitor_sender, monitor_receiver) = channel();
+        let mut session = DeviceSession {
+            midi_input: MidiInputHandle::disconnected(),
+            midi_output: MidiOutputHandle::virtual_port(name, transport(midi_sender.clone())),
+            owl_command_processor: OwlCommandProcessor::new(),
+            settings_edits: SettingsEdits::new(),
+            monitor: MidiMonitor::new(),
+            sysex_capture: SysexCapture::new(),
+            program_history: ProgramHistory::new(),
+            program_stats: StatsMonitor::new(),
+            midi_sender,
+            midi_receiver,
+            monitor_sender,
+            monitor_receiver,
+            virtual_device: true,
+        };
+        session.midi_output.monitor(session.monitor_sender.clone());
+        session.identify();
+        session
+    }
+    /// Session connected to emulated device instead of MIDI ports
+    pub fn emulator() -> Self {
+        Self::with_transport(EMULATOR_NAME, |sender| Box::new(OwlEmulator::new(sender)))
+    }
+    fn connect_input(
+        port: usize,
+        name: Option<String>,
+        sender: Sender<MidiFrame>,
+    ) -> MidiInputHandle<MidiFrameSender> {
+        // Selection is kept on failure, so the port is connected when rescan finds it
+        MidiInputHandle::new(
+            MIDI_CLIENT_NAME,
+            port,
+            name.clone(),
+            |stamp, message, frame_sender| frame_sender.receive(stamp, message),
+            MidiFrameSender::new(sender),
+        )
+        .unwrap_or_else(|err| {
+            error!(%err);
+            MidiInputHandle {
+                selected_port: port,
+                selected_name: name,
+                ..MidiInputHandle::disconnected()
+            }
+        })
+    }
+    fn connect_output(port: usize, name: Option<String>) -> MidiOutputHandle {
+        MidiOutputHandle::new(MIDI_CLIENT_NAME, port, name.clone()).unwrap_or_else(|err| {
+            error!(%err);
+            MidiOutputHandle {
+                selected_port: port,
+                selected_name: name,
+                ..MidiOutputHandle::disconnected()
+            }
+        })
+    }
+    /// Device name shown in device list
+    pub fn name(&self) -> &str {
+        self.midi_output
+            .selected_name
+            .as_deref()
+            .or(self.midi_input.selected_name.as_deref())
+            .map_or("No device", device_name)
+    }
+    /// Span that adds device name to logged events
+    pub fn span(&self) -> Span {
+        info_span!("device", name = self.name())
+    }
+    pub fn is_connected(&self) -> bool {
+        self.midi_output.connection.is_some()
+    }
+    pub fn update_midi_input(&mut self) {
+        if self.virtual_device {
+            return;
+        }
+        self.midi_input = Self::connect_input(
+            self.midi_input.selected_port,
+            self.midi_input.selected_name.clone(),
+            self.midi_sender.clone(),
+        );
+    }
+    pub fn update_midi_output(&mut self) {
+        if self.virtual_device {
+            return;
+        }
+        self.midi_output = Self::connect_output(
+            self.midi_output.selected_port,
+            self.midi_output.selected_name.clone(),
+        );
+        self.midi_output.monitor(self.monitor_sender.clone());
+        self.identify();
+    }
+    // Confirms that we're talking to an OWL
+    fn identify(&mut self) {
+        let is_owl = self
+            .midi_output
+            .selected_name
+            .as_deref()
+            .map_or(false, is_owl_port);
+        if is_owl {
+            self.with_connection(|processor, connection| processor.identify(connection));
+        }
+    }
+    // Runs processor command if output is connected, errors are logged
+    fn with_connection<F>(&mut self, command: F)
+    where
+        F: FnOnce(&mut OwlCommandProcessor, &mut dyn MidiTransport) -> Result<(), Box<Error>>,
+    {
+        let _span = self.span().entered();
+        if let Some(connection) = &mut self.midi_output.connection {
+            if let Err(err) = command(&mut self.owl_command_processor, connection) {
+                error!(%err);
+            }
+        }
+    }
+    /// Reconnects ports if list of available ports has changed, returns true on reconnection
+    pub fn rescan(&mut self) -> bool {
+        if self.virtual_device {
+            return false;
+        }
+        let input_changed =
+            MidiInputHandle::<MidiFrameSender>::scan(MIDI_CLIENT_NAME) != self.midi_input.names;
+        let output_changed = MidiOutputHandle::scan(MIDI_CLIENT_NAME) != self.midi_output.names;
+        if input_changed {
+            self.update_midi_input();
+        }
+        if output_changed {
+            self.update_midi_output();
+        }
+        input_changed || output_changed
+    }
+    /// Connects input and output ports of the same OWL device.
+    ///
+    /// Keeps selected input if `input_selected` is true, selected output if it's false.
+    /// Without selection, first OWL device is used unless current ports already match.
+    pub fn pair_ports(&mut self, input_selected: Option<bool>) {
+        if self.virtual_device {
+            return;
+        }
+        let pairs = pair_owl_ports(&self.midi_input.names, &self.midi_output.names);
+        let input_pair = pairs.iter().find(|(i, _)| {
+            self.midi_input.connection.is_some() && *i == self.midi_input.selected_port
+        });
+        let output_pair = pairs.iter().find(|(_, j)| {
+            self.midi_output.connection.is_some() && *j == self.midi_output.selected_port
+        });
+        let pair = match input_selected {
+            Some(true) => input_pair,
+            Some(false) => output_pair,
+            None if input_pair.is_some() && input_pair == output_pair => None,
+            None => input_pair.or(output_pair).or_else(|| pairs.first()),
+        };
+        if let Some(&(i, j)) = pair {
+            if i != self.midi_input.selected_port || self.midi_input.connection.is_none() {
+                self.midi_input.selected_port = i;
+                self.midi_input.selected_name = self.midi_input.names.get(i).cloned();
+                self.update_midi_input();
+            }
+            if j != self.midi_output.selected_port || self.midi_output.connection.is_none() {
+                self.midi_output.selected_port = j;
+                self.midi_output.selected_name = self.midi_output.names.get(j).cloned();
+                self.update_midi_output();
+            }
+        }
+    }
+    /// Handles received MIDI messages, request timeouts and stats polling
+    pub fn process_midi(&mut self, now: f64) {
+        let _span = self.span().entered();
+        if let Some(connection) = &mut self.midi_output.connection {
+            self.owl_command_processor.poll_requests(connection, now);
+            if self.program_stats.poll(now) {
+                let command = OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS;
+                if let Err(err) = self
+                    .owl_command_processor
+                    .poll_settings(connection, command)
+                {
+                    error!(%err);
+                }
+            }
+        }
+        for frame in self.monitor_receiver.try_iter() {
+            self.monitor.record(MonitorEntry {
+                time: now,
+                timestamp: None,
+                direction: Direction::Out,
+                data: frame.data,
+            });
+        }
+        while let Ok(frame) = self.midi_receiver.try_recv() {
+            self.sysex_capture.record(&frame.data);
+            self.program_history.record(now, &frame.data);
+            self.program_stats.record(now, &frame.data);
+            self.monitor.record(MonitorEntry {
+                time: now,
+                timestamp: Some(frame.timestamp),
+                direction: Direction::In,
+                data: frame.data.clone(),
+            });
+            match MidiMessage::try_from(frame.data.as_slice()) {
+                Ok(MidiMessage::SysEx(data)) => {
+                    if let Err(err) = self.owl_command_processor.handle_sysex(data) {
+                        warn!(%err, data = ?U7::data_to_bytes(data), "malformed OWL sysex");
+                    }
+                }
+                Ok(message) => self.owl_command_processor.handle_midi_message(message),
+                Err(err) => {
+                    warn!(?err, data = ?frame.data, "invalid MIDI message");
+                }
+            }
+        }
+    }
+    pub fn request_settings(&mut self, command: OpenWareMidiSysexCommand) {
+        self.with_connection(|processor, connection| {
+            processor.request_settings(connection, command)
+        });
+    }
+    pub fn send_sysex_command(&mut self, command: OpenWareMidiSysexCommand) {
+        self.with_connection(|processor, connection| {
+            processor.send_sysex_command(connection, command)
+        });
+    }
+    pub fn send_sysex_string(&mut self, command: OpenWareMidiSysexCommand, string: &[u8]) {
+        self.with_connection(|processor, connection| {
+            processor.send_sysex_string(connection, command, string)
+        });
+    }
+    pub fn send_setting(&mut self, config: SysexConfiguration, value: i64) {
+        self.with_connection(|processor, connection| {
+            processor.send_setting(connection, config, value)
+        });
+    }
+    pub fn send_raw(&mut self, data: &[u8]) {
+        self.with_connection(|processor, connection| processor.send_raw(connection, data));
+    }
+    pub fn send_message(&mut self, message: MidiMessage<'_>) {
+        self.with_connection(|processor, connection| processor.send_message(connection, message));
+    }
+    pub fn set_button(&mut self, id: u8, pressed: bool) {
+        self.with_connection(|processor, connection| processor.set_button(connection, id, pressed));
+    }
+    pub fn load_patch(&mut self, slot: u8) {
+        self.with_connection(|processor, connection| processor.load_patch(connection, slot));
+    }
+    /// Erases a single slot, or everything if slot is not set
+    pub fn erase(&mut self, slot: Option<u32>) {
+        self.with_connection(|processor, connection| processor.erase(connection, slot));
+    }
+    pub fn request_download(&mut self, slot: u32) {
+        self.with_connection(|processor, connection| processor.request_download(connection, slot));
+    }
+    pub fn sync_parameters(&mut self) {
+        self.with_connection(|processor, connection| processor.sync_parameters(connection));
+    }
+    /// Uploads resource to the next free resource slot
+    pub fn send_resource(&mut self, name: &str, data: &[u8]) {
+        self.with_connection(|processor, connection| {
+            processor.send_resource(connection, name, data)
+        });
+    }
+}
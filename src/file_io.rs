@@ -0,0 +1,140 @@
+//! File pickers and downloads that work both natively and in the browser.
+//!
+//! Browser gives file contents asynchronously, so opened files are delivered through a channel
+//! and handled by the UI on the next frame. Saving shows a dialog natively and downloads a file
+//! on web.
+use crate::owl_control::settings::PROFILE_EXTENSION;
+use crate::project::PROJECT_EXTENSION;
+use crate::sysex_librarian::SYSEX_EXTENSION;
+#[cfg(target_arch = "wasm32")]
+use anyhow::anyhow;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// What an opened file should be used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    Wavetable,
+    Project,
+    /// Resource uploaded to all connected devices
+    Resource,
+    /// Settings profile applied to active device
+    Settings,
+    /// MIDI file with parameter automation
+    Automation,
+    /// Sysex messages loaded into librarian, several files can be picked at once
+    Sysex,
+}
+
+impl FileKind {
+    fn filter(&self) -> Option<(&'static str, &'static [&'static str])> {
+        match self {
+            FileKind::Wavetable => Some(("WAV", &["wav"])),
+            FileKind::Project => Some(("OWL wave project", &[PROJECT_EXTENSION])),
+            FileKind::Resource => None,
+            FileKind::Settings => Some(("Settings profile", &[PROFILE_EXTENSION])),
+            FileKind::Automation => Some(("MIDI file", &["mid", "midi"])),
+            FileKind::Sysex => Some(("SysEx", &[SYSEX_EXTENSION])),
+        }
+    }
+    fn multiple(&self) -> bool {
+        *self == FileKind::Sysex
+    }
+}
+
+pub struct OpenedFile {
+    pub kind: FileKind,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+pub struct FileDialogs {
+    sender: Sender<OpenedFile>,
+    receiver: Receiver<OpenedFile>,
+}
+
+impl FileDialogs {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        FileDialogs { sender, receiver }
+    }
+    /// Asks user to pick a file, or several for some kinds, contents are returned by `poll`
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn open(&self, _ctx: &egui::Context, kind: FileKind) {
+        let mut dialog = rfd::FileDialog::new();
+        if let Some((name, extensions)) = kind.filter() {
+            dialog = dialog.add_filter(name, extensions);
+        }
+        let paths = if kind.multiple() {
+            dialog.pick_files().unwrap_or_default()
+        } else {
+            dialog.pick_file().into_iter().collect()
+        };
+        for path in paths {
+            if let Ok(data) = std::fs::read(&path) {
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let _ = self.sender.send(OpenedFile { kind, name, data });
+            }
+        }
+    }
+    /// Asks user to pick a file, or several for some kinds, contents are returned by `poll`
+    #[cfg(target_arch = "wasm32")]
+    pub fn open(&self, ctx: &egui::Context, kind: FileKind) {
+        let mut dialog = rfd::AsyncFileDialog::new();
+        if let Some((name, extensions)) = kind.filter() {
+            dialog = dialog.add_filter(name, extensions);
+        }
+        let sender = self.sender.clone();
+        let ctx = ctx.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let files = if kind.multiple() {
+                dialog.pick_files().await.unwrap_or_default()
+            } else {
+                dialog.pick_file().await.into_iter().collect()
+            };
+            for file in files {
+                let data = file.read().await;
+                let name = file.file_name();
+                let _ = sender.send(OpenedFile { kind, name, data });
+                ctx.request_repaint();
+            }
+        });
+    }
+    /// Returns next file that was opened
+    pub fn poll(&self) -> Option<OpenedFile> {
+        self.receiver.try_recv().ok()
+    }
+}
+
+/// Saves data to a file chosen by user
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_file(name: &str, data: &[u8]) -> anyhow::Result<()> {
+    if let Some(path) = rfd::FileDialog::new().set_file_name(name).save_file() {
+        std::fs::write(path, data)?;
+    }
+    Ok(())
+}
+
+/// Downloads data as a file, browser decides where to store it
+#[cfg(target_arch = "wasm32")]
+pub fn save_file(name: &str, data: &[u8]) -> anyhow::Result<()> {
+    use wasm_bindgen::{JsCast, JsValue};
+
+    let js_error = |err: JsValue| anyhow!("{err:?}");
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(data));
+    let blob = web_sys::Blob::new_with_u8_array_sequence(&parts).map_err(js_error)?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(js_error)?;
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(|| anyhow!("No document"))?;
+    let link: web_sys::HtmlAnchorElement = document
+        .create_element("a")
+        .map_err(js_error)?
+        .unchecked_into();
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    web_sys::Url::revoke_object_url(&url).map_err(js_error)
+}
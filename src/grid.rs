@@ -1,4 +1,5 @@
-use std::{cmp::min, sync::Arc};
+//! Grid of single cycle waves that make up a wavetable, with their spectrum and WAV export.
+use std::{cmp::min, iter::repeat, sync::Arc};
 
 use wavetable::{Harmonic, WavData, Wavetable, WtReader};
 
@@ -15,7 +16,7 @@ impl Grid {
         let mut wavetable = Wavetable::new(rows * cols, 1, samples);
         for i in 0..rows {
             for j in 0..cols {
-                let wave = wavetable.get_wave_mut(i * rows + j);
+                let wave = wavetable.get_wave_mut(i * cols + j);
                 Wavetable::add_sine_wave(wave, 1.0, 1.0, 0.0);
             }
         }
@@ -28,6 +29,18 @@ impl Grid {
             spectrum: Arc::from(spectrum),
         }
     }
+    /// Creates grid with given waves, short or missing waves are filled with silence
+    pub fn from_waves(rows: usize, cols: usize, samples: usize, waves: &[Vec<f64>]) -> Self {
+        let mut grid = Grid::new(rows, cols, samples);
+        for i in 0..grid.get_waves() {
+            let wave = grid.wavetable.get_wave_mut(i);
+            wave.clear();
+            let source = waves.get(i).map_or(&[][..], |source| source.as_slice());
+            wave.extend(source.iter().copied().chain(repeat(0.0)).take(samples));
+        }
+        grid.update_harmonics();
+        grid
+    }
     fn update_harmonics(&mut self) -> &mut Self {
         let spectrum = self.wavetable.get_freq_spectrum();
         self.spectrum = Arc::from(spectrum);
@@ -66,7 +79,8 @@ impl Grid {
     pub fn get_cols(&self) -> usize {
         self.cols
     }
-    pub fn load_waves(&mut self, wav_data: &WavData) -> Result<usize, ()> {
+    /// Loads waves from WAV data, returns number of loaded waves or 0 if data can't be used
+    pub fn load_waves(&mut self, wav_data: &WavData) -> usize {
         let result = WtReader::create_wavetable(wav_data, None);
         if let Ok(wt_ref) = result {
             let num_tables = min(self.get_waves(), wt_ref.num_samples / self.samples);
@@ -84,9 +98,96 @@ impl Grid {
                 self.wavetable.get_wave_mut(i).clear()
             }
             self.update_harmonics();
-            Ok(num_tables)
+            num_tables
         } else {
-            Ok(0)
+            0
+        }
+    }
+    /// Replaces consecutive waves from `start`, returns number of replaced waves.
+    ///
+    /// Waves that don't fit in grid are ignored, shorter waves are padded with silence.
+    pub fn set_waves(&mut self, start: usize, waves: &[Vec<f64>]) -> usize {
+        let count = min(waves.len(), self.get_waves().saturating_sub(start));
+        for (i, source) in waves.iter().take(count).enumerate() {
+            let wave = self.wavetable.get_wave_mut(start + i);
+            wave.clear();
+            wave.extend(source.iter().copied().chain(repeat(0.0)).take(self.samples));
         }
+        self.update_harmonics();
+        count
+    }
+    /// Encodes all waves as a single WAV file, empty waves are filled with silence
+    pub fn to_wav(&self) -> Vec<u8> {
+        let samples: Vec<f64> = (0..self.get_waves())
+            .flat_map(|i| {
+                self.get_wave_by_id(i)
+                    .iter()
+                    .copied()
+                    .chain(repeat(0.0))
+                    .take(self.samples)
+            })
+            .collect();
+        write_wav(&samples, WAV_SAMPLE_RATE)
+    }
+}
+
+/// Sample rate stored in exported WAV files, it doesn't affect wavetable playback
+pub const WAV_SAMPLE_RATE: u32 = 48000;
+
+/// Writes mono 16 bit PCM WAV file
+pub fn write_wav(samples: &[f64], sample_rate: u32) -> Vec<u8> {
+    let data_size = samples.len() as u32 * 2;
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM format, 1 channel
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&sample_rate.to_le_bytes());
+    wav.extend_from_slice(&(sample_rate * 2).to_le_bytes());
+    // Block align and bits per sample
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f64).round() as i16;
+        wav.extend_from_slice(&value.to_le_bytes());
+    }
+    wav
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_wav() {
+        let wav = write_wav(&[0.0, 1.0, -1.0, 2.0], 48000);
+        assert_eq!(wav.len(), 44 + 8);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(u32::from_le_bytes(wav[4..8].try_into().unwrap()), 44);
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 8);
+        assert_eq!(
+            wav[44..]
+                .chunks(2)
+                .map(|x| i16::from_le_bytes([x[0], x[1]]))
+                .collect::<Vec<_>>(),
+            vec![0, i16::MAX, -i16::MAX, i16::MAX]
+        );
+    }
+
+    #[test]
+    fn test_set_waves() {
+        let mut grid = Grid::new(2, 2, 4);
+        let waves = [vec![1.0; 4], vec![0.5; 2], vec![0.0; 4]];
+        assert_eq!(grid.set_waves(2, &waves), 2);
+        assert_eq!(grid.get_wave_by_id(2), &vec![1.0; 4]);
+        assert_eq!(grid.get_wave_by_id(3), &vec![0.5, 0.5, 0.0, 0.0]);
+        assert_eq!(grid.set_waves(4, &waves), 0);
     }
 }
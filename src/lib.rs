@@ -1,10 +1,33 @@
 #![warn(clippy::all, rust_2018_idioms)]
 
+//! Wavetable editor and librarian for OWL devices.
+//!
+//! The library can be used without the UI:
+//! - [`grid`] and [`project`] load, edit and save wavetables
+//! - [`device_session`] connects to a device over MIDI and [`owl_control`] implements its
+//!   protocol, while [`midi_transport`] allows replacing MIDI ports with other transports
+//!
+//! [`OwlWaveApp`] is the egui application built on top of these modules.
+
 mod app;
 pub use app::OwlWaveApp;
-mod audio_devices;
-mod grid;
-mod midi_devices;
-mod owl_control;
-mod wave;
+pub mod audio_analysis;
+pub mod audio_devices;
+pub mod device_session;
+mod file_io;
+pub mod grid;
+pub mod log_view;
+pub mod midi_devices;
+pub mod midi_file;
+pub mod midi_monitor;
+pub mod midi_stream;
+pub mod midi_transport;
+mod notification;
+pub mod owl_control;
+pub mod project;
+pub mod sysex_librarian;
+pub mod wave;
+pub mod wave_capture;
+#[cfg(target_arch = "wasm32")]
+mod web_midi;
 //mod effects;
@@ -0,0 +1,344 @@
+//! Records collected from `tracing` events by a layer, with level and text filters for log view.
+use std::collections::VecDeque;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Default number of records kept in log, older ones are dropped
+pub const DEFAULT_CAPACITY: usize = 5000;
+
+pub const LEVELS: [Level; 5] = [
+    Level::ERROR,
+    Level::WARN,
+    Level::INFO,
+    Level::DEBUG,
+    Level::TRACE,
+];
+
+/// Events shown in log view: everything from this crate down to debug, only warnings from others
+pub fn default_targets() -> Targets {
+    Targets::new()
+        .with_target(env!("CARGO_CRATE_NAME"), Level::DEBUG)
+        .with_default(Level::WARN)
+}
+
+/// Seconds since Unix epoch
+#[cfg(not(target_arch = "wasm32"))]
+fn now() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0.0, |time| time.as_secs_f64())
+}
+
+#[cfg(target_arch = "wasm32")]
+fn now() -> f64 {
+    js_sys::Date::now() / 1000.0
+}
+
+/// Formats event or span fields as `name=value` pairs, message is kept separately
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record_debug(field, &format_args!("{value}"))
+    }
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{value:?}");
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={value:?}", field.name());
+        }
+    }
+}
+
+/// Formatted fields stored in span extensions
+struct SpanFields(String);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogRecord {
+    /// Seconds since Unix epoch
+    pub time: f64,
+    pub level: Level,
+    pub target: String,
+    /// Enclosing spans from outermost one, e.g. `device{name=OWL}:request{command=...}`
+    pub spans: String,
+    pub message: String,
+    pub fields: String,
+}
+
+impl LogRecord {
+    /// Spans, message and fields without time and level
+    pub fn describe(&self) -> String {
+        let mut text = String::new();
+        if !self.spans.is_empty() {
+            text += self.spans.as_str();
+            text += ": ";
+        }
+        text += self.message.as_str();
+        if !self.message.is_empty() && !self.fields.is_empty() {
+            text.push(' ');
+        }
+        text += self.fields.as_str();
+        text
+    }
+    /// Time of day in UTC
+    pub fn time_of_day(&self) -> String {
+        let millis = (self.time * 1000.0) as u64 % (24 * 3600 * 1000);
+        format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000
+        )
+    }
+}
+
+impl fmt::Display for LogRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {:>5} {}",
+            self.time_of_day(),
+            self.level,
+            self.describe()
+        )
+    }
+}
+
+/// Shows records at given level or more severe that contain text
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogFilter {
+    pub level: Level,
+    pub text: String,
+}
+
+impl Default for LogFilter {
+    fn default() -> Self {
+        LogFilter {
+            level: Level::INFO,
+            text: String::new(),
+        }
+    }
+}
+
+impl LogFilter {
+    pub fn matches(&self, record: &LogRecord) -> bool {
+        record.level <= self.level
+            && (self.text.is_empty()
+                || [
+                    &record.target,
+                    &record.spans,
+                    &record.message,
+                    &record.fields,
+                ]
+                .iter()
+                .any(|text| text.contains(self.text.as_str())))
+    }
+}
+
+#[derive(Debug)]
+struct LogRecords {
+    records: VecDeque<LogRecord>,
+    capacity: usize,
+    dropped: usize,
+}
+
+/// Last log records, shared between tracing layer and log view
+#[derive(Clone, Debug)]
+pub struct LogBuffer {
+    inner: Arc<Mutex<LogRecords>>,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+impl LogBuffer {
+    pub fn new(capacity: usize) -> Self {
+        LogBuffer {
+            inner: Arc::new(Mutex::new(LogRecords {
+                records: VecDeque::new(),
+                capacity,
+                dropped: 0,
+            })),
+        }
+    }
+    // Records stay usable if a thread panicked while logging
+    fn lock(&self) -> MutexGuard<'_, LogRecords> {
+        self.inner
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    pub fn push(&self, record: LogRecord) {
+        let mut inner = self.lock();
+        while inner.records.len() >= inner.capacity.max(1) {
+            inner.records.pop_front();
+            inner.dropped += 1;
+        }
+        inner.records.push_back(record);
+    }
+    pub fn clear(&self) {
+        let mut inner = self.lock();
+        inner.records.clear();
+        inner.dropped = 0;
+    }
+    pub fn len(&self) -> usize {
+        self.lock().records.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lock().records.is_empty()
+    }
+    /// Number of records dropped since log was cleared
+    pub fn dropped(&self) -> usize {
+        self.lock().dropped
+    }
+    /// Copies of records that match filter.
+    ///
+    /// Records are copied so that the lock isn't held while they're shown, as that could log too.
+    pub fn filtered(&self, filter: &LogFilter) -> Vec<LogRecord> {
+        self.lock()
+            .records
+            .iter()
+            .filter(|record| filter.matches(record))
+            .cloned()
+            .collect()
+    }
+    /// Records that match filter, one per line
+    pub fn to_text(&self, filter: &LogFilter) -> String {
+        self.filtered(filter)
+            .iter()
+            .map(|record| format!("{record}\n"))
+            .collect()
+    }
+}
+
+/// Tracing layer that stores events with their spans in a log buffer
+pub struct LogLayer {
+    buffer: LogBuffer,
+}
+
+impl LogLayer {
+    pub fn new(buffer: LogBuffer) -> Self {
+        LogLayer { buffer }
+    }
+}
+
+impl<S> Layer<S> for LogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut visitor = FieldVisitor::default();
+            attrs.record(&mut visitor);
+            span.extensions_mut().insert(SpanFields(visitor.fields));
+        }
+    }
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut extensions = span.extensions_mut();
+            if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+                let mut visitor = FieldVisitor {
+                    message: String::new(),
+                    fields: std::mem::take(fields),
+                };
+                values.record(&mut visitor);
+                *fields = visitor.fields;
+            }
+        }
+    }
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let mut spans = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if !spans.is_empty() {
+                    spans.push(':');
+                }
+                spans += span.name();
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        let _ = write!(spans, "{{{fields}}}");
+                    }
+                }
+            }
+        }
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let metadata = event.metadata();
+        self.buffer.push(LogRecord {
+            time: now(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            spans,
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_layer() {
+        let buffer = LogBuffer::new(2);
+        let subscriber = tracing_subscriber::registry().with(LogLayer::new(buffer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("dropped");
+            let span = tracing::info_span!("device", name = "OWL", slot = tracing::field::Empty);
+            let _guard = span.enter();
+            span.record("slot", 3);
+            tracing::warn!(count = 2, "retrying");
+            tracing::debug!(reply = ?Some(1));
+        });
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.dropped(), 1);
+        let records = buffer.filtered(&LogFilter::default());
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].describe(),
+            "device{name=OWL slot=3}: retrying count=2"
+        );
+        let filter = LogFilter {
+            level: Level::TRACE,
+            text: "reply".to_string(),
+        };
+        assert_eq!(
+            buffer.filtered(&filter)[0].describe(),
+            "device{name=OWL slot=3}: reply=Some(1)"
+        );
+        buffer.clear();
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        let record = LogRecord {
+            time: 86400.0 * 2.0 + 3723.5,
+            level: Level::INFO,
+            target: String::new(),
+            spans: String::new(),
+            message: "Message".to_string(),
+            fields: String::new(),
+        };
+        assert_eq!(record.to_string(), "01:02:03.500  INFO Message");
+    }
+}
@@ -4,33 +4,51 @@
 // When compiling natively:
 #[cfg(not(target_arch = "wasm32"))]
 fn main() {
-    // Log to stdout (if you run with `RUST_LOG=debug`).
-    tracing_subscriber::fmt::init();
+    use tracing_subscriber::{filter::LevelFilter, prelude::*};
+
+    // Log to stdout and to log view
+    let log = owl_wave::log_view::LogBuffer::default();
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_filter(LevelFilter::INFO))
+        .with(
+            owl_wave::log_view::LogLayer::new(log.clone())
+                .with_filter(owl_wave::log_view::default_targets()),
+        )
+        .init();
 
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "OWL Wave",
         native_options,
-        Box::new(|cc| Box::new(owl_wave::OwlWaveApp::new(cc))),
+        Box::new(move |cc| Box::new(owl_wave::OwlWaveApp::new(cc, log))),
     );
 }
 
 // when compiling to web using trunk.
 #[cfg(target_arch = "wasm32")]
 fn main() {
+    use tracing_subscriber::prelude::*;
+
     // Make sure panics are logged using `console.error`.
     console_error_panic_hook::set_once();
 
-    // Redirect tracing to console.log and friends:
-    tracing_wasm::set_as_global_default();
+    // Redirect tracing to console.log and friends, and to log view
+    let log = owl_wave::log_view::LogBuffer::default();
+    tracing_subscriber::registry()
+        .with(tracing_wasm::WASMLayer::default())
+        .with(
+            owl_wave::log_view::LogLayer::new(log.clone())
+                .with_filter(owl_wave::log_view::default_targets()),
+        )
+        .init();
 
     let web_options = eframe::WebOptions::default();
 
-    wasm_bindgen_futures::spawn_local(async {
+    wasm_bindgen_futures::spawn_local(async move {
         eframe::start_web(
             "the_canvas_id", // hardcode it
             web_options,
-            Box::new(|cc| Box::new(owl_wave::OwlWaveApp::new(cc))),
+            Box::new(move |cc| Box::new(owl_wave::OwlWaveApp::new(cc, log))),
         )
         .await
         .expect("failed to start eframe");
@@ -1,8 +1,21 @@
-use midir::{
-    MidiInput, MidiInputConnection, MidiInputPorts, MidiOutput, MidiOutputConnection,
-    MidiOutputPorts,
+//! MIDI port listing and connections, with helpers for finding OWL devices.
+use crate::midi_stream::{MidiFrame, MidiFrameSender};
+use crate::midi_transport::{MidiTransport, MonitorTransport, SharedTransport};
+use anyhow::{anyhow, Error};
+#[cfg(not(target_arch = "wasm32"))]
+use midir::{MidiInput, MidiInputConnection, MidiInputPorts, MidiOutput, MidiOutputPorts};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[cfg(target_arch = "wasm32")]
+use crate::web_midi::{
+    MidiInput, MidiInputConnection, MidiInputPorts, MidiOutput, MidiOutputPorts,
 };
 
+/// Client name for our MIDI connections, ports with this name are not listed
+pub const MIDI_CLIENT_NAME: &str = "OWL wave";
+/// Seconds between checks for added or removed MIDI ports
+pub const MIDI_RESCAN_INTERVAL: f64 = 2.0;
+
 #[derive(PartialEq, Eq)]
 #[cfg_attr(feature = "persistence", derive(serde::Deserialize, serde::Serialize))]
 pub enum MidiDeviceSelection {
@@ -14,8 +27,54 @@ impl MidiDeviceSelection {
     pub fn show_midi_device(&self, name: &str) -> bool {
         match self {
             MidiDeviceSelection::All => true,
-            MidiDeviceSelection::Owl => name.starts_with("OWL-"),
+            MidiDeviceSelection::Owl => is_owl_port(name),
+        }
+    }
+}
+
+/// Returns device part of a port name, it's shared by input and output ports of the same device.
+///
+/// ALSA port names look like "OWL-MAGUS:OWL-MAGUS MIDI 1 24:0", other backends use device name.
+pub fn device_name(port_name: &str) -> &str {
+    port_name.split(':').next().unwrap_or(port_name).trim()
+}
+
+// Ports created by our own connections show up on some backends
+fn is_own_port(port_name: &str) -> bool {
+    device_name(port_name) == MIDI_CLIENT_NAME
+}
+
+pub fn is_owl_port(port_name: &str) -> bool {
+    device_name(port_name).contains("OWL-")
+}
+
+/// Finds matching (input, output) port indices for every OWL device.
+///
+/// Units of the same model share device name, n-th input of a device is paired with its n-th output.
+pub fn pair_owl_ports(inputs: &[String], outputs: &[String]) -> Vec<(usize, usize)> {
+    let mut used = vec![false; outputs.len()];
+    inputs
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| is_owl_port(name))
+        .filter_map(|(i, input_name)| {
+            let j = (0..outputs.len())
+                .find(|&j| !used[j] && device_name(&outputs[j]) == device_name(input_name))?;
+            used[j] = true;
+            Some((i, j))
+        })
+        .collect()
+}
+
+// Port to connect to: by name if it was selected before, otherwise by index.
+// Index is kept if its port has the selected name, units of the same model share names.
+fn find_port(names: &[String], selected_name: Option<&str>, selected_port: usize) -> Option<usize> {
+    match selected_name {
+        Some(name) if names.get(selected_port).map(String::as_str) == Some(name) => {
+            Some(selected_port)
         }
+        Some(name) => names.iter().position(|n| n == name),
+        None => (selected_port < names.len()).then_some(selected_port),
     }
 }
 
@@ -24,73 +83,299 @@ pub struct MidiInputHandle<T: 'static> {
     pub ports: MidiInputPorts,
     pub names: Vec<String>,
     pub selected_port: usize,
+    /// Port name used for reconnecting after ports were added or removed
+    pub selected_name: Option<String>,
 }
 
 impl<T> MidiInputHandle<T> {
-    pub fn new<F>(name: &str, selected_port: usize, callback: F, data: T) -> Self
+    pub fn new<F>(
+        name: &str,
+        selected_port: usize,
+        selected_name: Option<String>,
+        callback: F,
+        data: T,
+    ) -> Result<Self, Error>
     where
         T: Send + 'static,
         F: FnMut(u64, &[u8], &mut T) + Send + 'static,
     {
-        let source = MidiInput::new(name).unwrap();
+        let source =
+            MidiInput::new(name).map_err(|err| anyhow!("Can't create MIDI input: {err}"))?;
         let mut ports = MidiInputPorts::new();
         let mut names = Vec::<String>::new();
         for p in source.ports().iter() {
             if let Ok(port_name) = source.port_name(p) {
-                names.push(port_name);
-                ports.push(p.clone());
+                if !is_own_port(&port_name) {
+                    names.push(port_name);
+                    ports.push(p.clone());
+                }
             }
         }
         let mut connection = None;
-        if !ports.is_empty() {
-            connection = source
-                .connect(&ports[selected_port], name, callback, data)
-                .ok();
+        let port = find_port(&names, selected_name.as_deref(), selected_port);
+        if let Some(i) = port {
+            connection = source.connect(&ports[i], name, callback, data).ok();
         }
 
-        MidiInputHandle {
+        Ok(MidiInputHandle {
             connection,
             ports,
+            selected_port: port.unwrap_or(selected_port),
+            selected_name: port.map(|i| names[i].clone()).or(selected_name),
             names,
-            selected_port,
+        })
+    }
+    /// Handle without a port, used when messages come from a virtual device
+    pub fn disconnected() -> Self {
+        MidiInputHandle {
+            connection: None,
+            ports: MidiInputPorts::new(),
+            names: Vec::new(),
+            selected_port: 0,
+            selected_name: None,
         }
     }
     pub fn get_selected_port_mut(&mut self) -> &mut usize {
         &mut self.selected_port
     }
+    /// Lists names of currently available input ports
+    pub fn scan(name: &str) -> Vec<String> {
+        MidiInput::new(name)
+            .map(|source| {
+                source
+                    .ports()
+                    .iter()
+                    .filter_map(|p| source.port_name(p).ok())
+                    .filter(|port_name| !is_own_port(port_name))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
 }
 
 pub struct MidiOutputHandle {
-    pub connection: Option<MidiOutputConnection>,
+    pub connection: Option<SharedTransport>,
     pub ports: MidiOutputPorts,
     pub names: Vec<String>,
     pub selected_port: usize,
+    /// Port name used for reconnecting after ports were added or removed
+    pub selected_name: Option<String>,
 }
 
 impl MidiOutputHandle {
-    pub fn new(name: &str, selected_port: usize) -> Self {
-        let source = MidiOutput::new(name).unwrap();
+    pub fn new(
+        name: &str,
+        selected_port: usize,
+        selected_name: Option<String>,
+    ) -> Result<Self, Error> {
+        let source =
+            MidiOutput::new(name).map_err(|err| anyhow!("Can't create MIDI output: {err}"))?;
         let mut ports = MidiOutputPorts::new();
         let mut names = Vec::<String>::new();
         for p in source.ports().iter() {
             if let Ok(port_name) = source.port_name(p) {
-                names.push(port_name);
-                ports.push(p.clone());
+                if !is_own_port(&port_name) {
+                    names.push(port_name);
+                    ports.push(p.clone());
+                }
             }
         }
         let mut connection = None;
-        if !ports.is_empty() {
-            connection = source.connect(&ports[selected_port], name).ok();
+        let port = find_port(&names, selected_name.as_deref(), selected_port);
+        if let Some(i) = port {
+            if let Ok(port_connection) = source.connect(&ports[i], name) {
+                connection = Some(SharedTransport::new(Box::new(port_connection)));
+            }
         }
 
-        MidiOutputHandle {
+        Ok(MidiOutputHandle {
             connection,
             ports,
+            selected_port: port.unwrap_or(selected_port),
+            selected_name: port.map(|i| names[i].clone()).or(selected_name),
             names,
-            selected_port,
+        })
+    }
+    /// Handle without a port
+    pub fn disconnected() -> Self {
+        MidiOutputHandle {
+            connection: None,
+            ports: MidiOutputPorts::new(),
+            names: Vec::new(),
+            selected_port: 0,
+            selected_name: None,
+        }
+    }
+    /// Handle that sends messages to a virtual device instead of a port
+    pub fn virtual_port(name: &str, transport: Box<dyn MidiTransport>) -> Self {
+        MidiOutputHandle {
+            connection: Some(SharedTransport::new(transport)),
+            ports: MidiOutputPorts::new(),
+            names: Vec::new(),
+            selected_port: 0,
+            selected_name: Some(name.to_string()),
         }
     }
     pub fn get_selected_port_mut(&mut self) -> &mut usize {
         &mut self.selected_port
     }
+    /// Sends a copy of every delivered message to monitor
+    pub fn monitor(&mut self, sender: Sender<MidiFrame>) {
+        if let Some(connection) = self.connection.take() {
+            let transport = MonitorTransport::new(connection, sender);
+            self.connection = Some(SharedTransport::new(Box::new(transport)));
+        }
+    }
+    /// Lists names of currently available output ports
+    pub fn scan(name: &str) -> Vec<String> {
+        MidiOutput::new(name)
+            .map(|source| {
+                source
+                    .ports()
+                    .iter()
+                    .filter_map(|p| source.port_name(p).ok())
+                    .filter(|port_name| !is_own_port(port_name))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+struct ControllerPort {
+    name: String,
+    _connection: MidiInputConnection<MidiFrameSender>,
+    receiver: Receiver<MidiFrame>,
+}
+
+/// Connections to input ports that aren't used by a device session, for external controllers
+#[derive(Default)]
+pub struct ControllerInputs {
+    ports: Vec<ControllerPort>,
+    /// All available input ports when connections were made
+    pub names: Vec<String>,
+    /// Ports that connections were requested for, `None` for all ports
+    pub requested: Option<Vec<String>>,
+}
+
+impl ControllerInputs {
+    /// Connects to requested input ports, or all of them if `requested` is `None`.
+    ///
+    /// OWL devices and ports named in `exclude` are skipped. `wakeup` is called from MIDI thread
+    /// when messages are received.
+    pub fn new<F>(name: &str, exclude: &[String], requested: Option<Vec<String>>, wakeup: F) -> Self
+    where
+        F: Fn() + Clone + Send + 'static,
+    {
+        let names = MidiInputHandle::<MidiFrameSender>::scan(name);
+        let mut ports = Vec::new();
+        for port_name in names.iter().filter(|port_name| {
+            !is_owl_port(port_name)
+                && !exclude.contains(port_name)
+                && requested
+                    .as_ref()
+                    .map_or(true, |requested| requested.contains(port_name))
+        }) {
+            // Connecting consumes MIDI client, so every port needs its own
+            let source = match MidiInput::new(name) {
+                Ok(source) => source,
+                Err(_) => continue,
+            };
+            let port = source
+                .ports()
+                .into_iter()
+                .find(|p| source.port_name(p).ok().as_ref() == Some(port_name));
+            if let Some(port) = port {
+                let (sender, receiver) = channel();
+                let connection = source.connect(
+                    &port,
+                    name,
+                    |stamp, message, frame_sender: &mut MidiFrameSender| {
+                        frame_sender.receive(stamp, message)
+                    },
+                    MidiFrameSender::with_wakeup(sender, wakeup.clone()),
+                );
+                if let Ok(connection) = connection {
+                    ports.push(ControllerPort {
+                        name: port_name.clone(),
+                        _connection: connection,
+                        receiver,
+                    });
+                }
+            }
+        }
+        ControllerInputs {
+            ports,
+            names,
+            requested,
+        }
+    }
+    /// True if input ports were added or removed since connecting
+    pub fn ports_changed(&self, name: &str) -> bool {
+        MidiInputHandle::<MidiFrameSender>::scan(name) != self.names
+    }
+    /// Names of connected ports
+    pub fn port_names(&self) -> impl Iterator<Item = &str> {
+        self.ports.iter().map(|port| port.name.as_str())
+    }
+    /// Messages received since last call with names of their ports
+    pub fn receive(&self) -> Vec<(&str, MidiFrame)> {
+        self.ports
+            .iter()
+            .flat_map(|port| {
+                port.receiver
+                    .try_iter()
+                    .map(move |frame| (port.name.as_str(), frame))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_device_name() {
+        assert_eq!(device_name("OWL-MAGUS:OWL-MAGUS MIDI 1 24:0"), "OWL-MAGUS");
+        assert_eq!(device_name("OWL-WITCH"), "OWL-WITCH");
+        assert!(is_owl_port("2- OWL-LICH"));
+        assert!(!is_owl_port("Midi Through:Midi Through Port-0 14:0"));
+    }
+
+    #[test]
+    fn test_pair_owl_ports() {
+        let inputs = names(&[
+            "Midi Through:Midi Through Port-0 14:0",
+            "OWL-WITCH:OWL-WITCH MIDI 1 20:0",
+            "OWL-MAGUS:OWL-MAGUS MIDI 1 24:0",
+        ]);
+        let outputs = names(&[
+            "Midi Through:Midi Through Port-0 14:0",
+            "OWL-MAGUS:OWL-MAGUS MIDI 1 24:0",
+            "OWL-WITCH:OWL-WITCH MIDI 1 20:0",
+        ]);
+        assert_eq!(pair_owl_ports(&inputs, &outputs), vec![(1, 2), (2, 1)]);
+        assert_eq!(pair_owl_ports(&inputs, &outputs[..2]), vec![(2, 1)]);
+        // Two units of the same model
+        let inputs = names(&["OWL-MAGUS", "OWL-MAGUS"]);
+        let outputs = names(&["OWL-MAGUS", "OWL-MAGUS"]);
+        assert_eq!(pair_owl_ports(&inputs, &outputs), vec![(0, 0), (1, 1)]);
+        assert_eq!(pair_owl_ports(&inputs, &outputs[..1]), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn test_find_port() {
+        let ports = names(&["A", "OWL-MAGUS"]);
+        assert_eq!(find_port(&ports, Some("OWL-MAGUS"), 0), Some(1));
+        assert_eq!(find_port(&ports, Some("OWL-WITCH"), 0), None);
+        assert_eq!(find_port(&ports, None, 1), Some(1));
+        assert_eq!(find_port(&ports, None, 2), None);
+        let ports = names(&["OWL-MAGUS", "OWL-MAGUS"]);
+        assert_eq!(find_port(&ports, Some("OWL-MAGUS"), 1), Some(1));
+        assert_eq!(find_port(&ports, Some("OWL-MAGUS"), 2), Some(0));
+    }
 }
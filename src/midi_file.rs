@@ -0,0 +1,323 @@
+//! Minimal Standard MIDI File reading and writing for timestamped channel messages.
+use std::fmt;
+
+/// Resolution used for written files
+pub const TICKS_PER_QUARTER: u16 = 480;
+/// Tempo used for written files, in microseconds per quarter note (120 BPM)
+pub const DEFAULT_TEMPO: u32 = 500_000;
+
+/// Channel message at a time in seconds from start of file
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimedMessage {
+    pub time: f64,
+    pub data: Vec<u8>,
+}
+
+/// Errors returned when a MIDI file can't be read
+#[derive(Debug, PartialEq, Eq)]
+pub enum MidiFileError {
+    InvalidHeader,
+    UnexpectedEnd,
+    UnsupportedDivision,
+    InvalidEvent(u8),
+}
+
+impl fmt::Display for MidiFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MidiFileError::InvalidHeader => write!(f, "Not a MIDI file"),
+            MidiFileError::UnexpectedEnd => write!(f, "File is truncated"),
+            MidiFileError::UnsupportedDivision => write!(f, "Unsupported time division"),
+            MidiFileError::InvalidEvent(status) => write!(f, "Invalid event 0x{status:02x}"),
+        }
+    }
+}
+
+impl std::error::Error for MidiFileError {}
+
+fn write_variable_length(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+/// Writes a single track file, messages must be complete channel messages
+pub fn write_smf(messages: &[TimedMessage], length: f64) -> Vec<u8> {
+    let ticks_per_second = TICKS_PER_QUARTER as f64 * 1_000_000.0 / DEFAULT_TEMPO as f64;
+    let to_ticks = |time: f64| (time.max(0.0) * ticks_per_second).round() as u32;
+    let mut sorted: Vec<&TimedMessage> = messages.iter().collect();
+    sorted.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+    let mut track = Vec::new();
+    write_variable_length(&mut track, 0);
+    track.extend([0xff, 0x51, 0x03]);
+    track.extend(&DEFAULT_TEMPO.to_be_bytes()[1..]);
+    let mut last_tick = 0;
+    for message in sorted {
+        let tick = to_ticks(message.time);
+        write_variable_length(&mut track, tick - last_tick);
+        track.extend(&message.data);
+        last_tick = tick;
+    }
+    let end = to_ticks(length).max(last_tick);
+    write_variable_length(&mut track, end - last_tick);
+    track.extend([0xff, 0x2f, 0x00]);
+
+    let mut data = Vec::with_capacity(track.len() + 22);
+    data.extend(b"MThd");
+    data.extend(6u32.to_be_bytes());
+    data.extend(0u16.to_be_bytes());
+    data.extend(1u16.to_be_bytes());
+    data.extend(TICKS_PER_QUARTER.to_be_bytes());
+    data.extend(b"MTrk");
+    data.extend((track.len() as u32).to_be_bytes());
+    data.extend(track);
+    data
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], MidiFileError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(MidiFileError::UnexpectedEnd)?;
+        self.pos += len;
+        Ok(bytes)
+    }
+    fn byte(&mut self) -> Result<u8, MidiFileError> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u32(&mut self) -> Result<u32, MidiFileError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+    fn variable_length(&mut self) -> Result<u32, MidiFileError> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.byte()?;
+            value = value << 7 | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(MidiFileError::InvalidHeader)
+    }
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+}
+
+enum Event {
+    Message(Vec<u8>),
+    Tempo(u32),
+    Other,
+}
+
+// Events from all tracks with their absolute tick, end of the longest track is returned as well
+fn read_tracks(
+    reader: &mut Reader,
+    tracks: u16,
+) -> Result<(Vec<(u64, Event)>, u64), MidiFileError> {
+    let mut events = Vec::new();
+    let mut end = 0;
+    for _ in 0..tracks {
+        if reader.is_empty() {
+            break;
+        }
+        let id = reader.bytes(4)?;
+        let len = reader.u32()? as usize;
+        let chunk = reader.bytes(len)?;
+        if id != b"MTrk" {
+            continue;
+        }
+        let mut track = Reader {
+            data: chunk,
+            pos: 0,
+        };
+        let mut tick = 0u64;
+        let mut running_status = None;
+        while !track.is_empty() {
+            tick += track.variable_length()? as u64;
+            let mut status = track.byte()?;
+            if status < 0x80 {
+                // Running status, this byte is already data
+                status = running_status.ok_or(MidiFileError::InvalidEvent(status))?;
+                track.pos -= 1;
+            }
+            let event = match status {
+                0xff => {
+                    let kind = track.byte()?;
+                    let len = track.variable_length()? as usize;
+                    let data = track.bytes(len)?;
+                    match (kind, data) {
+                        (0x51, [a, b, c]) => Event::Tempo(u32::from_be_bytes([0, *a, *b, *c])),
+                        _ => Event::Other,
+                    }
+                }
+                0xf0 | 0xf7 => {
+                    let len = track.variable_length()? as usize;
+                    track.bytes(len)?;
+                    Event::Other
+                }
+                0x80..=0xef => {
+                    running_status = Some(status);
+                    let len = if matches!(status & 0xf0, 0xc0 | 0xd0) {
+                        1
+                    } else {
+                        2
+                    };
+                    let data = track.bytes(len)?;
+                    if let Some(&byte) = data.iter().find(|&&byte| byte >= 0x80) {
+                        return Err(MidiFileError::InvalidEvent(byte));
+                    }
+                    let mut message = vec![status];
+                    message.extend(data);
+                    Event::Message(message)
+                }
+                _ => return Err(MidiFileError::InvalidEvent(status)),
+            };
+            events.push((tick, event));
+        }
+        end = end.max(tick);
+    }
+    // Tempo changes apply to all tracks, stable sort keeps order within a tick
+    events.sort_by_key(|(tick, _)| *tick);
+    Ok((events, end))
+}
+
+/// Reads channel messages from all tracks and file length in seconds, tempo changes are applied
+pub fn read_smf(data: &[u8]) -> Result<(Vec<TimedMessage>, f64), MidiFileError> {
+    let mut reader = Reader { data, pos: 0 };
+    if reader.bytes(4).map_err(|_| MidiFileError::InvalidHeader)? != b"MThd" {
+        return Err(MidiFileError::InvalidHeader);
+    }
+    let header_len = reader.u32()? as usize;
+    let header = reader.bytes(header_len)?;
+    if header.len() < 6 {
+        return Err(MidiFileError::InvalidHeader);
+    }
+    let tracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = u16::from_be_bytes([header[4], header[5]]);
+    // Seconds per tick, either from tempo or from SMPTE frame rate
+    let smpte = division & 0x8000 != 0;
+    let seconds_per_tick = |tempo: u32| {
+        if smpte {
+            let fps = -((division >> 8) as i8) as f64;
+            let ticks_per_frame = (division & 0xff) as f64;
+            1.0 / (fps * ticks_per_frame)
+        } else {
+            tempo as f64 / 1_000_000.0 / division as f64
+        }
+    };
+    if division == 0 || (smpte && (division & 0xff == 0 || (division >> 8) as i8 >= 0)) {
+        return Err(MidiFileError::UnsupportedDivision);
+    }
+
+    let (events, end) = read_tracks(&mut reader, tracks)?;
+    let mut messages = Vec::new();
+    let mut tempo = DEFAULT_TEMPO;
+    let mut last_tick = 0;
+    let mut time = 0.0;
+    for (tick, event) in events {
+        time += (tick - last_tick) as f64 * seconds_per_tick(tempo);
+        last_tick = tick;
+        match event {
+            Event::Message(data) => messages.push(TimedMessage { time, data }),
+            Event::Tempo(new_tempo) => tempo = new_tempo,
+            Event::Other => {}
+        }
+    }
+    let length = time + (end.max(last_tick) - last_tick) as f64 * seconds_per_tick(tempo);
+    Ok((messages, length))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_variable_length() {
+        for (value, expected) in [
+            (0, vec![0x00]),
+            (0x7f, vec![0x7f]),
+            (0x80, vec![0x81, 0x00]),
+            (0x3fff, vec![0xff, 0x7f]),
+            (0x0fff_ffff, vec![0xff, 0xff, 0xff, 0x7f]),
+        ] {
+            let mut out = Vec::new();
+            write_variable_length(&mut out, value);
+            assert_eq!(out, expected);
+            let mut reader = Reader { data: &out, pos: 0 };
+            assert_eq!(reader.variable_length(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let messages = vec![
+            TimedMessage {
+                time: 0.5,
+                data: vec![0xb0, 20, 64],
+            },
+            TimedMessage {
+                time: 0.0,
+                data: vec![0xc0, 3],
+            },
+        ];
+        let data = write_smf(&messages, 2.0);
+        let (read, length) = read_smf(&data).unwrap();
+        assert_eq!(read, vec![messages[1].clone(), messages[0].clone()]);
+        assert_eq!(length, 2.0);
+    }
+
+    #[test]
+    fn test_tempo_and_running_status() {
+        // Format 1: tempo track at 60 BPM, second track with CCs using running status
+        let mut data = b"MThd\x00\x00\x00\x06\x00\x01\x00\x02\x00\x60".to_vec();
+        let tempo: &[u8] = &[
+            0x00, 0xff, 0x51, 0x03, 0x0f, 0x42, 0x40, 0x00, 0xff, 0x2f, 0x00,
+        ];
+        data.extend(b"MTrk");
+        data.extend((tempo.len() as u32).to_be_bytes());
+        data.extend(tempo);
+        let track: &[u8] = &[
+            0x60, 0xb1, 20, 1, 0x60, 20, 2, 0x00, 0xf0, 0x02, 0x7e, 0xf7, 0x00, 0xff, 0x2f, 0x00,
+        ];
+        data.extend(b"MTrk");
+        data.extend((track.len() as u32).to_be_bytes());
+        data.extend(track);
+        let (messages, length) = read_smf(&data).unwrap();
+        assert_eq!(
+            messages,
+            vec![
+                TimedMessage {
+                    time: 1.0,
+                    data: vec![0xb1, 20, 1]
+                },
+                TimedMessage {
+                    time: 2.0,
+                    data: vec![0xb1, 20, 2]
+                },
+            ]
+        );
+        assert_eq!(length, 2.0);
+        assert_eq!(read_smf(b"RIFF"), Err(MidiFileError::InvalidHeader));
+        let mut invalid = data.clone();
+        let value = invalid.len() - 13;
+        invalid[value] = 0xff;
+        assert_eq!(read_smf(&invalid), Err(MidiFileError::InvalidEvent(0xff)));
+        assert_eq!(
+            read_smf(&data[..data.len() - 2]),
+            Err(MidiFileError::UnexpectedEnd)
+        );
+    }
+}
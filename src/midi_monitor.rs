@@ -0,0 +1,391 @@
+//! Monitor of sent and received MIDI messages with decoding, filters and export.
+use owl_midi::OpenWareMidiSysexCommand;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Write;
+
+/// Default number of messages kept by monitor, older ones are dropped
+pub const DEFAULT_CAPACITY: usize = 5000;
+
+const NOTE_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    /// Symbol shown before sent or received message
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Direction::In => "<",
+            Direction::Out => ">",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MessageKind {
+    NoteOff,
+    NoteOn,
+    PolyPressure,
+    ControlChange,
+    ProgramChange,
+    ChannelPressure,
+    PitchBend,
+    SysEx,
+    System,
+    Realtime,
+}
+
+impl MessageKind {
+    pub const ALL: [MessageKind; 10] = [
+        MessageKind::NoteOff,
+        MessageKind::NoteOn,
+        MessageKind::PolyPressure,
+        MessageKind::ControlChange,
+        MessageKind::ProgramChange,
+        MessageKind::ChannelPressure,
+        MessageKind::PitchBend,
+        MessageKind::SysEx,
+        MessageKind::System,
+        MessageKind::Realtime,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            MessageKind::NoteOff => "Note off",
+            MessageKind::NoteOn => "Note on",
+            MessageKind::PolyPressure => "Poly pressure",
+            MessageKind::ControlChange => "CC",
+            MessageKind::ProgramChange => "Program change",
+            MessageKind::ChannelPressure => "Channel pressure",
+            MessageKind::PitchBend => "Pitch bend",
+            MessageKind::SysEx => "SysEx",
+            MessageKind::System => "System",
+            MessageKind::Realtime => "Realtime",
+        }
+    }
+    pub fn of(data: &[u8]) -> Option<Self> {
+        let kind = match data.first()? {
+            0x80..=0x8f => MessageKind::NoteOff,
+            0x90..=0x9f => MessageKind::NoteOn,
+            0xa0..=0xaf => MessageKind::PolyPressure,
+            0xb0..=0xbf => MessageKind::ControlChange,
+            0xc0..=0xcf => MessageKind::ProgramChange,
+            0xd0..=0xdf => MessageKind::ChannelPressure,
+            0xe0..=0xef => MessageKind::PitchBend,
+            0xf0 => MessageKind::SysEx,
+            0xf1..=0xf7 => MessageKind::System,
+            0xf8..=0xff => MessageKind::Realtime,
+            _ => return None,
+        };
+        Some(kind)
+    }
+}
+
+/// Name of an OWL sysex command, unknown commands are shown as hex
+pub fn command_name(command: u8) -> String {
+    match OpenWareMidiSysexCommand::try_from(command as isize) {
+        Ok(command) => format!("{command:?}"),
+        Err(_) => format!("Command 0x{command:02x}"),
+    }
+}
+
+fn note_name(note: u8) -> String {
+    format!(
+        "{}{}",
+        NOTE_NAMES[note as usize % 12],
+        (note / 12) as i32 - 1
+    )
+}
+
+/// Message sent to or received from device
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorEntry {
+    /// Seconds since app start
+    pub time: f64,
+    /// Microseconds from input callback, outgoing messages don't have it
+    pub timestamp: Option<u64>,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+impl MonitorEntry {
+    pub fn kind(&self) -> Option<MessageKind> {
+        MessageKind::of(&self.data)
+    }
+    /// Zero based channel of channel messages
+    pub fn channel(&self) -> Option<u8> {
+        match self.data.first() {
+            Some(&status) if (0x80..0xf0).contains(&status) => Some(status & 0x0f),
+            _ => None,
+        }
+    }
+    /// Command ID of OWL sysex messages, sent by device or addressed to any device by host
+    pub fn sysex_command(&self) -> Option<u8> {
+        match self.data[..] {
+            [0xf0, manufacturer, device, command, ..]
+                if manufacturer as u32 == owl_midi::MIDI_SYSEX_MANUFACTURER
+                    && (device as u32 == owl_midi::MIDI_SYSEX_OWL_DEVICE
+                        || device as u32 == owl_midi::MIDI_SYSEX_OMNI_DEVICE) =>
+            {
+                Some(command)
+            }
+            _ => None,
+        }
+    }
+    pub fn hex(&self) -> String {
+        self.data
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+    /// Message type with decoded values
+    pub fn describe(&self) -> String {
+        let data = &self.data;
+        let byte = |i: usize| data.get(i).copied().unwrap_or(0);
+        let kind = match self.kind() {
+            Some(kind) => kind,
+            None => return "Data".to_string(),
+        };
+        match kind {
+            MessageKind::NoteOff | MessageKind::NoteOn => {
+                format!("{} {} vel {}", kind.name(), note_name(byte(1)), byte(2))
+            }
+            MessageKind::PolyPressure => {
+                format!("{} {} = {}", kind.name(), note_name(byte(1)), byte(2))
+            }
+            MessageKind::ControlChange => format!("CC {} = {}", byte(1), byte(2)),
+            MessageKind::ProgramChange => format!("Program {}", byte(1)),
+            MessageKind::ChannelPressure => format!("{} {}", kind.name(), byte(1)),
+            MessageKind::PitchBend => {
+                let value = ((byte(2) as i32) << 7 | byte(1) as i32) - 0x2000;
+                format!("{} {value:+}", kind.name())
+            }
+            MessageKind::SysEx => match self.sysex_command() {
+                Some(command) => format!("{} ({} bytes)", command_name(command), data.len()),
+                None => format!("SysEx ({} bytes)", data.len()),
+            },
+            MessageKind::System => match byte(0) {
+                0xf1 => "MTC quarter frame".to_string(),
+                0xf2 => format!("Song position {}", (byte(2) as u16) << 7 | byte(1) as u16),
+                0xf3 => format!("Song select {}", byte(1)),
+                0xf6 => "Tune request".to_string(),
+                status => format!("System 0x{status:02x}"),
+            },
+            MessageKind::Realtime => match byte(0) {
+                0xf8 => "Clock".to_string(),
+                0xfa => "Start".to_string(),
+                0xfb => "Continue".to_string(),
+                0xfc => "Stop".to_string(),
+                0xfe => "Active sensing".to_string(),
+                0xff => "Reset".to_string(),
+                status => format!("Realtime 0x{status:02x}"),
+            },
+        }
+    }
+}
+
+/// Selects shown messages, filters only apply to messages that have the filtered property
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MonitorFilter {
+    pub hidden_kinds: HashSet<MessageKind>,
+    pub direction: Option<Direction>,
+    /// Zero based channel of channel messages
+    pub channel: Option<u8>,
+    /// OWL sysex command, other sysex messages are hidden when it's set
+    pub command: Option<u8>,
+}
+
+impl MonitorFilter {
+    pub fn matches(&self, entry: &MonitorEntry) -> bool {
+        let kind = entry.kind();
+        kind.map_or(true, |kind| !self.hidden_kinds.contains(&kind))
+            && self.direction.map_or(true, |d| d == entry.direction)
+            && (self.channel.is_none() || entry.channel().map_or(true, |c| Some(c) == self.channel))
+            && (self.command.is_none()
+                || kind != Some(MessageKind::SysEx)
+                || entry.sysex_command() == self.command)
+    }
+}
+
+/// Recent messages, oldest ones are dropped when capacity is reached
+#[derive(Debug)]
+pub struct MidiMonitor {
+    entries: VecDeque<MonitorEntry>,
+    pub capacity: usize,
+    /// Messages aren't recorded while paused
+    pub paused: bool,
+    dropped: usize,
+}
+
+impl Default for MidiMonitor {
+    fn default() -> Self {
+        MidiMonitor {
+            entries: VecDeque::new(),
+            capacity: DEFAULT_CAPACITY,
+            paused: false,
+            dropped: 0,
+        }
+    }
+}
+
+impl MidiMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn record(&mut self, entry: MonitorEntry) {
+        if self.paused {
+            return;
+        }
+        self.entries.push_back(entry);
+        while self.entries.len() > self.capacity {
+            self.entries.pop_front();
+            self.dropped += 1;
+        }
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.dropped = 0;
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Number of messages dropped because of capacity since last clear
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+    pub fn filtered<'a>(
+        &'a self,
+        filter: &'a MonitorFilter,
+    ) -> impl Iterator<Item = &'a MonitorEntry> + 'a {
+        self.entries.iter().filter(|entry| filter.matches(entry))
+    }
+    /// One line per message with time, direction, hex dump and decoded message
+    pub fn to_text(&self, filter: &MonitorFilter) -> String {
+        let mut text = String::new();
+        for entry in self.filtered(filter) {
+            let _ = write!(text, "{:10.4} {}", entry.time, entry.direction.symbol());
+            if let Some(timestamp) = entry.timestamp {
+                let _ = write!(text, " [{timestamp} us]");
+            }
+            let _ = writeln!(text, " {} | {}", entry.describe(), entry.hex());
+        }
+        text
+    }
+    /// Shown sysex messages concatenated, as stored in .syx files
+    pub fn to_syx(&self, filter: &MonitorFilter) -> Vec<u8> {
+        self.filtered(filter)
+            .filter(|entry| entry.kind() == Some(MessageKind::SysEx))
+            .flat_map(|entry| entry.data.iter().copied())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn entry(direction: Direction, data: &[u8]) -> MonitorEntry {
+        MonitorEntry {
+            time: 0.0,
+            timestamp: None,
+            direction,
+            data: data.to_vec(),
+        }
+    }
+
+    fn owl_sysex(command: u8) -> Vec<u8> {
+        vec![
+            0xf0,
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            command,
+            0xf7,
+        ]
+    }
+
+    #[test]
+    fn test_describe() {
+        let describe = |data: &[u8]| entry(Direction::In, data).describe();
+        assert_eq!(describe(&[0x91, 60, 100]), "Note on C4 vel 100");
+        assert_eq!(describe(&[0xb0, 20, 64]), "CC 20 = 64");
+        assert_eq!(describe(&[0xe0, 0, 0x40]), "Pitch bend +0");
+        assert_eq!(describe(&[0xe0, 0, 0]), "Pitch bend -8192");
+        assert_eq!(describe(&[0xf8]), "Clock");
+        assert_eq!(describe(&[0xf0, 0x7e, 0xf7]), "SysEx (3 bytes)");
+        assert_eq!(entry(Direction::In, &[0xb0, 1, 2]).hex(), "b0 01 02");
+        assert_eq!(entry(Direction::In, &[0x95, 1, 2]).channel(), Some(5));
+        assert_eq!(
+            entry(Direction::In, &owl_sysex(0x20)).sysex_command(),
+            Some(0x20)
+        );
+    }
+
+    #[test]
+    fn test_filter() {
+        let note = entry(Direction::In, &[0x91, 60, 100]);
+        let clock = entry(Direction::Out, &[0xf8]);
+        let sysex = entry(Direction::In, &owl_sysex(0x20));
+        let mut filter = MonitorFilter::default();
+        assert!(filter.matches(&note) && filter.matches(&clock));
+        filter.hidden_kinds.insert(MessageKind::Realtime);
+        assert!(!filter.matches(&clock));
+        filter.channel = Some(0);
+        assert!(!filter.matches(&note));
+        assert!(filter.matches(&sysex));
+        filter.channel = Some(1);
+        filter.command = Some(0x21);
+        assert!(filter.matches(&note));
+        assert!(!filter.matches(&sysex));
+        filter.command = None;
+        filter.direction = Some(Direction::Out);
+        assert!(!filter.matches(&sysex));
+
+        // Requests from host are sent to any device
+        let request = entry(
+            Direction::Out,
+            &[
+                0xf0,
+                owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+                owl_midi::MIDI_SYSEX_OMNI_DEVICE as u8,
+                0x21,
+                0xf7,
+            ],
+        );
+        assert_eq!(request.sysex_command(), Some(0x21));
+        filter.command = Some(0x21);
+        assert!(filter.matches(&request));
+        filter.command = Some(0x20);
+        assert!(!filter.matches(&request));
+    }
+
+    #[test]
+    fn test_capacity_and_export() {
+        let mut monitor = MidiMonitor::new();
+        monitor.capacity = 2;
+        monitor.record(entry(Direction::Out, &[0xc0, 1]));
+        monitor.record(entry(Direction::In, &owl_sysex(0x20)));
+        monitor.record(entry(Direction::In, &[0xb0, 1, 2]));
+        assert_eq!(monitor.len(), 2);
+        assert_eq!(monitor.dropped(), 1);
+        monitor.paused = true;
+        monitor.record(entry(Direction::In, &[0xb0, 1, 3]));
+        assert_eq!(monitor.len(), 2);
+
+        let filter = MonitorFilter::default();
+        assert_eq!(monitor.to_syx(&filter), owl_sysex(0x20));
+        let text = monitor.to_text(&filter);
+        assert_eq!(text.lines().count(), 2);
+        assert!(text.ends_with("CC 1 = 2 | b0 01 02\n"));
+        monitor.clear();
+        assert!(monitor.is_empty());
+        assert_eq!(monitor.dropped(), 0);
+    }
+}
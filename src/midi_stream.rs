@@ -0,0 +1,267 @@
+//! Splitting raw MIDI input into complete messages.
+use std::sync::mpsc::Sender;
+
+/// Sysex messages longer than this are dropped
+pub const MAX_SYSEX_SIZE: usize = 0x10000;
+
+/// Complete MIDI message received from an input port
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MidiFrame {
+    pub timestamp: u64,
+    pub data: Vec<u8>,
+}
+
+impl MidiFrame {
+    pub fn new(timestamp: u64, data: Vec<u8>) -> Self {
+        MidiFrame { timestamp, data }
+    }
+}
+
+/// Stateful decoder that splits raw MIDI byte stream into complete messages.
+///
+/// Handles running status, realtime bytes interleaved with other messages and
+/// messages that are split across several input callbacks.
+pub struct MidiStreamDecoder {
+    running_status: Option<u8>,
+    buffer: Vec<u8>,
+    in_sysex: bool,
+}
+
+impl Default for MidiStreamDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MidiStreamDecoder {
+    pub fn new() -> Self {
+        MidiStreamDecoder {
+            running_status: None,
+            buffer: Vec::new(),
+            in_sysex: false,
+        }
+    }
+    pub fn reset(&mut self) {
+        self.running_status = None;
+        self.buffer.clear();
+        self.in_sysex = false;
+    }
+    // Expected message size for a status byte, None for sysex and undefined statuses
+    fn message_size(status: u8) -> Option<usize> {
+        match status {
+            0x80..=0xbf | 0xe0..=0xef | 0xf2 => Some(3),
+            0xc0..=0xdf | 0xf1 | 0xf3 => Some(2),
+            0xf6 | 0xf8..=0xff => Some(1),
+            _ => None,
+        }
+    }
+    /// Decodes bytes, calling `handler` for every complete message
+    pub fn decode<F>(&mut self, bytes: &[u8], mut handler: F)
+    where
+        F: FnMut(&[u8]),
+    {
+        for &byte in bytes {
+            match byte {
+                0xf8..=0xff => {
+                    // Realtime messages may appear anywhere, even inside sysex
+                    handler(&[byte]);
+                }
+                0xf0 => {
+                    self.running_status = None;
+                    self.buffer.clear();
+                    self.buffer.push(byte);
+                    self.in_sysex = true;
+                }
+                0xf7 => {
+                    if self.in_sysex {
+                        self.buffer.push(byte);
+                        handler(&self.buffer);
+                    }
+                    self.buffer.clear();
+                    self.in_sysex = false;
+                }
+                0x80..=0xf6 => {
+                    // Any other status byte terminates unfinished sysex
+                    self.in_sysex = false;
+                    self.buffer.clear();
+                    self.running_status = if byte < 0xf0 { Some(byte) } else { None };
+                    match Self::message_size(byte) {
+                        Some(1) => handler(&[byte]),
+                        Some(_) => self.buffer.push(byte),
+                        None => {}
+                    }
+                }
+                _ => {
+                    if self.in_sysex {
+                        if self.buffer.len() < MAX_SYSEX_SIZE {
+                            self.buffer.push(byte);
+                        } else {
+                            self.in_sysex = false;
+                            self.buffer.clear();
+                        }
+                        continue;
+                    }
+                    if self.buffer.is_empty() {
+                        match self.running_status {
+                            Some(status) => self.buffer.push(status),
+                            // Data byte without status
+                            None => continue,
+                        }
+                    }
+                    self.buffer.push(byte);
+                    if Some(self.buffer.len()) == Self::message_size(self.buffer[0]) {
+                        handler(&self.buffer);
+                        self.buffer.clear();
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Input port callback data that decodes incoming bytes and sends complete messages to a channel
+pub struct MidiFrameSender {
+    decoder: MidiStreamDecoder,
+    sender: Sender<MidiFrame>,
+    /// Called after messages were sent, so that receiver doesn't have to poll
+    wakeup: Option<Box<dyn Fn() + Send>>,
+}
+
+impl MidiFrameSender {
+    pub fn new(sender: Sender<MidiFrame>) -> Self {
+        MidiFrameSender {
+            decoder: MidiStreamDecoder::new(),
+            sender,
+            wakeup: None,
+        }
+    }
+    /// Sender that calls `wakeup` after every input callback with complete messages
+    pub fn with_wakeup(sender: Sender<MidiFrame>, wakeup: impl Fn() + Send + 'static) -> Self {
+        MidiFrameSender {
+            wakeup: Some(Box::new(wakeup)),
+            ..Self::new(sender)
+        }
+    }
+    pub fn receive(&mut self, timestamp: u64, bytes: &[u8]) {
+        let sender = &self.sender;
+        let mut received = false;
+        self.decoder.decode(bytes, |message| {
+            // Receiver is gone if app is shutting down
+            let _ = sender.send(MidiFrame::new(timestamp, message.to_vec()));
+            received = true;
+        });
+        if let (true, Some(wakeup)) = (received, &self.wakeup) {
+            wakeup();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode_all(decoder: &mut MidiStreamDecoder, chunks: &[&[u8]]) -> Vec<Vec<u8>> {
+        let mut result = Vec::new();
+        for chunk in chunks {
+            decoder.decode(chunk, |message| result.push(message.to_vec()));
+        }
+        result
+    }
+
+    #[test]
+    fn test_channel_messages() {
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &[&[0xb0, 20, 64, 0xc0, 5]]),
+            vec![vec![0xb0, 20, 64], vec![0xc0, 5]]
+        );
+        // Data byte without status is dropped
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &[&[1, 2, 0x90, 60, 100]]),
+            vec![vec![0x90, 60, 100]]
+        );
+    }
+
+    #[test]
+    fn test_running_status() {
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &[&[0xb0, 20, 64, 21], &[65, 22, 66]]),
+            vec![vec![0xb0, 20, 64], vec![0xb0, 21, 65], vec![0xb0, 22, 66]]
+        );
+        // System common messages cancel running status
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &[&[0xb0, 20, 64, 0xf3, 1, 21, 65]]),
+            vec![vec![0xb0, 20, 64], vec![0xf3, 1]]
+        );
+    }
+
+    #[test]
+    fn test_split_sysex() {
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &[&[0xf0, 0x7d, 0x52], &[0x01, 0x02], &[0xf7]]),
+            vec![vec![0xf0, 0x7d, 0x52, 0x01, 0x02, 0xf7]]
+        );
+    }
+
+    #[test]
+    fn test_realtime_inside_sysex() {
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(
+                &mut decoder,
+                &[&[0xf0, 0x7d, 0xf8, 0x52], &[0xfe, 0x01, 0xf7, 0xfa]]
+            ),
+            vec![
+                vec![0xf8],
+                vec![0xfe],
+                vec![0xf0, 0x7d, 0x52, 0x01, 0xf7],
+                vec![0xfa]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_interrupted_sysex() {
+        let mut decoder = MidiStreamDecoder::new();
+        assert_eq!(
+            decode_all(&mut decoder, &[&[0xf0, 0x7d, 0x52, 0xb0, 1, 2, 0xf7]]),
+            vec![vec![0xb0, 1, 2]]
+        );
+    }
+
+    #[test]
+    fn test_frame_sender() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut frame_sender = MidiFrameSender::new(sender);
+        frame_sender.receive(1, &[0xf0, 0x7d]);
+        assert!(receiver.try_recv().is_err());
+        frame_sender.receive(2, &[0xf7, 0xb0, 1, 2]);
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                MidiFrame::new(2, vec![0xf0, 0x7d, 0xf7]),
+                MidiFrame::new(2, vec![0xb0, 1, 2])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_wakeup() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+        let (sender, _receiver) = std::sync::mpsc::channel();
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let mut frame_sender = MidiFrameSender::with_wakeup(sender, move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+        });
+        frame_sender.receive(1, &[0xb0, 1]);
+        assert_eq!(count.load(Ordering::Relaxed), 0);
+        frame_sender.receive(2, &[2, 0xb0, 3, 4]);
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+    }
+}
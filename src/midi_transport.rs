@@ -0,0 +1,247 @@
+//! Destinations for outgoing MIDI messages, including test doubles for device conversations.
+use crate::midi_stream::MidiFrame;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+/// Errors returned when a message can't be delivered.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum TransportError {
+    /// Port was closed or nobody receives messages anymore
+    Disconnected,
+    /// Backend failed to send message
+    Send(String),
+    /// Replayed session didn't expect this message
+    Unexpected(Vec<u8>),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransportError::Disconnected => write!(f, "Not connected"),
+            TransportError::Send(err) => write!(f, "Send failed: {err}"),
+            TransportError::Unexpected(data) => write!(f, "Unexpected message {data:02x?}"),
+        }
+    }
+}
+
+impl std::error::Error for TransportError {}
+
+/// Destination for outgoing MIDI messages, every call sends a single complete message.
+pub trait MidiTransport: Send {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError>;
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl MidiTransport for midir::MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        midir::MidiOutputConnection::send(self, message)
+            .map_err(|err| TransportError::Send(err.to_string()))
+    }
+}
+
+/// Sends every message back to a receiver, as if device echoed it
+pub struct LoopbackTransport {
+    sender: Sender<MidiFrame>,
+}
+
+impl LoopbackTransport {
+    pub fn new(sender: Sender<MidiFrame>) -> Self {
+        LoopbackTransport { sender }
+    }
+}
+
+impl MidiTransport for LoopbackTransport {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.sender
+            .send(MidiFrame::new(0, message.to_vec()))
+            .map_err(|_| TransportError::Disconnected)
+    }
+}
+
+/// Keeps a copy of every sent message and forwards it to another transport
+pub struct RecordingTransport<T> {
+    inner: T,
+    pub messages: Vec<Vec<u8>>,
+}
+
+impl<T: MidiTransport> RecordingTransport<T> {
+    pub fn new(inner: T) -> Self {
+        RecordingTransport {
+            inner,
+            messages: Vec::new(),
+        }
+    }
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: MidiTransport> MidiTransport for RecordingTransport<T> {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.messages.push(message.to_vec());
+        self.inner.send(message)
+    }
+}
+
+/// Forwards messages to another transport and sends a copy of every delivered message to a monitor
+pub struct MonitorTransport<T> {
+    inner: T,
+    sender: Sender<MidiFrame>,
+}
+
+impl<T: MidiTransport> MonitorTransport<T> {
+    pub fn new(inner: T, sender: Sender<MidiFrame>) -> Self {
+        MonitorTransport { inner, sender }
+    }
+}
+
+impl<T: MidiTransport> MidiTransport for MonitorTransport<T> {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.inner.send(message)?;
+        // Monitor may be gone while messages are still sent
+        let _ = self.sender.send(MidiFrame::new(0, message.to_vec()));
+        Ok(())
+    }
+}
+
+/// Transport that can be used from several threads, clones send to the same destination
+#[derive(Clone)]
+pub struct SharedTransport(Arc<Mutex<Box<dyn MidiTransport>>>);
+
+impl SharedTransport {
+    pub fn new(transport: Box<dyn MidiTransport>) -> Self {
+        SharedTransport(Arc::new(Mutex::new(transport)))
+    }
+    /// True if both handles send to the same transport
+    pub fn same(&self, other: &SharedTransport) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl MidiTransport for SharedTransport {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.0
+            .lock()
+            .map_err(|_| TransportError::Disconnected)?
+            .send(message)
+    }
+}
+
+/// Replays recorded conversation with a device.
+///
+/// Every sent message must match the next recorded request, then responses recorded
+/// for that request are sent to receiver.
+pub struct ReplayTransport {
+    script: VecDeque<(Vec<u8>, Vec<Vec<u8>>)>,
+    sender: Sender<MidiFrame>,
+}
+
+impl ReplayTransport {
+    pub fn new(sender: Sender<MidiFrame>) -> Self {
+        ReplayTransport {
+            script: VecDeque::new(),
+            sender,
+        }
+    }
+    /// Adds a request and responses sent for it
+    pub fn expect(mut self, request: &[u8], responses: &[&[u8]]) -> Self {
+        self.script.push_back((
+            request.to_vec(),
+            responses.iter().map(|response| response.to_vec()).collect(),
+        ));
+        self
+    }
+    /// Returns true if all recorded requests were sent
+    pub fn is_finished(&self) -> bool {
+        self.script.is_empty()
+    }
+}
+
+impl MidiTransport for ReplayTransport {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        match self.script.front() {
+            Some((request, _)) if request == message => {
+                let (_, responses) = self.script.pop_front().unwrap();
+                for response in responses {
+                    self.sender
+                        .send(MidiFrame::new(0, response))
+                        .map_err(|_| TransportError::Disconnected)?;
+                }
+                Ok(())
+            }
+            _ => Err(TransportError::Unexpected(message.to_vec())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_loopback() {
+        let (sender, receiver) = channel();
+        let mut transport = LoopbackTransport::new(sender);
+        assert_eq!(transport.send(&[0xb0, 1, 2]), Ok(()));
+        assert_eq!(
+            receiver.try_recv().unwrap(),
+            MidiFrame::new(0, vec![0xb0, 1, 2])
+        );
+        drop(receiver);
+        assert_eq!(
+            transport.send(&[0xb0, 1, 2]),
+            Err(TransportError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn test_recording() {
+        let (sender, receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        transport.send(&[0xc0, 1]).unwrap();
+        transport.send(&[0xb0, 1, 2]).unwrap();
+        assert_eq!(transport.messages, vec![vec![0xc0, 1], vec![0xb0, 1, 2]]);
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_monitor() {
+        let (sender, receiver) = channel();
+        let (monitor_sender, monitor_receiver) = channel();
+        let mut transport = MonitorTransport::new(LoopbackTransport::new(sender), monitor_sender);
+        transport.send(&[0xc0, 1]).unwrap();
+        assert_eq!(
+            monitor_receiver.try_iter().collect::<Vec<_>>(),
+            vec![MidiFrame::new(0, vec![0xc0, 1])]
+        );
+        drop(receiver);
+        assert!(transport.send(&[0xc0, 2]).is_err());
+        assert!(monitor_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let (sender, receiver) = channel();
+        let mut transport = ReplayTransport::new(sender)
+            .expect(&[0xb0, 1, 2], &[&[0xf0, 0x7d, 0xf7], &[0xc0, 3]])
+            .expect(&[0xc0, 1], &[]);
+        assert_eq!(
+            transport.send(&[0xc0, 1]),
+            Err(TransportError::Unexpected(vec![0xc0, 1]))
+        );
+        assert_eq!(transport.send(&[0xb0, 1, 2]), Ok(()));
+        assert_eq!(
+            receiver.try_iter().collect::<Vec<_>>(),
+            vec![
+                MidiFrame::new(0, vec![0xf0, 0x7d, 0xf7]),
+                MidiFrame::new(0, vec![0xc0, 3])
+            ]
+        );
+        assert!(!transport.is_finished());
+        assert_eq!(transport.send(&[0xc0, 1]), Ok(()));
+        assert!(transport.is_finished());
+    }
+}
@@ -0,0 +1,41 @@
+//! Desktop notifications, shown by the OS natively or by the browser on web.
+#[cfg(target_arch = "wasm32")]
+use web_sys::{Notification, NotificationOptions, NotificationPermission};
+
+/// Shows a notification, failures are only logged
+#[cfg(not(target_arch = "wasm32"))]
+pub fn notify(summary: &str, body: &str) {
+    if let Err(err) = notify_rust::Notification::new()
+        .appname("OWL Wave")
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        tracing::warn!(%err, "can't show notification");
+    }
+}
+
+/// Shows a notification if user has allowed them, failures are only logged
+#[cfg(target_arch = "wasm32")]
+pub fn notify(summary: &str, body: &str) {
+    if Notification::permission() == NotificationPermission::Granted {
+        if let Err(err) =
+            Notification::new_with_options(summary, NotificationOptions::new().body(body))
+        {
+            tracing::warn!(?err, "can't show notification");
+        }
+    }
+}
+
+/// Asks user to allow notifications, browsers only allow this in response to user input
+#[cfg(not(target_arch = "wasm32"))]
+pub fn request_permission() {}
+
+#[cfg(target_arch = "wasm32")]
+pub fn request_permission() {
+    if Notification::permission() == NotificationPermission::Default {
+        if let Err(err) = Notification::request_permission() {
+            tracing::warn!(?err, "can't request notification permission");
+        }
+    }
+}
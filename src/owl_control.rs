@@ -1,5 +1,16 @@
-pub(crate) mod command_processor;
+//! OWL device protocol: sysex encoding, command processing, requests and resource transfers.
+pub mod automation;
+pub mod command_processor;
 mod crc32;
-mod parameter;
-pub(crate) mod resources;
-mod sysex;
+pub mod emulator;
+pub mod midi_learn;
+pub mod modulator;
+pub mod parameter;
+pub mod patch_collection;
+pub mod program_history;
+pub mod program_stats;
+pub mod requests;
+pub mod resources;
+pub mod settings;
+pub mod snapshot;
+pub mod sysex;
@@ -0,0 +1,286 @@
+//! Parameter automation lanes that are recorded from parameter changes and played back in a loop.
+use super::parameter::{parameter_for_cc, OwlParameter};
+use crate::midi_file::{read_smf, write_smf, MidiFileError, TimedMessage};
+use owl_midi::{OpenWareMidiControl, PatchParameterId};
+use std::collections::HashMap;
+
+/// Shortest loop that can be recorded, in seconds
+pub const MIN_LENGTH: f64 = 0.1;
+
+/// Parameter value at a time in seconds from loop start
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutomationPoint {
+    pub time: f64,
+    pub value: f32,
+}
+
+/// Values of a single parameter over time, points are sorted by time
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutomationLane {
+    pub pid: PatchParameterId,
+    points: Vec<AutomationPoint>,
+}
+
+impl AutomationLane {
+    pub fn new(pid: PatchParameterId) -> Self {
+        AutomationLane {
+            pid,
+            points: Vec::new(),
+        }
+    }
+    pub fn points(&self) -> &[AutomationPoint] {
+        &self.points
+    }
+    /// Adds a point and returns its index
+    pub fn insert(&mut self, time: f64, value: f32) -> usize {
+        let index = self.points.partition_point(|point| point.time <= time);
+        self.points.insert(
+            index,
+            AutomationPoint {
+                time: time.max(0.0),
+                value: value.clamp(0.0, 1.0),
+            },
+        );
+        index
+    }
+    pub fn remove(&mut self, index: usize) {
+        if index < self.points.len() {
+            self.points.remove(index);
+        }
+    }
+    /// Moves a point, returns its new index as points stay sorted by time
+    pub fn move_point(&mut self, index: usize, time: f64, value: f32) -> usize {
+        self.remove(index);
+        self.insert(time, value)
+    }
+    /// Value interpolated between surrounding points, first and last values extend to loop edges
+    pub fn value_at(&self, time: f64) -> Option<f32> {
+        let next = self.points.partition_point(|point| point.time <= time);
+        match (
+            next.checked_sub(1).map(|i| self.points[i]),
+            self.points.get(next),
+        ) {
+            (Some(prev), Some(next)) if next.time > prev.time => {
+                let position = ((time - prev.time) / (next.time - prev.time)) as f32;
+                Some(prev.value + (next.value - prev.value) * position)
+            }
+            (Some(prev), _) => Some(prev.value),
+            (None, Some(next)) => Some(next.value),
+            (None, None) => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AutomationState {
+    Stopped,
+    Recording { start: f64 },
+    Playing { start: f64 },
+}
+
+/// Automation lanes for all recorded parameters with loop length
+#[derive(Debug)]
+pub struct Automation {
+    pub lanes: Vec<AutomationLane>,
+    /// Loop length in seconds
+    pub length: f64,
+    state: AutomationState,
+    /// Last recorded value for every parameter
+    recorded: HashMap<PatchParameterId, f32>,
+}
+
+impl Default for Automation {
+    fn default() -> Self {
+        Automation {
+            lanes: Vec::new(),
+            length: 0.0,
+            state: AutomationState::Stopped,
+            recorded: HashMap::new(),
+        }
+    }
+}
+
+impl Automation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn state(&self) -> AutomationState {
+        self.state
+    }
+    pub fn is_empty(&self) -> bool {
+        self.lanes.iter().all(|lane| lane.points.is_empty())
+    }
+    pub fn lane(&self, pid: PatchParameterId) -> Option<&AutomationLane> {
+        self.lanes.iter().find(|lane| lane.pid == pid)
+    }
+    pub fn lane_mut(&mut self, pid: PatchParameterId) -> &mut AutomationLane {
+        match self.lanes.iter().position(|lane| lane.pid == pid) {
+            Some(index) => &mut self.lanes[index],
+            None => {
+                self.lanes.push(AutomationLane::new(pid));
+                self.lanes.last_mut().unwrap()
+            }
+        }
+    }
+    /// Replaces lanes with a new recording that starts from current parameter values
+    pub fn record(&mut self, now: f64) {
+        self.lanes.clear();
+        self.recorded.clear();
+        self.length = 0.0;
+        self.state = AutomationState::Recording { start: now };
+    }
+    pub fn play(&mut self, now: f64) {
+        if self.length >= MIN_LENGTH {
+            self.state = AutomationState::Playing { start: now };
+        }
+    }
+    /// Stops recording or playback, recording sets loop length
+    pub fn stop(&mut self, now: f64) {
+        if let AutomationState::Recording { start } = self.state {
+            self.length = (now - start).max(MIN_LENGTH);
+        }
+        self.state = AutomationState::Stopped;
+    }
+    /// Position in loop, or recording time
+    pub fn position(&self, now: f64) -> Option<f64> {
+        match self.state {
+            AutomationState::Stopped => None,
+            AutomationState::Recording { start } => Some(now - start),
+            AutomationState::Playing { start } => Some((now - start) % self.length),
+        }
+    }
+    /// Records changed parameter values or sets parameters to played back values.
+    /// Parameters should be synced before recording, so that received CCs are included.
+    pub fn update(&mut self, now: f64, parameters: &mut HashMap<PatchParameterId, OwlParameter>) {
+        let time = match self.position(now) {
+            Some(time) => time,
+            None => return,
+        };
+        match self.state {
+            AutomationState::Recording { .. } => {
+                for (&pid, parameter) in parameters.iter() {
+                    if self.recorded.get(&pid) != Some(&parameter.value) {
+                        self.recorded.insert(pid, parameter.value);
+                        self.lane_mut(pid).points.push(AutomationPoint {
+                            time,
+                            value: parameter.value,
+                        });
+                    }
+                }
+            }
+            AutomationState::Playing { .. } => {
+                for lane in self.lanes.iter() {
+                    if let (Some(parameter), Some(value)) =
+                        (parameters.get_mut(&lane.pid), lane.value_at(time))
+                    {
+                        if !parameter.output {
+                            parameter.value = value;
+                        }
+                    }
+                }
+            }
+            AutomationState::Stopped => {}
+        }
+    }
+    /// Encodes lanes as parameter CCs on channel 1
+    pub fn to_smf(&self) -> Vec<u8> {
+        let messages: Vec<TimedMessage> = self
+            .lanes
+            .iter()
+            .flat_map(|lane| {
+                let cc = OpenWareMidiControl::from(lane.pid) as u8;
+                lane.points.iter().map(move |point| TimedMessage {
+                    time: point.time,
+                    data: vec![0xb0, cc, (point.value * 127.0).round() as u8],
+                })
+            })
+            .collect();
+        write_smf(&messages, self.length)
+    }
+    /// Reads parameter CCs on any channel, other messages are ignored
+    pub fn from_smf(data: &[u8]) -> Result<Self, MidiFileError> {
+        let (messages, length) = read_smf(data)?;
+        let mut automation = Automation::new();
+        for message in messages {
+            if let [status, cc, value] = message.data[..] {
+                if status & 0xf0 == 0xb0 {
+                    if let Some(pid) = parameter_for_cc(cc) {
+                        automation
+                            .lane_mut(pid)
+                            .insert(message.time, value as f32 / 127.0);
+                    }
+                }
+            }
+        }
+        automation.length = length.max(MIN_LENGTH);
+        Ok(automation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_lane() {
+        let pid = PatchParameterId::try_from(0).unwrap();
+        let mut lane = AutomationLane::new(pid);
+        assert_eq!(lane.value_at(0.0), None);
+        assert_eq!(lane.insert(1.0, 1.0), 0);
+        assert_eq!(lane.insert(0.0, 0.0), 0);
+        assert_eq!(lane.value_at(0.25), Some(0.25));
+        assert_eq!(lane.value_at(2.0), Some(1.0));
+        assert_eq!(lane.move_point(0, 1.5, 2.0), 1);
+        assert_eq!(
+            lane.points(),
+            &[
+                AutomationPoint {
+                    time: 1.0,
+                    value: 1.0
+                },
+                AutomationPoint {
+                    time: 1.5,
+                    value: 1.0
+                }
+            ]
+        );
+        assert_eq!(lane.value_at(0.0), Some(1.0));
+    }
+
+    #[test]
+    fn test_record_and_play() {
+        let pid = PatchParameterId::try_from(0).unwrap();
+        let mut parameters = HashMap::from([(pid, OwlParameter::new("A".to_string()))]);
+        let mut automation = Automation::new();
+        automation.play(0.0);
+        assert_eq!(automation.state(), AutomationState::Stopped);
+
+        automation.record(10.0);
+        automation.update(10.0, &mut parameters);
+        automation.update(10.5, &mut parameters);
+        parameters.get_mut(&pid).unwrap().value = 1.0;
+        automation.update(11.0, &mut parameters);
+        automation.stop(12.0);
+        assert_eq!(automation.length, 2.0);
+        assert_eq!(automation.lane(pid).unwrap().points().len(), 2);
+
+        automation.play(20.0);
+        automation.update(20.5, &mut parameters);
+        assert_eq!(parameters[&pid].value, 0.5);
+        automation.update(23.0, &mut parameters);
+        assert_eq!(automation.position(23.0), Some(1.0));
+        assert_eq!(parameters[&pid].value, 1.0);
+    }
+
+    #[test]
+    fn test_smf() {
+        let pid = PatchParameterId::try_from(1).unwrap();
+        let mut automation = Automation::new();
+        automation.length = 2.0;
+        automation.lane_mut(pid).insert(0.0, 0.0);
+        automation.lane_mut(pid).insert(1.0, 1.0);
+        let loaded = Automation::from_smf(&automation.to_smf()).unwrap();
+        assert_eq!(loaded.lanes, automation.lanes);
+        assert_eq!(loaded.length, 2.0);
+    }
+}
@@ -1,18 +1,75 @@
+//! Sends commands to device and keeps state decoded from its responses.
+use crate::midi_transport::MidiTransport;
 use anyhow::Error;
-use midir::MidiOutputConnection;
-use owl_midi::{OpenWareMidiSysexCommand, PatchParameterId, SysexConfiguration};
-use std::collections::HashMap;
+use owl_midi::{
+    OpenWareMidiControl, OpenWareMidiSysexCommand, PatchParameterId, SysexConfiguration,
+};
+use std::collections::{BTreeMap, HashMap};
+use tracing::{debug, error, info, info_span, warn};
 use wmidi::{Channel, ControlFunction, MidiMessage, U7};
 
 use super::{
-    parameter::OwlParameter,
-    resources::{Resource, ResourceData},
-    sysex::SysexData,
+    parameter::{
+        button_name, parameter_for_cc, NrpnDecoder, OwlParameter, PATCH_BUTTON_OFF, PATCH_BUTTON_ON,
+    },
+    requests::{RequestEvent, RequestState, RequestTracker},
+    resources::{encode_upload, resource_with_header, Resource, ResourceData},
+    sysex::{SysexData, SysexError},
 };
 
+/// Converts sysex string data up to optional null terminator
+fn sysex_string(data: &[U7]) -> String {
+    let end = data
+        .iter()
+        .position(|&x| x == U7::MIN)
+        .unwrap_or(data.len());
+    String::from_utf8_lossy(U7::data_to_bytes(&data[..end])).to_string()
+}
+
+// Device reports settings as 32 bit words in hex, so negative values like omni MIDI channel
+// are received as their two's complement
+fn setting_value(text: &str) -> Option<i64> {
+    let value = i64::from_str_radix(text, 16).ok()?;
+    match u32::try_from(value) {
+        Ok(word) => Some(word as i32 as i64),
+        Err(_) => Some(value),
+    }
+}
+
+// Slot number encoded as sysex payload
+fn slot_payload(slot: u32) -> [u8; 5] {
+    let mut data = [U7::MIN; 5];
+    slot.encode(&mut data).unwrap();
+    let mut payload = [0u8; 5];
+    payload.copy_from_slice(U7::data_to_bytes(&data));
+    payload
+}
+
+// Sends a complete message, converting transport error
+fn send_data(connection: &mut dyn MidiTransport, data: &[u8]) -> Result<(), Box<Error>> {
+    connection
+        .send(data)
+        .map_err(|err| Box::new(Error::from(err)))
+}
+
+/// Result of identity handshake with connected device
+#[derive(Debug, PartialEq, Eq)]
+pub enum DeviceIdentity<'a> {
+    /// No handshake was made
+    Unknown,
+    /// Waiting for firmware version
+    Probing,
+    /// Device responded with its firmware version
+    Owl(&'a str),
+    /// Device didn't respond to firmware version request
+    NotResponding,
+}
+
 pub struct OwlCommandProcessor {
     pub firmware_version: Option<String>,
     pub parameters: HashMap<PatchParameterId, OwlParameter>,
+    /// Patch button states by button ID
+    pub buttons: BTreeMap<u8, bool>,
     pub program_message: Option<String>,
     pub error_message: Option<String>,
     pub patch_name: Option<String>,
@@ -20,9 +77,16 @@ pub struct OwlCommandProcessor {
     pub resource_offset: usize,
     pub resources: Vec<Option<Resource>>,
     pub program_stats: Option<String>,
-    pub settings: HashMap<SysexConfiguration, String>,
-    pub log: String,
+    pub settings: HashMap<SysexConfiguration, i64>,
     pub resource_data: ResourceData,
+    pub requests: RequestTracker,
+    nrpn: NrpnDecoder,
+}
+
+impl Default for OwlCommandProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl OwlCommandProcessor {
@@ -30,6 +94,7 @@ impl OwlCommandProcessor {
         OwlCommandProcessor {
             firmware_version: None,
             parameters: HashMap::new(),
+            buttons: BTreeMap::new(),
             program_message: None,
             error_message: None,
             patch_name: None,
@@ -38,16 +103,77 @@ impl OwlCommandProcessor {
             resources: Vec::new(),
             program_stats: None,
             settings: HashMap::new(),
-            log: String::new(),
             resource_data: ResourceData::new(),
+            requests: RequestTracker::new(),
+            nrpn: NrpnDecoder::new(),
         }
     }
     pub fn request_settings(
         &mut self,
-        connection: &mut MidiOutputConnection,
+        connection: &mut dyn MidiTransport,
+        command: OpenWareMidiSysexCommand,
+    ) -> Result<(), Box<Error>> {
+        let _span = info_span!("request", ?command).entered();
+        info!("request settings");
+        if command == OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND {
+            self.patches.clear()
+        } else if command == OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND {
+            self.resource_offset = 0;
+            self.resources.clear()
+        }
+        self.requests.start(command);
+        Self::send_request(connection, command)
+    }
+    /// Requests firmware version to confirm that connected device is an OWL
+    pub fn identify(&mut self, connection: &mut dyn MidiTransport) -> Result<(), Box<Error>> {
+        self.firmware_version = None;
+        self.request_settings(connection, OpenWareMidiSysexCommand::SYSEX_FIRMWARE_VERSION)
+    }
+    pub fn identity(&self) -> DeviceIdentity<'_> {
+        if let Some(firmware_version) = &self.firmware_version {
+            return DeviceIdentity::Owl(firmware_version);
+        }
+        match self
+            .requests
+            .state(OpenWareMidiSysexCommand::SYSEX_FIRMWARE_VERSION)
+        {
+            Some(RequestState::Pending | RequestState::Receiving) => DeviceIdentity::Probing,
+            Some(RequestState::TimedOut) => DeviceIdentity::NotResponding,
+            _ => DeviceIdentity::Unknown,
+        }
+    }
+    /// Updates request timers, sends again requests that got no response
+    pub fn poll_requests(&mut self, connection: &mut dyn MidiTransport, now: f64) {
+        for event in self.requests.poll(now) {
+            match event {
+                RequestEvent::Retry(command) => {
+                    let _span = info_span!("request", ?command).entered();
+                    warn!("no response, retrying");
+                    if let Err(err) = Self::send_request(connection, command) {
+                        error!(%err, "retry failed");
+                    }
+                }
+                RequestEvent::TimedOut(command) => {
+                    let _span = info_span!("request", ?command).entered();
+                    error!("device not responding");
+                }
+                RequestEvent::Complete(_) => {}
+            }
+        }
+    }
+    /// Requests settings without tracking the request, used for periodic polling
+    pub fn poll_settings(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        command: OpenWareMidiSysexCommand,
+    ) -> Result<(), Box<Error>> {
+        debug!(?command, "poll settings");
+        Self::send_request(connection, command)
+    }
+    fn send_request(
+        connection: &mut dyn MidiTransport,
         command: OpenWareMidiSysexCommand,
     ) -> Result<(), Box<Error>> {
-        self.log += format!("> {command:?}\n").as_str();
         let chan = Channel::from_index(0).unwrap();
         let message = MidiMessage::ControlChange(
             chan,
@@ -58,23 +184,15 @@ impl OwlCommandProcessor {
         );
         let mut msg_data = [0u8; 3];
         message.copy_to_slice(&mut msg_data).unwrap();
-        if command == OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND {
-            self.patches.clear()
-        } else if command == OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND {
-            self.resource_offset = 0;
-            self.resources.clear()
-        }
-        connection
-            .send(&msg_data)
-            .unwrap_or_else(|_| println!("Error when forwarding message ..."));
-        Ok(())
+        send_data(connection, &msg_data)
     }
     pub fn send_sysex_command(
         &mut self,
-        connection: &mut MidiOutputConnection,
+        connection: &mut dyn MidiTransport,
         command: OpenWareMidiSysexCommand,
     ) -> Result<(), Box<Error>> {
-        self.log += format!("> {command:?}\n").as_str();
+        let _span = info_span!("request", ?command).entered();
+        info!("send command");
         let data = [
             owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
             owl_midi::MIDI_SYSEX_OMNI_DEVICE as u8,
@@ -83,14 +201,11 @@ impl OwlCommandProcessor {
         let message = MidiMessage::SysEx(U7::try_from_bytes(&data).unwrap());
         let mut msg_data = [0u8; 5];
         message.copy_to_slice(&mut msg_data).unwrap();
-        connection
-            .send(&msg_data)
-            .unwrap_or_else(|_| println!("Error when sending SysEx ..."));
-        Ok(())
+        send_data(connection, &msg_data)
     }
     pub fn send_sysex_string(
         &mut self,
-        connection: &mut MidiOutputConnection,
+        connection: &mut dyn MidiTransport,
         command: OpenWareMidiSysexCommand,
         string: &[u8],
     ) -> Result<(), Box<Error>> {
@@ -100,25 +215,211 @@ impl OwlCommandProcessor {
             command as u8,
         ];
         data.extend(string.iter());
-        let message = MidiMessage::SysEx(U7::try_from_bytes(&data).unwrap());
-        let mut msg_data = Vec::new();
-        msg_data.resize(message.bytes_size(), 0);
-        message.copy_to_slice(&mut msg_data).unwrap();
-        self.log += format!("> {command:?} = {data:?}\n").as_str();
-        connection
-            .send(&msg_data)
-            .unwrap_or_else(|_| println!("Error when sending SysEx ..."));
-        Ok(())
+        let data = U7::try_from_bytes(&data)
+            .map_err(|_| anyhow::anyhow!("Sysex data contains bytes over 0x7f"))?;
+        let message = MidiMessage::SysEx(data);
+        let mut msg_data = vec![0u8; message.bytes_size()];
+        message
+            .copy_to_slice(&mut msg_data)
+            .map_err(|err| anyhow::anyhow!("Can't encode {command:?}: {err:?}"))?;
+        let _span = info_span!("request", ?command).entered();
+        info!(?data, "send command");
+        send_data(connection, &msg_data)
     }
 
+    // Sends sysex message without logging it, used for bulk transfers
+    fn send_sysex_data(
+        connection: &mut dyn MidiTransport,
+        command: OpenWareMidiSysexCommand,
+        payload: &[U7],
+    ) -> Result<(), Box<Error>> {
+        let mut msg_data = vec![
+            0xf0,
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OMNI_DEVICE as u8,
+            command as u8,
+        ];
+        msg_data.extend_from_slice(U7::data_to_bytes(payload));
+        msg_data.push(0xf7);
+        send_data(connection, &msg_data)
+    }
+    /// Uploads resource and stores it in the first slot after listed resources.
+    ///
+    /// Resource list must be loaded first, otherwise we don't know which slot is free.
+    pub fn send_resource(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        name: &str,
+        data: &[u8],
+    ) -> Result<(), Box<Error>> {
+        if self.resources.is_empty() {
+            return Err(Box::new(anyhow::anyhow!(
+                "Resource list is empty, request resources before uploading"
+            )));
+        }
+        let slot = (self.resource_offset + self.resources.len()) as u32;
+        self.store_resource(connection, name, data, slot)
+    }
+    /// Uploads resource and stores it in given slot, replacing existing resource
+    pub fn store_resource(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        name: &str,
+        data: &[u8],
+        slot: u32,
+    ) -> Result<(), Box<Error>> {
+        let _span = info_span!("upload", name, slot).entered();
+        let packets = encode_upload(&resource_with_header(name, data));
+        info!(
+            size = data.len(),
+            messages = packets.len(),
+            "upload resource"
+        );
+        for packet in packets.iter() {
+            Self::send_sysex_data(
+                connection,
+                OpenWareMidiSysexCommand::SYSEX_FIRMWARE_UPLOAD,
+                packet,
+            )?;
+        }
+        let mut slot_data = [U7::MIN; 5];
+        slot.encode(&mut slot_data).unwrap();
+        info!("store resource");
+        Self::send_sysex_data(
+            connection,
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_STORE,
+            &slot_data,
+        )
+    }
+    /// Changes device setting, value is sent in hex after the 2 character setting code.
+    /// Setting is stored in `settings` once sent, device only reports values when requested.
+    pub fn send_setting(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        config: SysexConfiguration,
+        value: i64,
+    ) -> Result<(), Box<Error>> {
+        let code = config as isize;
+        let mut data = vec![(code >> 8) as u8, (code & 0xff) as u8];
+        if value >= 0 {
+            data.extend(format!("{value:x}").bytes());
+        } else {
+            data.extend(format!("-{:x}", value.unsigned_abs()).bytes());
+        }
+        self.send_sysex_string(
+            connection,
+            OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
+            &data,
+        )?;
+        self.settings.insert(config, value);
+        Ok(())
+    }
+    /// Loads patch from given slot
+    pub fn load_patch(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        slot: u8,
+    ) -> Result<(), Box<Error>> {
+        let program = U7::try_from(slot).map_err(|_| anyhow::anyhow!("Invalid slot {slot}"))?;
+        self.send_message(
+            connection,
+            MidiMessage::ProgramChange(Channel::Ch1, program),
+        )
+    }
+    /// Erases patch or resource in given slot, or all of them if slot is not set
+    pub fn erase(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        slot: Option<u32>,
+    ) -> Result<(), Box<Error>> {
+        let payload = slot.map(slot_payload);
+        let payload: &[u8] = match &payload {
+            Some(payload) => payload,
+            None => &[],
+        };
+        self.send_sysex_string(
+            connection,
+            OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE,
+            payload,
+        )
+    }
+    /// Requests patch or resource data, it's stored in `resource_data` once received
+    pub fn request_download(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        slot: u32,
+    ) -> Result<(), Box<Error>> {
+        self.resource_data.reset();
+        // Device expects slot + 1 here
+        self.send_sysex_string(
+            connection,
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_SEND,
+            &slot_payload(slot + 1),
+        )
+    }
+    /// Sends CC for every parameter that was changed in UI since last sync
+    pub fn sync_parameters(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+    ) -> Result<(), Box<Error>> {
+        let mut changes = Vec::new();
+        for (&pid, parameter) in self.parameters.iter_mut() {
+            if parameter.sync() {
+                changes.push((OpenWareMidiControl::from(pid) as u8, parameter.midi_value));
+            }
+        }
+        for (cc, value) in changes {
+            let message = MidiMessage::ControlChange(
+                Channel::Ch1,
+                ControlFunction(U7::try_from(cc).map_err(|_| anyhow::anyhow!("Invalid CC {cc}"))?),
+                U7::try_from(value)
+                    .map_err(|_| anyhow::anyhow!("Invalid value {value} for CC {cc}"))?,
+            );
+            self.send_message(connection, message)?;
+        }
+        Ok(())
+    }
+    /// Presses or releases a patch button
+    pub fn set_button(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        id: u8,
+        pressed: bool,
+    ) -> Result<(), Box<Error>> {
+        let button = U7::try_from(id).map_err(|_| anyhow::anyhow!("Invalid button {id}"))?;
+        let cc = if pressed {
+            PATCH_BUTTON_ON
+        } else {
+            PATCH_BUTTON_OFF
+        };
+        self.buttons.insert(id, pressed);
+        self.send_message(
+            connection,
+            MidiMessage::ControlChange(
+                Channel::Ch1,
+                ControlFunction(U7::try_from(cc).unwrap()),
+                button,
+            ),
+        )
+    }
+    /// Sends a complete message as it is, e.g. sysex loaded from a file
+    pub fn send_raw(
+        &mut self,
+        connection: &mut dyn MidiTransport,
+        data: &[u8],
+    ) -> Result<(), Box<Error>> {
+        info!(size = data.len(), "send raw message");
+        send_data(connection, data)
+    }
     pub fn send_message(
         &mut self,
-        connection: &mut MidiOutputConnection,
+        connection: &mut dyn MidiTransport,
         message: MidiMessage<'_>,
     ) -> Result<(), Box<Error>> {
-        self.log += format!("> MIDI {message:?}\n").as_str();
+        debug!(midi = ?message, "send message");
         if let MidiMessage::ProgramChange(_, _) = message {
             self.parameters.clear();
+            self.buttons.clear();
         }
         /*
         let message = MidiMessage::ControlChange(
@@ -127,177 +428,154 @@ impl OwlCommandProcessor {
             U7::try_from(value).unwrap(),
         );
         */
-        let mut msg_data = [0u8; 3];
-        message.copy_to_slice(&mut msg_data).unwrap();
-        connection
-            .send(&msg_data)
-            .unwrap_or_else(|_| println!("Error when sending MIDI message ..."));
-        Ok(())
+        let mut msg_data = vec![0u8; message.bytes_size()];
+        message
+            .copy_to_slice(&mut msg_data)
+            .map_err(|err| anyhow::anyhow!("Can't encode {message:?}: {err:?}"))?;
+        send_data(connection, &msg_data)
     }
-    pub fn handle_sysex(&mut self, data: &[U7]) -> Result<(), Error> {
-        // TODO: use different error trait
-        if u8::from(data[0]) as u32 == owl_midi::MIDI_SYSEX_MANUFACTURER
-            && u8::from(data[1]) as u32 == owl_midi::MIDI_SYSEX_OWL_DEVICE
+    /// Handles sysex data without start and end bytes, sysex for other devices is ignored
+    pub fn handle_sysex(&mut self, data: &[U7]) -> Result<(), SysexError> {
+        let owl_header = [
+            owl_midi::MIDI_SYSEX_MANUFACTURER,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE,
+        ];
+        if data
+            .iter()
+            .zip(owl_header)
+            .any(|(&byte, expected)| u8::from(byte) as u32 != expected)
         {
-            if let Some(cmd) = data
-                .get(2)
-                .and_then(|&x| OpenWareMidiSysexCommand::try_from(u8::from(x) as isize).ok())
-            {
-                return self.handle_sysex_command(cmd, &data[3..]);
-            }
-            // TODO: handle unknown commands here
+            debug!(data = ?U7::data_to_bytes(data), "ignored sysex for other device");
+            return Ok(());
         }
-        Ok(())
+        if data.len() < 3 {
+            return Err(SysexError::NotEnoughData);
+        }
+        let cmd_id = u8::from(data[2]);
+        let cmd = OpenWareMidiSysexCommand::try_from(cmd_id as isize)
+            .map_err(|_| SysexError::UnknownCommand(cmd_id))?;
+        self.handle_sysex_command(cmd, &data[3..])
     }
     fn handle_sysex_command(
         &mut self,
         cmd: OpenWareMidiSysexCommand,
         data: &[U7],
-    ) -> Result<(), Error> {
-        let size = data.len();
+    ) -> Result<(), SysexError> {
+        let _span = info_span!("response", command = ?cmd).entered();
+        self.requests.response(cmd);
         match cmd {
             OpenWareMidiSysexCommand::SYSEX_FIRMWARE_VERSION => {
                 let firmware_version = String::from_utf8_lossy(U7::data_to_bytes(data));
                 self.firmware_version = Some(firmware_version.to_string());
-                self.log += format!("< SYSEX_FIRMWARE_VERSION = {firmware_version}\n").as_str();
+                info!(version = %firmware_version);
             }
             OpenWareMidiSysexCommand::SYSEX_PARAMETER_NAME_COMMAND => {
-                let param = PatchParameterId::try_from(u8::from(data[0]) as isize).unwrap();
-                let parameter_name =
-                    String::from_utf8_lossy(U7::data_to_bytes(&data[1..size - 1])).to_string();
-                self.log += format!("< SYSEX_PARAMETER_NAME_COMMAND {param:?}: {parameter_name}\n")
-                    .as_str();
+                let (&pid, name) = data.split_first().ok_or(SysexError::NotEnoughData)?;
+                let param = PatchParameterId::try_from(u8::from(pid) as isize)
+                    .map_err(|_| SysexError::InvalidParameter(u8::from(pid)))?;
+                let parameter_name = sysex_string(name);
+                info!(parameter = ?param, name = %parameter_name);
                 self.parameters
                     .insert(param, OwlParameter::new(parameter_name));
                 //self.parameters.insert(k, v)
             }
             OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND => {
-                let pos: usize = u8::from(data[0]).into();
+                let patch = Resource::from_sysex(data)?;
+                let pos = patch.id as usize;
+                info!(slot = pos, name = %patch.name);
                 if pos >= self.patches.len() {
                     self.patches.resize_with(pos + 1, || None);
                 }
-
-                let mut end = size - 1;
-                for (i, item) in data.iter().enumerate().take(size - 1).skip(1) {
-                    if *item == U7::MIN {
-                        end = i;
-                        break;
-                    }
-                }
-                let mut patch_size = 0;
-                patch_size.decode(&data[end + 1..end + 6]).unwrap();
-                let mut checksum = 0;
-                checksum.decode(&data[end + 6..end + 11]).unwrap();
-                self.patches[pos] = Some(Resource::new(
-                    pos as u8,
-                    String::from_utf8_lossy(U7::data_to_bytes(&data[1..end])).to_string(),
-                    patch_size,
-                    checksum,
-                ));
-                if !self.patches.is_empty() {
-                    if let Some(patch) = &self.patches[0] {
-                        self.patch_name = Some(patch.name.clone());
-                    } else {
-                        self.patch_name = None
-                    }
+                self.patches[pos] = Some(patch);
+                if let Some(patch) = &self.patches[0] {
+                    self.patch_name = Some(patch.name.clone());
+                } else {
+                    self.patch_name = None
                 }
-                self.log += format!(
-                    "< SYSEX_PATCH_NAME_COMMAND {pos} = {}\n",
-                    self.patches[pos].as_ref().unwrap().name
-                )
-                .as_str();
             }
             OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND => {
-                let mut pos: usize = u8::from(data[0]).into();
+                let mut resource = Resource::from_sysex(data)?;
                 if self.resources.is_empty() {
-                    self.resource_offset = pos;
+                    self.resource_offset = resource.id as usize;
                 }
-                pos -= self.resource_offset;
+                let pos = (resource.id as usize)
+                    .checked_sub(self.resource_offset)
+                    .ok_or(SysexError::InvalidSlot(resource.id))?;
+                resource.id = pos as u8;
+                info!(slot = pos, name = %resource.name);
                 if pos >= self.resources.len() {
                     self.resources.resize_with(pos + 1, || None);
                 }
-
-                let mut end = size - 1;
-                for (i, item) in data.iter().enumerate().take(size - 1).skip(1) {
-                    if *item == wmidi::U7::MIN {
-                        end = i;
-                        break;
-                    }
-                }
-                let mut resource_size = 0;
-                resource_size.decode(&data[end + 1..end + 6]).unwrap();
-                let mut checksum = 0;
-                checksum.decode(&data[end + 6..end + 11]).unwrap();
-                self.resources[pos] = Some(Resource::new(
-                    pos as u8,
-                    String::from_utf8_lossy(U7::data_to_bytes(&data[1..end])).to_string(),
-                    resource_size,
-                    checksum,
-                ));
-                self.log += format!(
-                    "< SYSEX_RESOURCE_NAME_COMMAND {pos} = {}\n",
-                    self.resources[pos].as_ref().unwrap().name
-                )
-                .as_str();
+                self.resources[pos] = Some(resource);
             }
             OpenWareMidiSysexCommand::SYSEX_PROGRAM_MESSAGE => {
-                let program_message = String::from_utf8_lossy(U7::data_to_bytes(&data[..size - 1]));
-                self.program_message = Some(program_message.to_string());
-                self.log += format!("< SYSEX_PROGRAM_MESSAGE = {program_message}\n").as_str();
+                let program_message = sysex_string(data);
+                info!(program_message = %program_message);
+                self.program_message = Some(program_message);
             }
             OpenWareMidiSysexCommand::SYSEX_PROGRAM_ERROR => {
-                let error_message = String::from_utf8_lossy(U7::data_to_bytes(&data[..size - 1]));
-                self.error_message = Some(error_message.to_string());
-                self.log += format!("< SYSEX_PROGRAM_ERROR = {error_message}\n").as_str();
+                let error_message = sysex_string(data);
+                warn!(error_message = %error_message);
+                self.error_message = Some(error_message);
             }
             OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS => {
-                let stats = String::from_utf8_lossy(U7::data_to_bytes(&data[..size - 1]));
-                self.program_stats = Some(stats.to_string());
-                self.log += format!("< SYSEX_PROGRAM_STATS {stats}\n").as_str();
+                let stats = sysex_string(data);
+                debug!(stats = %stats);
+                self.program_stats = Some(stats);
             }
             //cmd if OpenWareMidiSysexCommand::SYSEX_PARAMETER_NAME_COMMAND as u8 == cmd => {
             //}
             OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND => {
+                if data.len() < 2 {
+                    return Err(SysexError::NotEnoughData);
+                }
                 let command_int = (u8::from(data[0]) as isize) << 8 | u8::from(data[1]) as isize;
                 let command = SysexConfiguration::from(command_int);
-                let value_str =
-                    String::from_utf8_lossy(U7::data_to_bytes(&data[2..size])).to_string();
-                println!("{command:?} = {value_str}");
-                let result = i64::from_str_radix(value_str.as_str(), 16)
-                    .map(|x| x.to_string())
-                    .unwrap_or_else(|_err| {
-                        self.log += format!("! Error parsing {value_str} as hex\n").as_str();
-                        String::new()
-                    });
-                self.settings.insert(command, result);
-                self.log += format!("< SYSEX_CONFIGURATION_COMMAND {command:?}\n").as_str();
+                let value_str = sysex_string(&data[2..]);
+                match setting_value(&value_str) {
+                    Some(value) => {
+                        self.settings.insert(command, value);
+                        info!(setting = ?command, value);
+                    }
+                    None => warn!(setting = ?command, value = %value_str, "invalid hex value"),
+                }
             }
             OpenWareMidiSysexCommand::SYSEX_FIRMWARE_UPLOAD => {
                 let mut idx = 0;
-                if let Ok(_result) = idx.decode(data) {
-                    if idx == 0 {
-                        self.resource_data.reset();
-                    }
-                    self.log += format!("< SYSEX_FIRMWARE_UPLOAD #{idx}\n").as_str();
-                    //idx.decode(&data[4..9]).unwrap();
-                    //let decoded = SysexData::decode(&data[9..size - 1]).unwrap();
-                    self.resource_data.process_data(&data[5..])?
+                idx.decode(data)?;
+                if idx == 0 {
+                    self.resource_data.reset();
                 }
+                debug!(index = idx);
+                //idx.decode(&data[4..9]).unwrap();
+                //let decoded = SysexData::decode(&data[9..size - 1]).unwrap();
+                self.resource_data.process_data(&data[5..])?
             }
             _ => {
-                println!("Unhandled command: {cmd:?}")
+                warn!("unhandled command")
             }
         }
         Ok(())
     }
+    fn set_parameter_high_res(&mut self, number: u16, value: u16) {
+        match PatchParameterId::try_from(number as isize) {
+            Ok(pid) => {
+                debug!(parameter = pid.string_id(), value, "received NRPN");
+                self.parameters
+                    .entry(pid)
+                    .or_insert_with(|| OwlParameter::new(pid.string_id().into()))
+                    .set_high_res(value);
+            }
+            Err(_) => debug!(number, value, "received NRPN"),
+        }
+    }
     pub fn handle_midi_message(&mut self, midi_message: MidiMessage<'_>) {
         match midi_message {
             MidiMessage::ControlChange(_channel, function, value) => {
                 let cc = u8::from(function);
                 let value = u8::from(value);
-                //let control = OpenWareMidiControl::try_from(cc as isize);
-                if let Ok(pid) = PatchParameterId::try_from(cc as isize) {
-                    self.log += format!("< PARAMETER {:?} = {}\n", pid.string_id(), value).as_str();
+                if let Some(pid) = parameter_for_cc(cc) {
+                    debug!(parameter = pid.string_id(), value, "received parameter");
                     self.parameters
                         .entry(pid)
                         .and_modify(|p| p.midi_value = value)
@@ -306,13 +584,323 @@ impl OwlCommandProcessor {
                             new_param.midi_value = value;
                             new_param
                         });
+                } else if cc == PATCH_BUTTON_ON || cc == PATCH_BUTTON_OFF {
+                    let pressed = cc == PATCH_BUTTON_ON;
+                    debug!(button = %button_name(value), pressed, "received button");
+                    self.buttons.insert(value, pressed);
+                } else if NrpnDecoder::is_nrpn(cc) {
+                    let received = self.nrpn.receive(cc, value);
+                    if let Some((number, value)) = received {
+                        self.set_parameter_high_res(number, value);
+                    }
                 } else {
-                    self.log += format!("< CC{cc} = {value}\n").as_str();
+                    debug!(cc, value, "received CC");
                 }
             }
             _ => {
-                self.log += format!("< MIDI {midi_message:?}\n").as_str();
+                debug!(midi = ?midi_message, "received message");
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi_transport::{LoopbackTransport, RecordingTransport, ReplayTransport};
+    use std::sync::mpsc::channel;
+
+    fn owl_sysex(cmd: OpenWareMidiSysexCommand, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            cmd as u8,
+        ];
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn test_truncated_sysex() {
+        let mut processor = OwlCommandProcessor::new();
+        assert_eq!(processor.handle_sysex(&[]), Err(SysexError::NotEnoughData));
+        let data = U7::try_from_bytes(&[0x7d]).unwrap();
+        assert_eq!(processor.handle_sysex(data), Err(SysexError::NotEnoughData));
+        // Sysex from other manufacturers and devices isn't an error
+        for bytes in [&[0x43, 0x10, 0x4c][..], &[0x7d, 0x52, 0x01], &[0x7e]] {
+            let data = U7::try_from_bytes(bytes).unwrap();
+            assert_eq!(processor.handle_sysex(data), Ok(()));
+        }
+        for cmd in [
+            OpenWareMidiSysexCommand::SYSEX_PARAMETER_NAME_COMMAND,
+            OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND,
+            OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND,
+            OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_UPLOAD,
+        ] {
+            let bytes = owl_sysex(cmd, &[]);
+            let data = U7::try_from_bytes(&bytes).unwrap();
+            assert_eq!(processor.handle_sysex(data), Err(SysexError::NotEnoughData));
+        }
+        let bytes = owl_sysex(
+            OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND,
+            &[1, b'P', b'a', b't', b'c', b'h', 0, 0, 0],
+        );
+        let data = U7::try_from_bytes(&bytes).unwrap();
+        assert_eq!(processor.handle_sysex(data), Err(SysexError::NotEnoughData));
+        assert!(processor.patches.is_empty());
+    }
+
+    #[test]
+    fn test_preset_name() {
+        let mut processor = OwlCommandProcessor::new();
+        let bytes = owl_sysex(
+            OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND,
+            &[
+                0, b'W', b'a', b'v', b'e', 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0x2a,
+            ],
+        );
+        let data = U7::try_from_bytes(&bytes).unwrap();
+        assert_eq!(processor.handle_sysex(data), Ok(()));
+        assert_eq!(processor.patch_name, Some("Wave".to_string()));
+        assert_eq!(
+            processor.patches,
+            vec![Some(Resource::new(0, "Wave".to_string(), 256, 0x2a))]
+        );
+    }
+
+    #[test]
+    fn test_request_retry() {
+        let (sender, _receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        let mut processor = OwlCommandProcessor::new();
+        let command = OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS;
+        processor.request_settings(&mut transport, command).unwrap();
+        processor.poll_requests(&mut transport, 0.5);
+        processor.poll_requests(&mut transport, 1.5);
+        let request = vec![
+            0xb0,
+            owl_midi::OpenWareMidiControl::REQUEST_SETTINGS as u8,
+            command as u8,
+        ];
+        assert_eq!(transport.messages, vec![request.clone(), request]);
+    }
+
+    #[test]
+    fn test_send_setting() {
+        let (sender, _receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        let mut processor = OwlCommandProcessor::new();
+        let config = owl_midi::SYSEX_CONFIGURATIONS[0];
+        let code = config as isize;
+        processor.send_setting(&mut transport, config, 255).unwrap();
+        processor.send_setting(&mut transport, config, -16).unwrap();
+        let message = |value: &[u8]| {
+            let mut data = vec![
+                0xf0,
+                owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+                owl_midi::MIDI_SYSEX_OMNI_DEVICE as u8,
+                OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND as u8,
+                (code >> 8) as u8,
+                (code & 0xff) as u8,
+            ];
+            data.extend_from_slice(value);
+            data.push(0xf7);
+            data
+        };
+        assert_eq!(transport.messages, vec![message(b"ff"), message(b"-10")]);
+        assert_eq!(processor.settings.get(&config), Some(&-16));
+    }
+
+    #[test]
+    fn test_setting_value() {
+        assert_eq!(setting_value("2a"), Some(42));
+        assert_eq!(setting_value("ffffffff"), Some(-1));
+        assert_eq!(setting_value("-10"), Some(-16));
+        assert_eq!(setting_value("x"), None);
+
+        let mut processor = OwlCommandProcessor::new();
+        let config = owl_midi::SYSEX_CONFIGURATIONS[0];
+        let code = config as isize;
+        let mut payload = vec![(code >> 8) as u8, (code & 0xff) as u8];
+        payload.extend_from_slice(b"ffffff80");
+        let bytes = owl_sysex(
+            OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
+            &payload,
+        );
+        let data = U7::try_from_bytes(&bytes).unwrap();
+        assert_eq!(processor.handle_sysex(data), Ok(()));
+        assert_eq!(processor.settings.get(&config), Some(&-128));
+    }
+
+    #[test]
+    fn test_send_message() {
+        let (sender, _receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        let mut processor = OwlCommandProcessor::new();
+        let data = U7::try_from_bytes(&[0x7d, 0x01, 0x02, 0x03]).unwrap();
+        processor
+            .send_message(&mut transport, MidiMessage::SysEx(data))
+            .unwrap();
+        let program = MidiMessage::ProgramChange(Channel::Ch1, U7::try_from(5).unwrap());
+        processor.send_message(&mut transport, program).unwrap();
+        assert_eq!(
+            transport.messages,
+            vec![vec![0xf0, 0x7d, 0x01, 0x02, 0x03, 0xf7], vec![0xc0, 0x05]]
+        );
+        let command = OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND;
+        assert!(processor
+            .send_sysex_string(&mut transport, command, &[0x80])
+            .is_err());
+        assert_eq!(transport.messages.len(), 2);
+    }
+
+    #[test]
+    fn test_slot_commands() {
+        let (sender, _receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        let mut processor = OwlCommandProcessor::new();
+        processor.load_patch(&mut transport, 3).unwrap();
+        processor.erase(&mut transport, Some(42)).unwrap();
+        processor.erase(&mut transport, None).unwrap();
+        processor.request_download(&mut transport, 41).unwrap();
+        assert!(processor.load_patch(&mut transport, 0x80).is_err());
+        let sysex = |command: OpenWareMidiSysexCommand, payload: &[u8]| {
+            let mut data = vec![
+                0xf0,
+                owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+                owl_midi::MIDI_SYSEX_OMNI_DEVICE as u8,
+                command as u8,
+            ];
+            data.extend_from_slice(payload);
+            data.push(0xf7);
+            data
+        };
+        assert_eq!(
+            transport.messages,
+            vec![
+                vec![0xc0, 3],
+                sysex(
+                    OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE,
+                    &[0, 0, 0, 0, 42]
+                ),
+                sysex(OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE, &[]),
+                sysex(
+                    OpenWareMidiSysexCommand::SYSEX_FIRMWARE_SEND,
+                    &[0, 0, 0, 0, 42]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sync_parameters() {
+        let (sender, _receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        let mut processor = OwlCommandProcessor::new();
+        let pid = PatchParameterId::try_from(0).unwrap();
+        processor
+            .parameters
+            .insert(pid, OwlParameter::new("Frequency".to_string()));
+        processor.sync_parameters(&mut transport).unwrap();
+        assert!(transport.messages.is_empty());
+        processor.parameters.get_mut(&pid).unwrap().value = 1.0;
+        processor.sync_parameters(&mut transport).unwrap();
+        processor.sync_parameters(&mut transport).unwrap();
+        assert_eq!(
+            transport.messages,
+            vec![vec![0xb0, OpenWareMidiControl::from(pid) as u8, 127]]
+        );
+    }
+
+    #[test]
+    fn test_parameter_messages() {
+        let (sender, _receiver) = channel();
+        let mut transport = RecordingTransport::new(LoopbackTransport::new(sender));
+        let mut processor = OwlCommandProcessor::new();
+        let cc = |cc: u8, value: u8| {
+            MidiMessage::ControlChange(
+                Channel::Ch1,
+                ControlFunction(U7::try_from(cc).unwrap()),
+                U7::try_from(value).unwrap(),
+            )
+        };
+        let pid = PatchParameterId::try_from(1).unwrap();
+        processor.handle_midi_message(cc(OpenWareMidiControl::from(pid) as u8, 127));
+        assert_eq!(processor.parameters[&pid].midi_value, 127);
+
+        processor.handle_midi_message(cc(99, 0));
+        processor.handle_midi_message(cc(98, 1));
+        processor.handle_midi_message(cc(6, 0x7f));
+        processor.handle_midi_message(cc(38, 0x7f));
+        assert_eq!(processor.parameters[&pid].value, 1.0);
+        assert!(!processor.parameters.get_mut(&pid).unwrap().sync());
+
+        processor.handle_midi_message(cc(PATCH_BUTTON_ON, 4));
+        assert_eq!(processor.buttons.get(&4), Some(&true));
+        processor.set_button(&mut transport, 4, false).unwrap();
+        assert_eq!(processor.buttons.get(&4), Some(&false));
+        assert_eq!(transport.messages, vec![vec![0xb0, PATCH_BUTTON_OFF, 4]]);
+    }
+
+    #[test]
+    fn test_send_error() {
+        let (sender, _receiver) = channel();
+        let mut transport = ReplayTransport::new(sender);
+        let mut processor = OwlCommandProcessor::new();
+        assert!(processor
+            .send_sysex_command(
+                &mut transport,
+                OpenWareMidiSysexCommand::SYSEX_SETTINGS_STORE
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let command = OpenWareMidiSysexCommand::SYSEX_PROGRAM_MESSAGE;
+        let mut response = vec![0xf0];
+        response.extend(owl_sysex(command, b"OK"));
+        response.push(0xf7);
+        let (sender, receiver) = channel();
+        let mut transport = ReplayTransport::new(sender).expect(
+            &[
+                0xb0,
+                owl_midi::OpenWareMidiControl::REQUEST_SETTINGS as u8,
+                command as u8,
+            ],
+            &[&response],
+        );
+        let mut processor = OwlCommandProcessor::new();
+        processor.request_settings(&mut transport, command).unwrap();
+        assert!(transport.is_finished());
+        let frame = receiver.try_recv().unwrap();
+        if let Ok(MidiMessage::SysEx(data)) = MidiMessage::try_from(frame.data.as_slice()) {
+            processor.handle_sysex(data).unwrap();
+        }
+        assert_eq!(processor.program_message.as_deref(), Some("OK"));
+    }
+
+    #[test]
+    fn test_arbitrary_sysex() {
+        // Feed pseudo-random frames to every command handler, none of them may panic
+        let mut seed = 0x1234_5678u32;
+        let mut next = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let mut processor = OwlCommandProcessor::new();
+        for _ in 0..10000 {
+            let len = (next() % 48) as usize;
+            let mut bytes = vec![
+                owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+                owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            ];
+            bytes.extend((0..len).map(|_| (next() & 0x7f) as u8));
+            let data = U7::try_from_bytes(&bytes).unwrap();
+            let _ = processor.handle_sysex(data);
+        }
+    }
+}
@@ -0,0 +1,486 @@
+//! Emulated OWL device that answers host commands, used for testing without hardware.
+use crate::midi_stream::MidiFrame;
+use crate::midi_transport::{MidiTransport, TransportError};
+use owl_midi::{
+    OpenWareMidiControl, OpenWareMidiSysexCommand, PatchParameterId, SysexConfiguration,
+    SYSEX_CONFIGURATIONS,
+};
+use std::collections::HashMap;
+use std::sync::mpsc::Sender;
+use wmidi::{MidiMessage, U7};
+
+use super::{
+    crc32::Crc32,
+    resources::{encode_upload, resource_from_header, ResourceData, ResourceState},
+    sysex::SysexData,
+};
+
+/// Port name reported for emulated device
+pub const EMULATOR_NAME: &str = "OWL-EMULATOR";
+/// Number of patch slots, patches use slots starting from 1
+pub const EMULATOR_PATCH_SLOTS: usize = 40;
+/// Resources are stored in slots after patches
+pub const RESOURCE_SLOT_OFFSET: usize = EMULATOR_PATCH_SLOTS + 1;
+// Slot numbers are sent as a single 7-bit value in name lists
+const MAX_SLOT: usize = 0x7f;
+
+/// Patch or resource stored on emulated device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EmulatedResource {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+impl EmulatedResource {
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        EmulatedResource {
+            name: name.to_string(),
+            data,
+        }
+    }
+    // Name list entry: slot, null-terminated name, size and checksum
+    fn name_entry(&self, slot: usize) -> Vec<u8> {
+        let mut entry = vec![slot as u8];
+        entry.extend(self.name.bytes().filter(|&b| b.is_ascii() && b != 0));
+        entry.push(0);
+        let mut buf = [U7::MIN; 10];
+        (self.data.len() as u32).encode(&mut buf[..5]).unwrap();
+        Crc32::new()
+            .update(&self.data)
+            .crc
+            .encode(&mut buf[5..])
+            .unwrap();
+        entry.extend_from_slice(U7::data_to_bytes(&buf));
+        entry
+    }
+}
+
+/// Software OWL that answers host requests the way firmware does.
+///
+/// Messages sent to the emulator are handled immediately, responses are sent as complete
+/// MIDI frames to the same channel that receives messages from an input port.
+pub struct OwlEmulator {
+    pub firmware_version: String,
+    /// Stored patches, slot number is index + 1
+    pub patches: Vec<Option<EmulatedResource>>,
+    /// Stored resources, slot number is index + `RESOURCE_SLOT_OFFSET`
+    pub resources: Vec<Option<EmulatedResource>>,
+    /// Parameter names sent when a patch is loaded
+    pub parameter_names: Vec<(PatchParameterId, String)>,
+    /// Last value received for every CC number
+    pub controls: HashMap<u8, u8>,
+    pub settings: HashMap<SysexConfiguration, i64>,
+    /// Slot of currently loaded patch
+    pub program: usize,
+    upload: ResourceData,
+    sender: Sender<MidiFrame>,
+}
+
+impl OwlEmulator {
+    pub fn new(sender: Sender<MidiFrame>) -> Self {
+        OwlEmulator {
+            firmware_version: "OWL-EMULATOR v22.5.0".to_string(),
+            patches: vec![
+                Some(EmulatedResource::new("Wavetable Oscillator", vec![0; 1024])),
+                Some(EmulatedResource::new("Morphing Filter", vec![0; 2048])),
+            ],
+            resources: vec![Some(EmulatedResource::new(
+                "wavetable.wav",
+                (0..256u32).map(|x| x as u8).collect(),
+            ))],
+            parameter_names: [(0, "Frequency"), (1, "Morph")]
+                .iter()
+                .map(|&(pid, name)| (PatchParameterId::try_from(pid).unwrap(), name.to_string()))
+                .collect(),
+            controls: HashMap::new(),
+            settings: Self::default_settings(),
+            program: 1,
+            upload: ResourceData::new(),
+            sender,
+        }
+    }
+    fn default_settings() -> HashMap<SysexConfiguration, i64> {
+        SYSEX_CONFIGURATIONS
+            .iter()
+            .map(|&config| (config, 0))
+            .collect()
+    }
+    /// Handles a complete MIDI message sent by host
+    pub fn receive(&mut self, message: &[u8]) {
+        match MidiMessage::try_from(message) {
+            Ok(MidiMessage::ControlChange(_, function, value)) => {
+                let cc = u8::from(function);
+                let value = u8::from(value);
+                if cc == OpenWareMidiControl::REQUEST_SETTINGS as u8 {
+                    if let Ok(command) = OpenWareMidiSysexCommand::try_from(value as isize) {
+                        self.send_settings(command);
+                    }
+                } else {
+                    self.controls.insert(cc, value);
+                }
+            }
+            Ok(MidiMessage::ProgramChange(_, program)) => {
+                self.program = u8::from(program) as usize;
+                self.send_program();
+            }
+            Ok(MidiMessage::SysEx(data)) => self.handle_sysex(data),
+            _ => {}
+        }
+    }
+    fn current_patch(&self) -> Option<&EmulatedResource> {
+        self.program
+            .checked_sub(1)
+            .and_then(|i| self.patches.get(i))
+            .and_then(|patch| patch.as_ref())
+    }
+    fn send_sysex(&self, command: OpenWareMidiSysexCommand, payload: &[u8]) {
+        let mut data = vec![
+            0xf0,
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            command as u8,
+        ];
+        data.extend_from_slice(payload);
+        data.push(0xf7);
+        // Receiver is gone if session was closed
+        let _ = self.sender.send(MidiFrame::new(0, data));
+    }
+    fn send_settings(&self, command: OpenWareMidiSysexCommand) {
+        match command {
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_VERSION => {
+                self.send_sysex(command, self.firmware_version.as_bytes());
+            }
+            OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND => {
+                // Slot 0 is the patch that is currently running
+                if let Some(patch) = self.current_patch() {
+                    self.send_sysex(command, &patch.name_entry(0));
+                }
+                for (i, patch) in self.patches.iter().enumerate() {
+                    if let Some(patch) = patch {
+                        self.send_sysex(command, &patch.name_entry(i + 1));
+                    }
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND => {
+                for (i, resource) in self.resources.iter().enumerate() {
+                    if let Some(resource) = resource {
+                        self.send_sysex(command, &resource.name_entry(i + RESOURCE_SLOT_OFFSET));
+                    }
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_PARAMETER_NAME_COMMAND => self.send_parameter_names(),
+            OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND => {
+                for &config in SYSEX_CONFIGURATIONS.iter() {
+                    if let Some(&value) = self.settings.get(&config) {
+                        let code = config as isize;
+                        let mut payload = vec![(code >> 8) as u8, (code & 0xff) as u8];
+                        // Device sends 32 bit words, negative values in two's complement
+                        payload.extend_from_slice(format!("{:x}", value as u32).as_bytes());
+                        self.send_sysex(command, &payload);
+                    }
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS => {
+                self.send_sysex(command, b"CPU: 25% Heap: 4096");
+            }
+            _ => {}
+        }
+    }
+    fn send_parameter_names(&self) {
+        for (pid, name) in self.parameter_names.iter() {
+            let mut payload = vec![*pid as u8];
+            payload.extend(name.bytes().filter(|&b| b.is_ascii() && b != 0));
+            payload.push(0);
+            self.send_sysex(
+                OpenWareMidiSysexCommand::SYSEX_PARAMETER_NAME_COMMAND,
+                &payload,
+            );
+        }
+    }
+    // Device announces patch name and parameters after loading a patch
+    fn send_program(&self) {
+        if let Some(patch) = self.current_patch() {
+            self.send_sysex(
+                OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND,
+                &patch.name_entry(0),
+            );
+            self.send_parameter_names();
+        }
+    }
+    fn handle_sysex(&mut self, data: &[U7]) {
+        let bytes = U7::data_to_bytes(data);
+        if bytes.len() < 3 || bytes[0] as u32 != owl_midi::MIDI_SYSEX_MANUFACTURER {
+            return;
+        }
+        let command = match OpenWareMidiSysexCommand::try_from(bytes[2] as isize) {
+            Ok(command) => command,
+            Err(_) => return,
+        };
+        let payload = &data[3..];
+        let slot = || {
+            let mut slot = 0u32;
+            slot.decode(payload).ok().map(|_| slot as usize)
+        };
+        match command {
+            OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND if bytes.len() > 5 => {
+                let code = (bytes[3] as isize) << 8 | bytes[4] as isize;
+                let value_str = String::from_utf8_lossy(&bytes[5..]);
+                if let Ok(value) = i64::from_str_radix(value_str.trim_end_matches('\0'), 16) {
+                    self.settings.insert(SysexConfiguration::from(code), value);
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_SETTINGS_RESET => {
+                self.settings = Self::default_settings();
+            }
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_UPLOAD => {
+                if let Some(index) = slot() {
+                    if index == 0 {
+                        self.upload.reset();
+                    }
+                    let _ = self.upload.process_data(&payload[5..]);
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_STORE => {
+                if let Some(slot) = slot() {
+                    self.store(slot);
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_SEND => {
+                // Host sends resource slot + 1
+                if let Some(slot) = slot().and_then(|slot| slot.checked_sub(1)) {
+                    self.send_resource(slot);
+                }
+            }
+            OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE => match slot() {
+                Some(slot) => self.erase(slot),
+                None if payload.is_empty() => {
+                    self.patches.clear();
+                    self.resources.clear();
+                }
+                None => {}
+            },
+            _ => {}
+        }
+    }
+    // Index in patch or resource list for a slot
+    fn slot_index(&mut self, slot: usize) -> Option<(&mut Vec<Option<EmulatedResource>>, usize)> {
+        match slot {
+            0 => None,
+            slot if slot < RESOURCE_SLOT_OFFSET => Some((&mut self.patches, slot - 1)),
+            slot if slot <= MAX_SLOT => Some((&mut self.resources, slot - RESOURCE_SLOT_OFFSET)),
+            _ => None,
+        }
+    }
+    fn store(&mut self, slot: usize) {
+        if self.upload.state != ResourceState::Success {
+            return;
+        }
+        let data = std::mem::take(&mut self.upload.data);
+        self.upload.reset();
+        let (name, data) = resource_from_header(&data)
+            .map(|(name, payload)| (name, payload.to_vec()))
+            .unwrap_or_else(|| (format!("Slot {slot}"), data));
+        if let Some((slots, index)) = self.slot_index(slot) {
+            if index >= slots.len() {
+                slots.resize(index + 1, None);
+            }
+            slots[index] = Some(EmulatedResource::new(&name, data));
+        }
+    }
+    fn erase(&mut self, slot: usize) {
+        if let Some((slots, index)) = self.slot_index(slot) {
+            if let Some(entry) = slots.get_mut(index) {
+                *entry = None;
+            }
+        }
+    }
+    fn send_resource(&self, slot: usize) {
+        let resource = slot
+            .checked_sub(RESOURCE_SLOT_OFFSET)
+            .and_then(|i| self.resources.get(i))
+            .and_then(|resource| resource.as_ref());
+        if let Some(resource) = resource {
+            for packet in encode_upload(&resource.data) {
+                self.send_sysex(
+                    OpenWareMidiSysexCommand::SYSEX_FIRMWARE_UPLOAD,
+                    U7::data_to_bytes(&packet),
+                );
+            }
+        }
+    }
+}
+
+impl MidiTransport for OwlEmulator {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.receive(message);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::owl_control::command_processor::{DeviceIdentity, OwlCommandProcessor};
+    use std::sync::mpsc::{channel, Receiver};
+
+    fn connect() -> (OwlEmulator, Receiver<MidiFrame>) {
+        let (sender, receiver) = channel();
+        (OwlEmulator::new(sender), receiver)
+    }
+
+    fn process(processor: &mut OwlCommandProcessor, receiver: &Receiver<MidiFrame>) {
+        while let Ok(frame) = receiver.try_recv() {
+            match MidiMessage::try_from(frame.data.as_slice()) {
+                Ok(MidiMessage::SysEx(data)) => processor.handle_sysex(data).unwrap(),
+                Ok(message) => processor.handle_midi_message(message),
+                Err(err) => panic!("Invalid frame {:?}: {err:?}", frame.data),
+            }
+        }
+    }
+
+    #[test]
+    fn test_identify() {
+        let (mut connection, receiver) = connect();
+        let mut processor = OwlCommandProcessor::new();
+        processor.identify(&mut connection).unwrap();
+        assert_eq!(processor.identity(), DeviceIdentity::Probing);
+        process(&mut processor, &receiver);
+        assert_eq!(
+            processor.identity(),
+            DeviceIdentity::Owl("OWL-EMULATOR v22.5.0")
+        );
+    }
+
+    #[test]
+    fn test_name_lists() {
+        let (mut connection, receiver) = connect();
+        let mut processor = OwlCommandProcessor::new();
+        processor
+            .request_settings(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND,
+            )
+            .unwrap();
+        processor
+            .request_settings(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND,
+            )
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(
+            processor.patch_name.as_deref(),
+            Some("Wavetable Oscillator")
+        );
+        assert_eq!(processor.patches.len(), 3);
+        assert_eq!(
+            processor.patches[2].as_ref().map(|p| p.name.as_str()),
+            Some("Morphing Filter")
+        );
+        assert_eq!(processor.resource_offset, RESOURCE_SLOT_OFFSET);
+        assert_eq!(processor.resources.len(), 1);
+        assert_eq!(processor.resources[0].as_ref().unwrap().size, 256);
+    }
+
+    #[test]
+    fn test_settings() {
+        let (mut connection, receiver) = connect();
+        let mut processor = OwlCommandProcessor::new();
+        let config = SYSEX_CONFIGURATIONS[0];
+        let code = config as isize;
+        processor
+            .send_sysex_string(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
+                &[(code >> 8) as u8, (code & 0xff) as u8, b'2', b'a'],
+            )
+            .unwrap();
+        processor
+            .request_settings(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
+            )
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(processor.settings.get(&config), Some(&42));
+
+        processor.send_setting(&mut connection, config, -1).unwrap();
+        processor.settings.clear();
+        processor
+            .request_settings(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND,
+            )
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(processor.settings.get(&config), Some(&-1));
+    }
+
+    #[test]
+    fn test_program_change() {
+        let (mut connection, receiver) = connect();
+        let mut processor = OwlCommandProcessor::new();
+        processor
+            .send_message(
+                &mut connection,
+                MidiMessage::ProgramChange(wmidi::Channel::Ch1, U7::try_from(2).unwrap()),
+            )
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(processor.patch_name.as_deref(), Some("Morphing Filter"));
+        let pid = PatchParameterId::try_from(1).unwrap();
+        assert_eq!(processor.parameters[&pid].name, "Morph");
+    }
+
+    #[test]
+    fn test_upload_and_download() {
+        let (mut connection, receiver) = connect();
+        let mut processor = OwlCommandProcessor::new();
+        let data = (0..1000u32).map(|x| (x * 3) as u8).collect::<Vec<_>>();
+        // Upload needs resource list to find a free slot
+        assert!(processor
+            .send_resource(&mut connection, "test.wav", &data)
+            .is_err());
+        let command = OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND;
+        processor
+            .request_settings(&mut connection, command)
+            .unwrap();
+        process(&mut processor, &receiver);
+        processor
+            .send_resource(&mut connection, "test.wav", &data)
+            .unwrap();
+        processor
+            .request_settings(&mut connection, command)
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(processor.resources.len(), 2);
+        let resource = processor.resources[1].clone().unwrap();
+        assert_eq!(resource.name, "test.wav");
+        assert_eq!(resource.size, 1000);
+
+        let slot = 1 + processor.resource_offset as u8;
+        processor
+            .send_sysex_string(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_FIRMWARE_SEND,
+                &[0, 0, 0, 0, slot + 1],
+            )
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(processor.resource_data.state, ResourceState::Success);
+        assert_eq!(processor.resource_data.data, data);
+
+        processor
+            .send_sysex_string(
+                &mut connection,
+                OpenWareMidiSysexCommand::SYSEX_FLASH_ERASE,
+                &[0, 0, 0, 0, slot],
+            )
+            .unwrap();
+        processor
+            .request_settings(&mut connection, command)
+            .unwrap();
+        process(&mut processor, &receiver);
+        assert_eq!(processor.resources.len(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+//! Mappings from external controller CCs to patch parameters, learned from received messages.
+use super::parameter::parameter_id;
+use super::patch_collection::PatchCollection;
+use owl_midi::PatchParameterId;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Controller has picked up parameter value when it's this close to it
+const PICKUP_DISTANCE: f32 = 1.5 / 127.0;
+
+/// Channel, CC number and value of a control change message
+pub fn control_change(data: &[u8]) -> Option<(u8, u8, u8)> {
+    match *data {
+        [status, cc, value] if status & 0xf0 == 0xb0 => Some((status & 0x0f, cc, value)),
+        _ => None,
+    }
+}
+
+/// Controller CC received on an input port
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ControlSource {
+    pub port: String,
+    /// Zero based MIDI channel
+    pub channel: u8,
+    pub cc: u8,
+}
+
+impl fmt::Display for ControlSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ch{} CC{}", self.port, self.channel + 1, self.cc)
+    }
+}
+
+/// Forwards a controller CC to a parameter
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ControllerMapping {
+    pub source: ControlSource,
+    /// Parameter string ID, e.g. "A" or "AA"
+    pub parameter: String,
+    /// Parameter value for lowest controller value
+    pub min: f32,
+    /// Parameter value for highest controller value
+    pub max: f32,
+    pub invert: bool,
+    /// Controller changes parameter only after it reaches current value, so it doesn't jump
+    pub soft_takeover: bool,
+    #[serde(skip)]
+    picked_up: bool,
+    /// Last value from controller
+    #[serde(skip)]
+    received: Option<f32>,
+    /// Last value set by this mapping
+    #[serde(skip)]
+    sent: Option<f32>,
+}
+
+impl ControllerMapping {
+    pub fn new(source: ControlSource, pid: PatchParameterId) -> Self {
+        ControllerMapping {
+            source,
+            parameter: pid.string_id().to_string(),
+            min: 0.0,
+            max: 1.0,
+            invert: false,
+            soft_takeover: true,
+            picked_up: false,
+            received: None,
+            sent: None,
+        }
+    }
+    pub fn pid(&self) -> Option<PatchParameterId> {
+        parameter_id(&self.parameter)
+    }
+    /// Parameter value for a controller value
+    pub fn scale(&self, value: u8) -> f32 {
+        let position = value.min(127) as f32 / 127.0;
+        let position = if self.invert {
+            1.0 - position
+        } else {
+            position
+        };
+        (self.min + (self.max - self.min) * position).clamp(0.0, 1.0)
+    }
+    /// Returns parameter value for received controller value, or None while controller
+    /// hasn't picked up current parameter value yet
+    pub fn receive(&mut self, value: u8, current: f32) -> Option<f32> {
+        let target = self.scale(value);
+        let previous = self.received.replace(target);
+        if self.soft_takeover {
+            // Parameter was changed elsewhere, so controller has to pick it up again
+            if self
+                .sent
+                .map_or(true, |sent| (sent - current).abs() > PICKUP_DISTANCE)
+            {
+                self.picked_up = false;
+            }
+            if !self.picked_up {
+                let crossed = previous.map_or(false, |previous| {
+                    (previous - current) * (target - current) <= 0.0
+                });
+                if !crossed && (target - current).abs() > PICKUP_DISTANCE {
+                    return None;
+                }
+                self.picked_up = true;
+            }
+        }
+        self.sent = Some(target);
+        Some(target)
+    }
+    /// True until controller has picked up parameter value with soft takeover
+    pub fn is_waiting(&self) -> bool {
+        self.soft_takeover && !self.picked_up
+    }
+}
+
+/// Controller mappings for every patch, stored by patch name
+pub type ControllerMappings = PatchCollection<ControllerMapping>;
+
+impl ControllerMappings {
+    pub fn mappings(&self, patch: &str) -> &[ControllerMapping] {
+        self.get(patch)
+    }
+    pub fn mappings_mut(&mut self, patch: &str) -> &mut [ControllerMapping] {
+        self.get_mut(patch)
+    }
+    /// Sorted names of input ports used by mappings of a patch
+    pub fn ports(&self, patch: &str) -> Vec<String> {
+        let mut ports: Vec<String> = self
+            .mappings(patch)
+            .iter()
+            .map(|mapping| mapping.source.port.clone())
+            .collect();
+        ports.sort();
+        ports.dedup();
+        ports
+    }
+    /// Maps source to parameter, replacing existing mapping of the same source
+    pub fn learn(&mut self, patch: &str, source: ControlSource, pid: PatchParameterId) {
+        let mapping = ControllerMapping::new(source.clone(), pid);
+        self.push(patch, mapping, |existing| existing.source == source);
+    }
+    /// Applies controller value to mapped parameters, returns their new values
+    pub fn receive(
+        &mut self,
+        patch: &str,
+        source: &ControlSource,
+        value: u8,
+        mut current: impl FnMut(PatchParameterId) -> Option<f32>,
+    ) -> Vec<(PatchParameterId, f32)> {
+        self.mappings_mut(patch)
+            .iter_mut()
+            .filter(|mapping| mapping.source == *source)
+            .filter_map(|mapping| {
+                let pid = mapping.pid()?;
+                let value = mapping.receive(value, current(pid)?)?;
+                Some((pid, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn source(cc: u8) -> ControlSource {
+        ControlSource {
+            port: "Controller".to_string(),
+            channel: 0,
+            cc,
+        }
+    }
+
+    #[test]
+    fn test_scale() {
+        let pid = PatchParameterId::try_from(0).unwrap();
+        let mut mapping = ControllerMapping::new(source(1), pid);
+        mapping.min = 0.2;
+        mapping.max = 0.6;
+        assert_eq!(mapping.scale(0), 0.2);
+        assert_eq!(mapping.scale(127), 0.6);
+        mapping.invert = true;
+        assert_eq!(mapping.scale(0), 0.6);
+        assert_eq!(control_change(&[0xb2, 1, 64]), Some((2, 1, 64)));
+        assert_eq!(control_change(&[0x92, 1, 64]), None);
+    }
+
+    #[test]
+    fn test_soft_takeover() {
+        let pid = PatchParameterId::try_from(0).unwrap();
+        let mut mapping = ControllerMapping::new(source(1), pid);
+        // Controller is below parameter value until it crosses it
+        assert_eq!(mapping.receive(0, 0.5), None);
+        assert!(mapping.is_waiting());
+        assert_eq!(mapping.receive(32, 0.5), None);
+        assert_eq!(mapping.receive(127, 0.5), Some(1.0));
+        assert_eq!(mapping.receive(0, 1.0), Some(0.0));
+        // Value changed elsewhere, controller has to reach it again
+        assert_eq!(mapping.receive(10, 0.5), None);
+        assert_eq!(mapping.receive(64, 0.5), Some(64.0 / 127.0));
+
+        mapping.soft_takeover = false;
+        assert_eq!(mapping.receive(0, 1.0), Some(0.0));
+    }
+
+    #[test]
+    fn test_mappings() {
+        let pid = PatchParameterId::try_from(1).unwrap();
+        let mut library = ControllerMappings::new();
+        library.learn("Patch", source(1), pid);
+        library.learn("Patch", source(2), pid);
+        library.learn("Patch", source(1), PatchParameterId::try_from(0).unwrap());
+        assert_eq!(library.mappings("Patch").len(), 2);
+        assert_eq!(library.mappings("Patch")[1].parameter, "A");
+        assert!(library.mappings("Other").is_empty());
+        assert_eq!(library.ports("Patch"), vec!["Controller".to_string()]);
+        assert!(library.ports("Other").is_empty());
+
+        library.mappings_mut("Patch")[0].soft_takeover = false;
+        let values = library.receive("Patch", &source(2), 127, |_| Some(0.0));
+        assert_eq!(values, vec![(pid, 1.0)]);
+        assert!(library
+            .receive("Other", &source(2), 127, |_| Some(0.0))
+            .is_empty());
+
+        let json = serde_json::to_string(&library).unwrap();
+        let loaded: ControllerMappings = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded.mappings("Patch")[0].source, source(2));
+        library.remove("Patch", 0);
+        library.remove("Patch", 0);
+        assert!(library.patches.is_empty());
+    }
+}
@@ -0,0 +1,438 @@
+//! Software modulators (LFOs, envelopes and random walks) that send parameter CCs to device.
+use crate::midi_transport::{MidiTransport, TransportError};
+use owl_midi::{OpenWareMidiControl, PatchParameterId};
+use std::collections::HashMap;
+use std::f64::consts::TAU;
+#[cfg(not(target_arch = "wasm32"))]
+use {
+    crate::midi_transport::SharedTransport,
+    std::sync::atomic::{AtomicBool, Ordering},
+    std::sync::{Arc, Mutex},
+    std::thread::{self, JoinHandle},
+    std::time::{Duration, Instant},
+};
+
+/// Default maximum number of CCs per second for each modulated parameter
+pub const DEFAULT_SEND_RATE: f64 = 50.0;
+/// Seconds between modulator updates on timer thread
+pub const TICK_INTERVAL: f64 = 0.005;
+/// Largest random walk step, relative to full modulation range
+const WALK_STEP: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfoShape {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    SampleAndHold,
+}
+
+impl LfoShape {
+    pub const ALL: [LfoShape; 5] = [
+        LfoShape::Sine,
+        LfoShape::Triangle,
+        LfoShape::Saw,
+        LfoShape::Square,
+        LfoShape::SampleAndHold,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            LfoShape::Sine => "Sine",
+            LfoShape::Triangle => "Triangle",
+            LfoShape::Saw => "Saw",
+            LfoShape::Square => "Square",
+            LfoShape::SampleAndHold => "S&H",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ModulatorKind {
+    Lfo(LfoShape),
+    /// Runs while gate is held, times are in seconds and sustain is a level
+    Envelope {
+        attack: f64,
+        decay: f64,
+        sustain: f32,
+        release: f64,
+    },
+    /// Random steps at modulator rate
+    RandomWalk,
+}
+
+impl ModulatorKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModulatorKind::Lfo(shape) => shape.name(),
+            ModulatorKind::Envelope { .. } => "Envelope",
+            ModulatorKind::RandomWalk => "Random walk",
+        }
+    }
+    pub fn envelope() -> Self {
+        ModulatorKind::Envelope {
+            attack: 0.1,
+            decay: 0.2,
+            sustain: 0.7,
+            release: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum EnvelopeStage {
+    Idle,
+    Attack,
+    Decay,
+    Sustain,
+    Release,
+}
+
+/// Modulation source assigned to a patch parameter
+#[derive(Clone, Debug)]
+pub struct Modulator {
+    pub pid: PatchParameterId,
+    pub kind: ModulatorKind,
+    /// Cycles or random steps per second
+    pub rate: f64,
+    /// Range of modulation, LFOs swing by half of it around center
+    pub depth: f32,
+    /// Value that LFOs swing around and envelopes start from
+    pub center: f32,
+    pub enabled: bool,
+    /// Envelope runs while gate is held
+    pub gate: bool,
+    phase: f64,
+    /// Sample-and-hold value or random walk position, -1 to 1
+    held: f32,
+    level: f32,
+    stage: EnvelopeStage,
+    random: u32,
+    output: f32,
+}
+
+impl Modulator {
+    pub fn new(pid: PatchParameterId, kind: ModulatorKind, center: f32) -> Self {
+        Modulator {
+            pid,
+            kind,
+            rate: 1.0,
+            depth: 0.5,
+            center,
+            enabled: true,
+            gate: false,
+            phase: 0.0,
+            held: 0.0,
+            level: 0.0,
+            stage: EnvelopeStage::Idle,
+            random: 0x2545_f491 ^ OpenWareMidiControl::from(pid) as u32,
+            output: center,
+        }
+    }
+    /// Last computed parameter value
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+    // Xorshift noise between -1 and 1
+    fn next_random(&mut self) -> f32 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+    fn advance_envelope(&mut self, dt: f64, attack: f64, decay: f64, sustain: f32, release: f64) {
+        let step = |time: f64| {
+            if time > 0.0 {
+                (dt / time) as f32
+            } else {
+                1.0
+            }
+        };
+        if self.gate && matches!(self.stage, EnvelopeStage::Idle | EnvelopeStage::Release) {
+            self.stage = EnvelopeStage::Attack;
+        } else if !self.gate && self.stage != EnvelopeStage::Idle {
+            self.stage = EnvelopeStage::Release;
+        }
+        match self.stage {
+            EnvelopeStage::Attack => {
+                self.level += step(attack);
+                if self.level >= 1.0 {
+                    self.level = 1.0;
+                    self.stage = EnvelopeStage::Decay;
+                }
+            }
+            EnvelopeStage::Decay => {
+                self.level -= step(decay) * (1.0 - sustain);
+                if self.level <= sustain {
+                    self.level = sustain;
+                    self.stage = EnvelopeStage::Sustain;
+                }
+            }
+            EnvelopeStage::Sustain => self.level = sustain,
+            EnvelopeStage::Release => {
+                self.level -= step(release);
+                if self.level <= 0.0 {
+                    self.level = 0.0;
+                    self.stage = EnvelopeStage::Idle;
+                }
+            }
+            EnvelopeStage::Idle => {}
+        }
+    }
+    /// Moves modulator forward by a number of seconds and returns new parameter value
+    pub fn advance(&mut self, dt: f64) -> f32 {
+        self.phase += dt * self.rate.max(0.0);
+        let cycle_done = self.phase >= 1.0;
+        self.phase = self.phase.fract();
+        let phase = self.phase;
+        let offset = match self.kind {
+            ModulatorKind::Lfo(shape) => {
+                let value = match shape {
+                    LfoShape::Sine => (phase * TAU).sin() as f32,
+                    LfoShape::Triangle => 1.0 - 4.0 * (phase as f32 - 0.5).abs(),
+                    LfoShape::Saw => phase as f32 * 2.0 - 1.0,
+                    LfoShape::Square if phase < 0.5 => 1.0,
+                    LfoShape::Square => -1.0,
+                    LfoShape::SampleAndHold => {
+                        if cycle_done {
+                            self.held = self.next_random();
+                        }
+                        self.held
+                    }
+                };
+                value * self.depth * 0.5
+            }
+            ModulatorKind::Envelope {
+                attack,
+                decay,
+                sustain,
+                release,
+            } => {
+                self.advance_envelope(dt, attack, decay, sustain.clamp(0.0, 1.0), release);
+                self.level * self.depth
+            }
+            ModulatorKind::RandomWalk => {
+                if cycle_done {
+                    self.held = (self.held + self.next_random() * WALK_STEP).clamp(-1.0, 1.0);
+                }
+                self.held * self.depth * 0.5
+            }
+        };
+        self.output = (self.center + offset).clamp(0.0, 1.0);
+        self.output
+    }
+}
+
+/// Runs modulators and limits the rate of CCs sent for every parameter
+#[derive(Debug)]
+pub struct ModulationEngine {
+    /// Later modulators override earlier ones assigned to the same parameter
+    pub modulators: Vec<Modulator>,
+    /// Maximum number of CCs per second for each parameter
+    pub send_rate: f64,
+    /// Last send error, modulation continues with next update
+    pub error: Option<TransportError>,
+    time: f64,
+    /// Last sent value and time for every parameter
+    sent: HashMap<PatchParameterId, (u8, f64)>,
+}
+
+impl Default for ModulationEngine {
+    fn default() -> Self {
+        ModulationEngine {
+            modulators: Vec::new(),
+            send_rate: DEFAULT_SEND_RATE,
+            error: None,
+            time: 0.0,
+            sent: HashMap::new(),
+        }
+    }
+}
+
+impl ModulationEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn is_active(&self) -> bool {
+        self.modulators.iter().any(|modulator| modulator.enabled)
+    }
+    /// Advances enabled modulators and returns CC messages for values that should be sent now
+    pub fn update(&mut self, dt: f64) -> Vec<[u8; 3]> {
+        self.time += dt;
+        let mut values = HashMap::new();
+        for modulator in self.modulators.iter_mut().filter(|m| m.enabled) {
+            let value = modulator.advance(dt);
+            values.insert(modulator.pid, (value * 127.0).round() as u8);
+        }
+        let mut messages = Vec::new();
+        for (pid, value) in values {
+            let ready = match self.sent.get(&pid) {
+                Some(&(sent, time)) => sent != value && self.time - time >= 1.0 / self.send_rate,
+                None => true,
+            };
+            if ready {
+                self.sent.insert(pid, (value, self.time));
+                messages.push([0xb0, OpenWareMidiControl::from(pid) as u8, value]);
+            }
+        }
+        messages.sort_unstable();
+        messages
+    }
+    /// Advances modulators and sends changed values
+    pub fn send(&mut self, connection: &mut dyn MidiTransport, dt: f64) {
+        for message in self.update(dt) {
+            if let Err(err) = connection.send(&message) {
+                self.error = Some(err);
+                return;
+            }
+        }
+        self.error = None;
+    }
+}
+
+/// Timer thread that sends modulation to a connection until it's dropped
+#[cfg(not(target_arch = "wasm32"))]
+pub struct ModulationThread {
+    connection: SharedTransport,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl ModulationThread {
+    pub fn spawn(engine: Arc<Mutex<ModulationEngine>>, connection: SharedTransport) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            let mut connection = connection.clone();
+            thread::spawn(move || {
+                let mut last = Instant::now();
+                while !stop.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_secs_f64(TICK_INTERVAL));
+                    let now = Instant::now();
+                    let dt = now.duration_since(last).as_secs_f64();
+                    last = now;
+                    match engine.lock() {
+                        Ok(mut engine) => engine.send(&mut connection, dt),
+                        Err(_) => break,
+                    }
+                }
+            })
+        };
+        ModulationThread {
+            connection,
+            stop,
+            handle: Some(handle),
+        }
+    }
+    /// Connection that modulation is sent to
+    pub fn connection(&self) -> &SharedTransport {
+        &self.connection
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for ModulationThread {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::midi_transport::RecordingTransport;
+
+    struct NullTransport;
+
+    impl MidiTransport for NullTransport {
+        fn send(&mut self, _message: &[u8]) -> Result<(), TransportError> {
+            Ok(())
+        }
+    }
+
+    fn lfo(shape: LfoShape) -> Modulator {
+        let mut modulator = Modulator::new(
+            PatchParameterId::try_from(0).unwrap(),
+            ModulatorKind::Lfo(shape),
+            0.5,
+        );
+        modulator.depth = 1.0;
+        modulator
+    }
+
+    #[test]
+    fn test_lfo_shapes() {
+        let values = |shape| {
+            let mut modulator = lfo(shape);
+            (0..4)
+                .map(|_| (modulator.advance(0.25) * 100.0).round() / 100.0)
+                .collect::<Vec<f32>>()
+        };
+        assert_eq!(values(LfoShape::Sine), [1.0, 0.5, 0.0, 0.5]);
+        assert_eq!(values(LfoShape::Triangle), [0.5, 1.0, 0.5, 0.0]);
+        assert_eq!(values(LfoShape::Saw), [0.25, 0.5, 0.75, 0.0]);
+        assert_eq!(values(LfoShape::Square), [1.0, 0.0, 0.0, 1.0]);
+        let held = values(LfoShape::SampleAndHold);
+        assert_eq!(held[..3], [0.5, 0.5, 0.5]);
+        assert_ne!(held[3], 0.5);
+    }
+
+    #[test]
+    fn test_envelope() {
+        let mut modulator = Modulator::new(
+            PatchParameterId::try_from(0).unwrap(),
+            ModulatorKind::Envelope {
+                attack: 1.0,
+                decay: 1.0,
+                sustain: 0.5,
+                release: 0.0,
+            },
+            0.0,
+        );
+        modulator.depth = 1.0;
+        assert_eq!(modulator.advance(0.5), 0.0);
+        modulator.gate = true;
+        assert_eq!(modulator.advance(0.5), 0.5);
+        assert_eq!(modulator.advance(0.5), 1.0);
+        assert_eq!(modulator.advance(0.5), 0.75);
+        assert_eq!(modulator.advance(5.0), 0.5);
+        assert_eq!(modulator.advance(5.0), 0.5);
+        modulator.gate = false;
+        assert_eq!(modulator.advance(0.1), 0.0);
+    }
+
+    #[test]
+    fn test_random_walk() {
+        let mut modulator = lfo(LfoShape::Sine);
+        modulator.kind = ModulatorKind::RandomWalk;
+        modulator.rate = 10.0;
+        let mut previous = modulator.output();
+        for _ in 0..100 {
+            let value = modulator.advance(0.1);
+            assert!((value - previous).abs() <= WALK_STEP * 0.5 + f32::EPSILON);
+            previous = value;
+        }
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut engine = ModulationEngine::new();
+        engine.send_rate = 8.0;
+        engine.modulators.push(lfo(LfoShape::Saw));
+        let mut connection = RecordingTransport::new(NullTransport);
+        for _ in 0..64 {
+            engine.send(&mut connection, 1.0 / 64.0);
+        }
+        // One second at 8 CCs per second, first value is sent right away
+        assert_eq!(connection.messages.len(), 8);
+        assert_eq!(connection.messages[0][0], 0xb0);
+
+        engine.modulators[0].enabled = false;
+        assert!(!engine.is_active());
+        assert!(engine.update(1.0).is_empty());
+    }
+}
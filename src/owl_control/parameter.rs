@@ -1,29 +1,84 @@
+//! Patch parameter values that are kept in sync with device, patch buttons and NRPN decoding.
+use owl_midi::{OpenWareMidiControl, PatchParameterId};
+
+/// Number of patch parameters, A-H followed by AA-DH
+pub const PARAMETER_COUNT: usize = 40;
+/// Parameters are grouped in banks like A-H or AA-AH
+pub const BANK_SIZE: usize = 8;
+/// CC that presses a patch button, value is the button ID
+pub const PATCH_BUTTON_ON: u8 = 27;
+/// CC that releases a patch button, value is the button ID
+pub const PATCH_BUTTON_OFF: u8 = 28;
+/// ID of button A, buttons A-H are numbered consecutively
+pub const BUTTON_A: u8 = 4;
+pub const BUTTON_COUNT: u8 = 8;
+/// Largest 14-bit value received with NRPN
+pub const HIGH_RES_MAX: u16 = 0x3fff;
+
+const NRPN_MSB: u8 = 99;
+const NRPN_LSB: u8 = 98;
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+
+/// Parameter controlled by a CC, uses the same mapping as parameter changes sent to device
+pub fn parameter_for_cc(cc: u8) -> Option<PatchParameterId> {
+    (0..PARAMETER_COUNT as isize)
+        .filter_map(|i| PatchParameterId::try_from(i).ok())
+        .find(|&pid| OpenWareMidiControl::from(pid) as u8 == cc)
+}
+
+/// Parameter with a string ID like "A" or "AA", used when parameters are stored by name
+pub fn parameter_id(string_id: &str) -> Option<PatchParameterId> {
+    (0..PARAMETER_COUNT as isize)
+        .filter_map(|i| PatchParameterId::try_from(i).ok())
+        .find(|pid| pid.string_id() == string_id)
+}
+
+/// Button label, A-H buttons use their letter
+pub fn button_name(id: u8) -> String {
+    match id.checked_sub(BUTTON_A) {
+        Some(i) if i < BUTTON_COUNT => ((b'A' + i) as char).to_string(),
+        _ => format!("Button {id}"),
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct OwlParameter {
     pub name: String,
     pub value: f32,
     pub midi_value: u8,
     pub prev_midi_value: u8,
+    /// Set by patch and sent back to host, names of output parameters end with '>'
+    pub output: bool,
 }
 
 impl OwlParameter {
     pub fn new(name: String) -> Self {
         OwlParameter {
+            output: name.ends_with('>'),
             name,
             value: 0.0,
             midi_value: 0,
             prev_midi_value: 0,
         }
     }
+    /// Sets value received with full resolution, it's not sent back on next sync
+    pub fn set_high_res(&mut self, value: u16) {
+        self.value = value.min(HIGH_RES_MAX) as f32 / HIGH_RES_MAX as f32;
+        self.midi_value = (self.value * 127.0) as u8;
+        self.prev_midi_value = self.midi_value;
+    }
     // Returns true if CC should be sent
     pub fn sync(&mut self) -> bool {
         if self.midi_value != self.prev_midi_value {
             // MIDI value changed
             self.prev_midi_value = self.midi_value;
             self.value = self.midi_value as f32 / 127.0;
+        } else if self.output {
+            // Outputs are only changed by patch
         } else {
             // UI value changed
-            let expected_value = (self.value * 127.0) as u8;
+            let expected_value = (self.value.clamp(0.0, 1.0) * 127.0) as u8;
             if self.midi_value != expected_value {
                 self.midi_value = expected_value;
                 self.prev_midi_value = expected_value;
@@ -34,9 +89,47 @@ impl OwlParameter {
     }
 }
 
+/// Decodes 14-bit NRPN values, parameter number is the patch parameter ID
+#[derive(Debug, Default)]
+pub struct NrpnDecoder {
+    parameter: Option<u16>,
+    data_msb: u8,
+}
+
+impl NrpnDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn is_nrpn(cc: u8) -> bool {
+        matches!(cc, NRPN_MSB | NRPN_LSB | DATA_ENTRY_MSB | DATA_ENTRY_LSB)
+    }
+    /// Returns parameter number and its value when data entry CC is received
+    pub fn receive(&mut self, cc: u8, value: u8) -> Option<(u16, u16)> {
+        let value = value as u16 & 0x7f;
+        match cc {
+            NRPN_MSB => {
+                let lsb = self.parameter.unwrap_or(0) & 0x7f;
+                self.parameter = Some(value << 7 | lsb);
+                None
+            }
+            NRPN_LSB => {
+                let msb = self.parameter.unwrap_or(0) & !0x7f;
+                self.parameter = Some(msb | value);
+                None
+            }
+            DATA_ENTRY_MSB => {
+                self.data_msb = value as u8;
+                Some((self.parameter?, value << 7))
+            }
+            DATA_ENTRY_LSB => Some((self.parameter?, (self.data_msb as u16) << 7 | value)),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::OwlParameter;
+    use super::*;
 
     #[test]
     pub fn test_sync() {
@@ -52,7 +145,8 @@ mod tests {
                 name: "foo".to_string(),
                 value: 0.0,
                 midi_value: 1,
-                prev_midi_value: 0
+                prev_midi_value: 0,
+                output: false
             }
         );
         assert!(!param.sync());
@@ -62,7 +156,8 @@ mod tests {
                 name: "foo".to_string(),
                 value: 1.0 / 127.0,
                 midi_value: 1,
-                prev_midi_value: 1
+                prev_midi_value: 1,
+                output: false
             }
         );
         param.value = 0.5;
@@ -73,7 +168,8 @@ mod tests {
                 name: "foo".to_string(),
                 value: 0.5,
                 midi_value: 63,
-                prev_midi_value: 63
+                prev_midi_value: 63,
+                output: false
             }
         );
         assert!(!param.sync());
@@ -83,8 +179,57 @@ mod tests {
                 name: "foo".to_string(),
                 value: 0.5,
                 midi_value: 63,
-                prev_midi_value: 63
+                prev_midi_value: 63,
+                output: false
             }
         );
+        param.value = 2.0;
+        assert!(param.sync());
+        assert_eq!(param.midi_value, 127);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut param = OwlParameter::new("LFO>".to_string());
+        assert!(param.output);
+        param.value = 0.5;
+        assert!(!param.sync());
+        param.midi_value = 127;
+        assert!(!param.sync());
+        assert_eq!(param.value, 1.0);
+    }
+
+    #[test]
+    fn test_high_res() {
+        let mut param = OwlParameter::new("foo".to_string());
+        param.set_high_res(HIGH_RES_MAX / 2);
+        assert!((param.value - 0.5).abs() < 0.001);
+        assert!(!param.sync());
+        param.set_high_res(u16::MAX);
+        assert_eq!(param.value, 1.0);
+        assert!(!param.sync());
+    }
+
+    #[test]
+    fn test_nrpn() {
+        let mut nrpn = NrpnDecoder::new();
+        assert_eq!(nrpn.receive(DATA_ENTRY_MSB, 1), None);
+        assert_eq!(nrpn.receive(NRPN_MSB, 0), None);
+        assert_eq!(nrpn.receive(NRPN_LSB, 3), None);
+        assert_eq!(nrpn.receive(DATA_ENTRY_MSB, 0x40), Some((3, 0x2000)));
+        assert_eq!(nrpn.receive(DATA_ENTRY_LSB, 0x7f), Some((3, 0x207f)));
+        assert_eq!(nrpn.receive(NRPN_MSB, 1), None);
+        assert_eq!(nrpn.receive(DATA_ENTRY_LSB, 1), Some((0x83, 0x2001)));
+        assert_eq!(nrpn.receive(20, 1), None);
+        assert!(NrpnDecoder::is_nrpn(DATA_ENTRY_LSB));
+        assert!(!NrpnDecoder::is_nrpn(20));
+    }
+
+    #[test]
+    fn test_button_name() {
+        assert_eq!(button_name(BUTTON_A), "A");
+        assert_eq!(button_name(BUTTON_A + 7), "H");
+        assert_eq!(button_name(BUTTON_A + 8), "Button 12");
+        assert_eq!(button_name(1), "Button 1");
     }
 }
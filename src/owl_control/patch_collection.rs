@@ -0,0 +1,79 @@
+//! Lists of entries stored for every patch by patch name.
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Entries for every patch, patches without entries are not stored
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PatchCollection<T> {
+    pub patches: BTreeMap<String, Vec<T>>,
+}
+
+impl<T> Default for PatchCollection<T> {
+    fn default() -> Self {
+        PatchCollection {
+            patches: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> PatchCollection<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn get(&self, patch: &str) -> &[T] {
+        self.patches.get(patch).map_or(&[], |entries| entries)
+    }
+    pub fn get_mut(&mut self, patch: &str) -> &mut [T] {
+        self.patches
+            .get_mut(patch)
+            .map_or(&mut [], |entries| entries)
+    }
+    /// Replaces first entry that `same` matches, or adds entry if there isn't one
+    pub fn replace(&mut self, patch: &str, entry: T, same: impl Fn(&T) -> bool) {
+        let entries = self.patches.entry(patch.to_string()).or_default();
+        match entries.iter_mut().find(|existing| same(existing)) {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+    }
+    /// Removes entries that `same` matches and adds entry after remaining ones
+    pub fn push(&mut self, patch: &str, entry: T, same: impl Fn(&T) -> bool) {
+        let entries = self.patches.entry(patch.to_string()).or_default();
+        entries.retain(|existing| !same(existing));
+        entries.push(entry);
+    }
+    pub fn remove(&mut self, patch: &str, index: usize) {
+        if let Some(entries) = self.patches.get_mut(patch) {
+            if index < entries.len() {
+                entries.remove(index);
+            }
+            if entries.is_empty() {
+                self.patches.remove(patch);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_collection() {
+        let mut collection = PatchCollection::new();
+        collection.replace("Patch", (1, 'a'), |entry| entry.0 == 1);
+        collection.replace("Patch", (2, 'b'), |entry| entry.0 == 2);
+        collection.replace("Patch", (1, 'c'), |entry| entry.0 == 1);
+        assert_eq!(collection.get("Patch"), &[(1, 'c'), (2, 'b')]);
+        collection.push("Patch", (1, 'd'), |entry| entry.0 == 1);
+        assert_eq!(collection.get("Patch"), &[(2, 'b'), (1, 'd')]);
+        assert!(collection.get("Other").is_empty());
+        assert!(collection.get_mut("Other").is_empty());
+
+        collection.remove("Patch", 2);
+        assert_eq!(collection.get("Patch").len(), 2);
+        collection.remove("Patch", 0);
+        collection.remove("Patch", 0);
+        assert!(collection.patches.is_empty());
+    }
+}
@@ -0,0 +1,291 @@
+//! History of messages and errors sent by running patch, with numbers from debug output.
+use super::sysex::string_message;
+use owl_midi::OpenWareMidiSysexCommand;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Default number of messages kept in history, older ones are dropped
+pub const DEFAULT_CAPACITY: usize = 1000;
+/// Number of values kept for every plotted series
+pub const MAX_POINTS: usize = 10_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgramMessageKind {
+    Message,
+    Error,
+}
+
+impl ProgramMessageKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProgramMessageKind::Message => "Message",
+            ProgramMessageKind::Error => "Error",
+        }
+    }
+}
+
+/// Program message or error from a complete sysex message, including start and end bytes
+pub fn program_message(data: &[u8]) -> Option<(ProgramMessageKind, String)> {
+    match string_message(data)? {
+        (OpenWareMidiSysexCommand::SYSEX_PROGRAM_MESSAGE, text) => {
+            Some((ProgramMessageKind::Message, text))
+        }
+        (OpenWareMidiSysexCommand::SYSEX_PROGRAM_ERROR, text) => {
+            Some((ProgramMessageKind::Error, text))
+        }
+        _ => None,
+    }
+}
+
+/// Numbers in debug output named by the word before them, e.g. "freq 440 gain: 0.5 x=1 2"
+/// gives "freq", "gain", "x" and "x 2". Numbers without a name are called "value".
+pub fn parse_values(text: &str) -> Vec<(String, f64)> {
+    let mut values = Vec::new();
+    let mut label = "value";
+    let mut index = 0;
+    let mut add = |label: &str, index: &mut usize, value: f64| {
+        if value.is_finite() {
+            let name = match *index {
+                0 => label.to_string(),
+                i => format!("{label} {}", i + 1),
+            };
+            values.push((name, value));
+        }
+        *index += 1;
+    };
+    for token in text
+        .split(|c: char| c.is_whitespace() || c == ',' || c == ';')
+        .filter(|token| !token.is_empty())
+    {
+        if let Ok(value) = token.parse::<f64>() {
+            add(label, &mut index, value);
+            continue;
+        }
+        match token.split_once(['=', ':']) {
+            Some((name, value)) if !name.is_empty() && !value.is_empty() => {
+                label = name;
+                index = 0;
+                if let Ok(value) = value.parse::<f64>() {
+                    add(label, &mut index, value);
+                }
+            }
+            _ => {
+                label = token.trim_end_matches(['=', ':']);
+                index = 0;
+            }
+        }
+    }
+    values
+}
+
+/// Received message, repeated messages are counted instead of stored again
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgramMessage {
+    /// Time of first message
+    pub time: f64,
+    /// Time of last repeated message
+    pub last_time: f64,
+    pub kind: ProgramMessageKind,
+    pub text: String,
+    pub count: usize,
+}
+
+/// Messages and errors received from device, with values parsed from messages over time
+#[derive(Debug)]
+pub struct ProgramHistory {
+    entries: VecDeque<ProgramMessage>,
+    /// Time and value for every parsed value name
+    series: BTreeMap<String, VecDeque<[f64; 2]>>,
+    /// Errors that weren't returned by `take_new_errors` yet
+    new_errors: Vec<String>,
+    pub capacity: usize,
+}
+
+impl Default for ProgramHistory {
+    fn default() -> Self {
+        ProgramHistory {
+            entries: VecDeque::new(),
+            series: BTreeMap::new(),
+            new_errors: Vec::new(),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+impl ProgramHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Records program message or error from a received message, other messages are ignored
+    pub fn record(&mut self, time: f64, data: &[u8]) {
+        if let Some((kind, text)) = program_message(data) {
+            self.push(time, kind, text);
+        }
+    }
+    pub fn push(&mut self, time: f64, kind: ProgramMessageKind, text: String) {
+        if kind == ProgramMessageKind::Message {
+            for (name, value) in parse_values(&text) {
+                let points = self.series.entry(name).or_default();
+                if points.len() >= MAX_POINTS {
+                    points.pop_front();
+                }
+                points.push_back([time, value]);
+            }
+        }
+        if let Some(last) = self.entries.back_mut() {
+            if last.kind == kind && last.text == text {
+                last.count += 1;
+                last.last_time = time;
+                return;
+            }
+        }
+        if kind == ProgramMessageKind::Error {
+            self.new_errors.push(text.clone());
+        }
+        while self.entries.len() >= self.capacity.max(1) {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(ProgramMessage {
+            time,
+            last_time: time,
+            kind,
+            text,
+            count: 1,
+        });
+    }
+    pub fn entries(&self) -> impl Iterator<Item = &ProgramMessage> {
+        self.entries.iter()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn error_count(&self) -> usize {
+        self.entries
+            .iter()
+            .filter(|entry| entry.kind == ProgramMessageKind::Error)
+            .count()
+    }
+    pub fn series(&self) -> &BTreeMap<String, VecDeque<[f64; 2]>> {
+        &self.series
+    }
+    /// Errors received since last call, each distinct error is returned once
+    pub fn take_new_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.new_errors)
+    }
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.series.clear();
+        self.new_errors.clear();
+    }
+    /// Removes errors, leaving messages and plotted values
+    pub fn clear_errors(&mut self) {
+        self.entries
+            .retain(|entry| entry.kind != ProgramMessageKind::Error);
+        self.new_errors.clear();
+    }
+    /// History as text, one message per line
+    pub fn to_text(&self) -> String {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let repeated = match entry.count {
+                    1 => String::new(),
+                    count => format!(" (×{count})"),
+                };
+                format!(
+                    "{:10.3} {:<7} {}{repeated}\n",
+                    entry.time,
+                    entry.kind.name(),
+                    entry.text
+                )
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sysex(command: OpenWareMidiSysexCommand, text: &str) -> Vec<u8> {
+        let mut data = vec![
+            0xf0,
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            command as u8,
+        ];
+        data.extend_from_slice(text.as_bytes());
+        data.extend_from_slice(&[0, 0xf7]);
+        data
+    }
+
+    #[test]
+    fn test_parse_values() {
+        assert_eq!(
+            parse_values("Freq 440.5 gain: 0.25 x=1 2, 3"),
+            vec![
+                ("Freq".to_string(), 440.5),
+                ("gain".to_string(), 0.25),
+                ("x".to_string(), 1.0),
+                ("x 2".to_string(), 2.0),
+                ("x 3".to_string(), 3.0),
+            ]
+        );
+        assert_eq!(
+            parse_values("-1 2e3 nan"),
+            vec![("value".to_string(), -1.0), ("value 2".to_string(), 2000.0)]
+        );
+        assert!(parse_values("Ready").is_empty());
+    }
+
+    #[test]
+    fn test_history() {
+        let mut history = ProgramHistory::new();
+        history.record(
+            1.0,
+            &sysex(OpenWareMidiSysexCommand::SYSEX_PROGRAM_MESSAGE, "level 0.5"),
+        );
+        history.record(
+            2.0,
+            &sysex(OpenWareMidiSysexCommand::SYSEX_PROGRAM_MESSAGE, "level 0.5"),
+        );
+        history.record(
+            3.0,
+            &sysex(
+                OpenWareMidiSysexCommand::SYSEX_PROGRAM_ERROR,
+                "Out of memory",
+            ),
+        );
+        history.record(
+            4.0,
+            &sysex(
+                OpenWareMidiSysexCommand::SYSEX_PROGRAM_ERROR,
+                "Out of memory",
+            ),
+        );
+        history.record(
+            5.0,
+            &sysex(OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS, "CPU 10%"),
+        );
+        let entries: Vec<&ProgramMessage> = history.entries().collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].count, 2);
+        assert_eq!(entries[0].last_time, 2.0);
+        assert_eq!(entries[1].kind, ProgramMessageKind::Error);
+        assert_eq!(
+            history.series()["level"],
+            VecDeque::from([[1.0, 0.5], [2.0, 0.5]])
+        );
+        assert_eq!(history.take_new_errors(), vec!["Out of memory".to_string()]);
+        assert!(history.take_new_errors().is_empty());
+        assert_eq!(
+            history.to_text(),
+            "     1.000 Message level 0.5 (×2)\n     3.000 Error   Out of memory (×2)\n"
+        );
+        history.clear_errors();
+        assert_eq!(history.error_count(), 0);
+        assert_eq!(history.entries().count(), 1);
+        history.clear();
+        assert!(history.is_empty());
+        assert!(history.series().is_empty());
+    }
+}
@@ -0,0 +1,299 @@
+//! Periodic polling of program stats with CPU load and memory usage parsed over time.
+use super::sysex::string_message;
+use owl_midi::OpenWareMidiSysexCommand;
+use std::collections::VecDeque;
+
+/// Default seconds between stats requests
+pub const DEFAULT_INTERVAL: f64 = 1.0;
+/// Default CPU load in percent that shows a warning
+pub const DEFAULT_CPU_WARNING: f32 = 90.0;
+/// Memory available to patches in bytes when device doesn't report it, OWL devices have 8 MiB
+pub const DEFAULT_MEMORY_LIMIT: u64 = 8 * 1024 * 1024;
+/// Share of memory limit that shows a warning
+pub const MEMORY_WARNING: f32 = 0.9;
+/// Number of stats kept for plots
+pub const MAX_POINTS: usize = 3600;
+
+/// Values parsed from stats string, e.g. "CPU: 25% Heap: 4096" or "CPU: 25% Heap: 4096/65536"
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ProgramStats {
+    /// CPU load in percent
+    pub cpu: Option<f32>,
+    /// Heap memory used in bytes
+    pub memory: Option<u64>,
+    /// Heap memory available in bytes, if device reports it
+    pub memory_total: Option<u64>,
+}
+
+/// Parses a number of bytes with optional k or M suffix
+fn parse_bytes(text: &str) -> Option<u64> {
+    let text = text.trim_end_matches(['b', 'B']);
+    let (number, multiplier) = match text.char_indices().last()? {
+        (i, 'k' | 'K') => (&text[..i], 1024.0),
+        (i, 'M') => (&text[..i], 1024.0 * 1024.0),
+        _ => (text, 1.0),
+    };
+    let value = number.parse::<f64>().ok()?;
+    (value >= 0.0).then_some((value * multiplier) as u64)
+}
+
+impl ProgramStats {
+    pub fn parse(text: &str) -> Self {
+        let mut stats = ProgramStats::default();
+        let mut label = String::new();
+        for token in text.split_whitespace() {
+            // Values follow their labels
+            match label.as_str() {
+                "cpu" => stats.cpu = token.trim_end_matches('%').parse().ok(),
+                "heap" | "mem" | "memory" => match token.split_once('/') {
+                    Some((used, total)) => {
+                        stats.memory = parse_bytes(used);
+                        stats.memory_total = parse_bytes(total);
+                    }
+                    None => stats.memory = parse_bytes(token),
+                },
+                _ => {}
+            }
+            label = token.trim_end_matches(':').to_ascii_lowercase();
+        }
+        stats
+    }
+    pub fn is_empty(&self) -> bool {
+        self.cpu.is_none() && self.memory.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StatsWarning {
+    Cpu,
+    Memory,
+}
+
+impl StatsWarning {
+    pub fn message(&self) -> &'static str {
+        match self {
+            StatsWarning::Cpu => "Patch is close to 100% CPU load",
+            StatsWarning::Memory => "Patch is running out of memory",
+        }
+    }
+}
+
+/// Requests stats periodically and keeps received values for plotting
+#[derive(Debug)]
+pub struct StatsMonitor {
+    pub polling: bool,
+    /// Seconds between requests
+    pub interval: f64,
+    /// CPU load in percent that shows a warning
+    pub cpu_warning: f32,
+    /// Memory available to patch in bytes, used when device doesn't report it.
+    /// No warning is shown when it's 0.
+    pub memory_limit: u64,
+    history: VecDeque<(f64, ProgramStats)>,
+    next_poll: f64,
+    warnings: Vec<StatsWarning>,
+    new_warnings: Vec<StatsWarning>,
+}
+
+impl Default for StatsMonitor {
+    fn default() -> Self {
+        StatsMonitor {
+            polling: false,
+            interval: DEFAULT_INTERVAL,
+            cpu_warning: DEFAULT_CPU_WARNING,
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            history: VecDeque::new(),
+            next_poll: 0.0,
+            warnings: Vec::new(),
+            new_warnings: Vec::new(),
+        }
+    }
+}
+
+impl StatsMonitor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns true when it's time to request stats
+    pub fn poll(&mut self, now: f64) -> bool {
+        if !self.polling || now < self.next_poll {
+            return false;
+        }
+        self.next_poll = now + self.interval;
+        true
+    }
+    /// Records stats from a received message, other messages are ignored
+    pub fn record(&mut self, time: f64, data: &[u8]) {
+        if let Some((OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS, text)) = string_message(data) {
+            self.push(time, ProgramStats::parse(&text));
+        }
+    }
+    pub fn push(&mut self, time: f64, stats: ProgramStats) {
+        if stats.is_empty() {
+            return;
+        }
+        if self.history.len() >= MAX_POINTS {
+            self.history.pop_front();
+        }
+        self.history.push_back((time, stats));
+        let warnings = self.check(&stats);
+        for warning in warnings.iter() {
+            if !self.warnings.contains(warning) {
+                self.new_warnings.push(*warning);
+            }
+        }
+        self.warnings = warnings;
+    }
+    fn check(&self, stats: &ProgramStats) -> Vec<StatsWarning> {
+        let mut warnings = Vec::new();
+        if stats.cpu.map_or(false, |cpu| cpu >= self.cpu_warning) {
+            warnings.push(StatsWarning::Cpu);
+        }
+        let limit = stats.memory_total.unwrap_or(self.memory_limit);
+        if limit > 0
+            && stats.memory.map_or(false, |memory| {
+                memory as f32 >= limit as f32 * MEMORY_WARNING
+            })
+        {
+            warnings.push(StatsWarning::Memory);
+        }
+        warnings
+    }
+    /// Memory available to patch in bytes, as reported in latest stats or set by user
+    pub fn memory_limit(&self) -> u64 {
+        self.latest()
+            .and_then(|stats| stats.memory_total)
+            .unwrap_or(self.memory_limit)
+    }
+    pub fn latest(&self) -> Option<&ProgramStats> {
+        self.history.back().map(|(_, stats)| stats)
+    }
+    /// Warnings for latest stats
+    pub fn warnings(&self) -> &[StatsWarning] {
+        &self.warnings
+    }
+    /// Warnings that weren't shown for previous stats, returned once
+    pub fn take_new_warnings(&mut self) -> Vec<StatsWarning> {
+        std::mem::take(&mut self.new_warnings)
+    }
+    /// Time and CPU load in percent
+    pub fn cpu_points(&self) -> Vec<[f64; 2]> {
+        self.history
+            .iter()
+            .filter_map(|(time, stats)| Some([*time, stats.cpu? as f64]))
+            .collect()
+    }
+    /// Time and memory usage in bytes
+    pub fn memory_points(&self) -> Vec<[f64; 2]> {
+        self.history
+            .iter()
+            .filter_map(|(time, stats)| Some([*time, stats.memory? as f64]))
+            .collect()
+    }
+    pub fn clear(&mut self) {
+        self.history.clear();
+        self.warnings.clear();
+        self.new_warnings.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ProgramStats::parse("CPU: 25% Heap: 4096"),
+            ProgramStats {
+                cpu: Some(25.0),
+                memory: Some(4096),
+                memory_total: None
+            }
+        );
+        assert_eq!(
+            ProgramStats::parse("CPU: 25% Heap: 4k/64k"),
+            ProgramStats {
+                cpu: Some(25.0),
+                memory: Some(4096),
+                memory_total: Some(65536)
+            }
+        );
+        assert_eq!(
+            ProgramStats::parse("cpu 7.5% mem: 12k"),
+            ProgramStats {
+                cpu: Some(7.5),
+                memory: Some(12288),
+                memory_total: None
+            }
+        );
+        assert!(ProgramStats::parse("No program").is_empty());
+        assert_eq!(parse_bytes("2MB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_bytes("-1"), None);
+    }
+
+    #[test]
+    fn test_monitor() {
+        let mut monitor = StatsMonitor::new();
+        assert!(!monitor.poll(0.0));
+        monitor.polling = true;
+        monitor.memory_limit = 1000;
+        assert!(monitor.poll(0.0));
+        assert!(!monitor.poll(0.5));
+        assert!(monitor.poll(1.0));
+
+        let mut data = vec![
+            0xf0,
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS as u8,
+        ];
+        data.extend_from_slice(b"CPU: 95% Heap: 100\0");
+        data.push(0xf7);
+        monitor.record(1.0, &data);
+        assert_eq!(monitor.warnings(), &[StatsWarning::Cpu]);
+        assert_eq!(monitor.take_new_warnings(), vec![StatsWarning::Cpu]);
+        monitor.push(
+            2.0,
+            ProgramStats {
+                cpu: Some(96.0),
+                memory: Some(950),
+                memory_total: None,
+            },
+        );
+        assert_eq!(monitor.take_new_warnings(), vec![StatsWarning::Memory]);
+        monitor.push(
+            3.0,
+            ProgramStats {
+                cpu: Some(10.0),
+                memory: None,
+                memory_total: None,
+            },
+        );
+        assert!(monitor.warnings().is_empty());
+        assert_eq!(
+            monitor.cpu_points(),
+            vec![[1.0, 95.0], [2.0, 96.0], [3.0, 10.0]]
+        );
+        assert_eq!(monitor.memory_points(), vec![[1.0, 100.0], [2.0, 950.0]]);
+        monitor.clear();
+        assert!(monitor.latest().is_none());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        // Default limit is used until device reports it
+        let mut monitor = StatsMonitor::new();
+        assert_eq!(monitor.memory_limit(), DEFAULT_MEMORY_LIMIT);
+        monitor.push(1.0, ProgramStats::parse("Heap: 7.5M"));
+        assert_eq!(monitor.take_new_warnings(), vec![StatsWarning::Memory]);
+        monitor.push(2.0, ProgramStats::parse("Heap: 7.5M/64M"));
+        assert!(monitor.warnings().is_empty());
+        assert_eq!(monitor.memory_limit(), 64 * 1024 * 1024);
+        monitor.push(3.0, ProgramStats::parse("Heap: 950/1000"));
+        assert_eq!(monitor.warnings(), &[StatsWarning::Memory]);
+        monitor.memory_limit = 0;
+        monitor.push(4.0, ProgramStats::parse("Heap: 7.5M"));
+        assert!(monitor.warnings().is_empty());
+    }
+}
@@ -0,0 +1,199 @@
+//! Tracking of device requests with retries and timeouts.
+use owl_midi::OpenWareMidiSysexCommand;
+
+/// Seconds to wait for the first response before a request is sent again
+pub const REQUEST_TIMEOUT: f64 = 1.0;
+/// Number of times a request is sent again before giving up
+pub const REQUEST_RETRIES: u32 = 2;
+/// Seconds without new responses after which a list request is complete
+pub const LIST_SETTLE_TIME: f64 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestState {
+    /// Waiting for the first response
+    Pending,
+    /// Receiving entries of a list
+    Receiving,
+    /// All responses received
+    Complete,
+    /// Device didn't answer after all retries
+    TimedOut,
+}
+
+/// Changes of request state reported by `RequestTracker::poll`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RequestEvent {
+    Retry(OpenWareMidiSysexCommand),
+    Complete(OpenWareMidiSysexCommand),
+    TimedOut(OpenWareMidiSysexCommand),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceRequest {
+    pub command: OpenWareMidiSysexCommand,
+    pub state: RequestState,
+    pub attempts: u32,
+    pub responses: usize,
+    sent_at: f64,
+    last_response_at: f64,
+}
+
+impl DeviceRequest {
+    // Device answers these requests with multiple messages and no end marker
+    fn is_list(&self) -> bool {
+        matches!(
+            self.command,
+            OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND
+                | OpenWareMidiSysexCommand::SYSEX_RESOURCE_NAME_COMMAND
+                | OpenWareMidiSysexCommand::SYSEX_CONFIGURATION_COMMAND
+        )
+    }
+    pub fn is_active(&self) -> bool {
+        matches!(self.state, RequestState::Pending | RequestState::Receiving)
+    }
+}
+
+/// Tracks outstanding device requests by command.
+///
+/// Time is passed in seconds from an arbitrary epoch, so that the tracker
+/// works with UI frame time and doesn't depend on system clock.
+pub struct RequestTracker {
+    requests: Vec<DeviceRequest>,
+    time: f64,
+}
+
+impl Default for RequestTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RequestTracker {
+    pub fn new() -> Self {
+        RequestTracker {
+            requests: Vec::new(),
+            time: 0.0,
+        }
+    }
+    /// Registers a new request, replacing previous request for the same command
+    pub fn start(&mut self, command: OpenWareMidiSysexCommand) {
+        self.dismiss(command);
+        self.requests.push(DeviceRequest {
+            command,
+            state: RequestState::Pending,
+            attempts: 1,
+            responses: 0,
+            sent_at: self.time,
+            last_response_at: self.time,
+        });
+    }
+    /// Records a response from device
+    pub fn response(&mut self, command: OpenWareMidiSysexCommand) {
+        let time = self.time;
+        if let Some(request) = self
+            .requests
+            .iter_mut()
+            .find(|r| r.command == command && r.is_active())
+        {
+            request.responses += 1;
+            request.last_response_at = time;
+            request.state = if request.is_list() {
+                RequestState::Receiving
+            } else {
+                RequestState::Complete
+            };
+        }
+    }
+    /// Updates timers, returns requests that must be sent again or changed state
+    pub fn poll(&mut self, now: f64) -> Vec<RequestEvent> {
+        self.time = now;
+        let mut events = Vec::new();
+        for request in self.requests.iter_mut() {
+            match request.state {
+                RequestState::Pending if now - request.sent_at > REQUEST_TIMEOUT => {
+                    if request.attempts <= REQUEST_RETRIES {
+                        request.attempts += 1;
+                        request.sent_at = now;
+                        events.push(RequestEvent::Retry(request.command));
+                    } else {
+                        request.state = RequestState::TimedOut;
+                        events.push(RequestEvent::TimedOut(request.command));
+                    }
+                }
+                RequestState::Receiving if now - request.last_response_at > LIST_SETTLE_TIME => {
+                    request.state = RequestState::Complete;
+                    events.push(RequestEvent::Complete(request.command));
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+    pub fn state(&self, command: OpenWareMidiSysexCommand) -> Option<RequestState> {
+        self.requests
+            .iter()
+            .find(|r| r.command == command)
+            .map(|r| r.state)
+    }
+    pub fn requests(&self) -> &[DeviceRequest] {
+        &self.requests
+    }
+    pub fn is_busy(&self) -> bool {
+        self.requests.iter().any(|r| r.is_active())
+    }
+    pub fn dismiss(&mut self, command: OpenWareMidiSysexCommand) {
+        self.requests.retain(|r| r.command != command);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_single_response() {
+        let mut tracker = RequestTracker::new();
+        let command = OpenWareMidiSysexCommand::SYSEX_FIRMWARE_VERSION;
+        assert_eq!(tracker.state(command), None);
+        tracker.start(command);
+        assert_eq!(tracker.state(command), Some(RequestState::Pending));
+        assert!(tracker.is_busy());
+        assert_eq!(tracker.poll(0.5), vec![]);
+        tracker.response(command);
+        assert_eq!(tracker.state(command), Some(RequestState::Complete));
+        assert!(!tracker.is_busy());
+    }
+
+    #[test]
+    fn test_list_response() {
+        let mut tracker = RequestTracker::new();
+        let command = OpenWareMidiSysexCommand::SYSEX_PRESET_NAME_COMMAND;
+        tracker.start(command);
+        tracker.poll(0.1);
+        tracker.response(command);
+        tracker.response(command);
+        assert_eq!(tracker.state(command), Some(RequestState::Receiving));
+        assert_eq!(tracker.poll(0.5), vec![]);
+        assert_eq!(tracker.poll(0.7), vec![RequestEvent::Complete(command)]);
+        assert_eq!(tracker.state(command), Some(RequestState::Complete));
+        assert_eq!(tracker.requests()[0].responses, 2);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut tracker = RequestTracker::new();
+        let command = OpenWareMidiSysexCommand::SYSEX_PROGRAM_STATS;
+        tracker.start(command);
+        assert_eq!(tracker.poll(1.5), vec![RequestEvent::Retry(command)]);
+        assert_eq!(tracker.poll(2.0), vec![]);
+        assert_eq!(tracker.poll(3.0), vec![RequestEvent::Retry(command)]);
+        assert_eq!(tracker.poll(4.5), vec![RequestEvent::TimedOut(command)]);
+        assert_eq!(tracker.state(command), Some(RequestState::TimedOut));
+        assert_eq!(tracker.requests()[0].attempts, 3);
+        // Late response is ignored
+        tracker.response(command);
+        assert_eq!(tracker.state(command), Some(RequestState::TimedOut));
+        tracker.dismiss(command);
+        assert!(tracker.requests().is_empty());
+    }
+}
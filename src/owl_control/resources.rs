@@ -1,6 +1,10 @@
-use crate::owl_control::{crc32::Crc32, sysex::SysexData};
-use anyhow::Result;
+//! Patch and resource entries, resource headers and data transfers.
+use crate::owl_control::{
+    crc32::Crc32,
+    sysex::{SysexData, SysexError},
+};
 use byte_unit::Byte;
+use tracing::debug;
 use wmidi::U7;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
@@ -20,6 +24,21 @@ impl Resource {
             checksum,
         }
     }
+    /// Decodes patch or resource name entry: slot, null-terminated name, size and checksum
+    pub fn from_sysex(data: &[U7]) -> Result<Self, SysexError> {
+        let (&slot, data) = data.split_first().ok_or(SysexError::NotEnoughData)?;
+        let end = data
+            .iter()
+            .position(|&x| x == U7::MIN)
+            .ok_or(SysexError::MissingTerminator)?;
+        let name = String::from_utf8_lossy(U7::data_to_bytes(&data[..end])).to_string();
+        let data = &data[end + 1..];
+        let mut size = 0;
+        size.decode(data)?;
+        let mut checksum = 0;
+        checksum.decode(data.get(5..).ok_or(SysexError::NotEnoughData)?)?;
+        Ok(Resource::new(u8::from(slot), name, size, checksum))
+    }
     pub fn size_string(&self) -> String {
         let byte = Byte::from_bytes(self.size.into());
         let adjusted_byte = byte.get_appropriate_unit(true);
@@ -27,6 +46,75 @@ impl Resource {
     }
 }
 
+/// Magic number that starts resource header
+pub const RESOURCE_MAGIC: u32 = 0xdadadeed;
+/// Size of name field in resource header
+pub const RESOURCE_NAME_SIZE: usize = 24;
+/// Magic, size, name and flags
+pub const RESOURCE_HEADER_SIZE: usize = RESOURCE_NAME_SIZE + 12;
+/// Raw bytes sent in a single upload message, encodes to 256 bytes of sysex data
+pub const UPLOAD_CHUNK_SIZE: usize = 224;
+
+/// Prepends resource header (magic, size, name and flags) to resource data
+pub fn resource_with_header(name: &str, data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len() + RESOURCE_HEADER_SIZE);
+    result.extend_from_slice(&RESOURCE_MAGIC.to_le_bytes());
+    result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    let mut name_field = [0u8; RESOURCE_NAME_SIZE];
+    for (dst, src) in name_field
+        .iter_mut()
+        .zip(name.bytes().take(RESOURCE_NAME_SIZE - 1))
+    {
+        *dst = src;
+    }
+    result.extend_from_slice(&name_field);
+    result.extend_from_slice(&0u32.to_le_bytes());
+    result.extend_from_slice(data);
+    result
+}
+
+/// Returns resource name and data if data starts with a valid resource header
+pub fn resource_from_header(data: &[u8]) -> Option<(String, &[u8])> {
+    let header = data.get(..RESOURCE_HEADER_SIZE)?;
+    if u32::from_le_bytes(header[..4].try_into().unwrap()) != RESOURCE_MAGIC {
+        return None;
+    }
+    let size = u32::from_le_bytes(header[4..8].try_into().unwrap()) as usize;
+    let name_field = &header[8..8 + RESOURCE_NAME_SIZE];
+    let end = name_field
+        .iter()
+        .position(|&x| x == 0)
+        .unwrap_or(RESOURCE_NAME_SIZE);
+    let name = String::from_utf8_lossy(&name_field[..end]).to_string();
+    let payload = data.get(RESOURCE_HEADER_SIZE..RESOURCE_HEADER_SIZE + size)?;
+    Some((name, payload))
+}
+
+/// Splits data into SYSEX_FIRMWARE_UPLOAD payloads.
+///
+/// Every payload starts with packet index, first one contains data size, last one contains CRC32.
+pub fn encode_upload(data: &[u8]) -> Vec<Vec<U7>> {
+    let mut packets = Vec::new();
+    let mut index = 0u32;
+    let mut packet = |value: Option<u32>, chunk: &[u8]| {
+        let mut buf = vec![U7::MIN; 10 + chunk.len() + (chunk.len() + 6) / 7];
+        index.encode(&mut buf[..5]).unwrap();
+        let size = match value {
+            Some(value) => value.encode(&mut buf[5..10]).unwrap().bytes_written,
+            None => chunk.encode(&mut buf[5..]).unwrap().bytes_written,
+        };
+        buf.truncate(5 + size);
+        index += 1;
+        buf
+    };
+    packets.push(packet(Some(data.len() as u32), &[]));
+    for chunk in data.chunks(UPLOAD_CHUNK_SIZE) {
+        packets.push(packet(None, chunk));
+    }
+    packets.push(packet(Some(Crc32::new().update(data).crc), &[]));
+    packets
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum ResourceState {
     New,
@@ -49,6 +137,12 @@ pub struct ResourceData {
     decode_buffer: [u8; 256],
 }
 
+impl Default for ResourceData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl ResourceData {
     pub const fn new() -> Self {
         ResourceData {
@@ -65,7 +159,16 @@ impl ResourceData {
     pub fn reset(&mut self) {
         *self = Self::new();
     }
-    pub fn process_data(&mut self, data: &[U7]) -> Result<()> {
+    /// Returns downloaded data once, state is reset so that it isn't handled again
+    pub fn take(&mut self) -> Option<Vec<u8>> {
+        if self.state != ResourceState::Success {
+            return None;
+        }
+        let data = std::mem::take(&mut self.data);
+        self.reset();
+        Some(data)
+    }
+    pub fn process_data(&mut self, data: &[U7]) -> Result<(), SysexError> {
         let old_offset = self.offset;
         match self.state {
             ResourceState::New => {
@@ -80,8 +183,8 @@ impl ResourceData {
                     }
                 }
             }
-            ResourceState::InProgress => {
-                if let Ok(result) = self.decode_buffer.decode(data) {
+            ResourceState::InProgress => match self.decode_buffer.decode(data) {
+                Ok(result) => {
                     self.offset += result.bytes_written as u32;
                     self.data
                         .extend(&self.decode_buffer[..result.bytes_written]);
@@ -89,10 +192,12 @@ impl ResourceData {
                     if self.offset >= self.size {
                         self.state = ResourceState::Complete;
                     }
-                } else {
+                }
+                Err(err) => {
                     self.state = ResourceState::Failed;
+                    return Err(err);
                 }
-            }
+            },
             ResourceState::Complete => {
                 if data.len() == 5 {
                     if let Ok(_result) = self.checksum.decode(data) {
@@ -112,12 +217,12 @@ impl ResourceData {
             }
             _ => {}
         }
-        println!(
-            "{} / {} (+{}, {:?})",
-            self.offset,
-            self.size,
-            self.offset - old_offset,
-            self.state
+        debug!(
+            offset = self.offset,
+            size = self.size,
+            received = self.offset - old_offset,
+            state = ?self.state,
+            "resource data"
         );
         Ok(())
     }
@@ -137,4 +242,57 @@ mod test {
             "2.00 KiB"
         );
     }
+    #[test]
+    fn test_resource_header() {
+        let data = resource_with_header("Wavetable", &[1, 2, 3]);
+        assert_eq!(data.len(), 36 + 3);
+        assert_eq!(data[..8], [0xed, 0xde, 0xda, 0xda, 3, 0, 0, 0]);
+        assert_eq!(&data[8..17], b"Wavetable");
+        assert_eq!(data[17..36], [0; 19]);
+        assert_eq!(data[36..], [1, 2, 3]);
+        assert_eq!(
+            resource_from_header(&data),
+            Some(("Wavetable".to_string(), &[1u8, 2, 3][..]))
+        );
+        assert_eq!(resource_from_header(&data[..38]), None);
+        assert_eq!(resource_from_header(&[1, 2, 3]), None);
+    }
+    #[test]
+    fn test_upload_roundtrip() {
+        let data = (0..1000).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let packets = encode_upload(&data);
+        assert_eq!(
+            packets.len(),
+            2 + (1000 + UPLOAD_CHUNK_SIZE - 1) / UPLOAD_CHUNK_SIZE
+        );
+        let mut resource_data = ResourceData::new();
+        for (i, packet) in packets.iter().enumerate() {
+            let mut index = 0;
+            index.decode(packet).unwrap();
+            assert_eq!(index, i as u32);
+            assert!(packet.len() <= 5 + 256);
+            resource_data.process_data(&packet[5..]).unwrap();
+        }
+        assert_eq!(resource_data.state, ResourceState::Success);
+        assert_eq!(resource_data.data, data);
+        assert_eq!(resource_data.take(), Some(data));
+        assert_eq!(resource_data.state, ResourceState::New);
+        assert_eq!(resource_data.take(), None);
+    }
+    #[test]
+    fn test_resource_from_sysex() {
+        let data = U7::try_from_bytes(&[3, b'W', b'T', 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 1]).unwrap();
+        assert_eq!(
+            Resource::from_sysex(data),
+            Ok(Resource::new(3, "WT".to_string(), 1024, 1))
+        );
+        let data = U7::try_from_bytes(&[3, b'W', b'T', 0, 0, 0, 0, 4, 0]).unwrap();
+        assert_eq!(Resource::from_sysex(data), Err(SysexError::NotEnoughData));
+        let data = U7::try_from_bytes(&[3, b'W', b'T']).unwrap();
+        assert_eq!(
+            Resource::from_sysex(data),
+            Err(SysexError::MissingTerminator)
+        );
+        assert_eq!(Resource::from_sysex(&[]), Err(SysexError::NotEnoughData));
+    }
 }
@@ -0,0 +1,632 @@
+//! Typed description of device configuration settings, pending edits and saved profiles.
+use anyhow::anyhow;
+use owl_midi::{SysexConfiguration, SYSEX_CONFIGURATIONS};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// Type of values accepted by a setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SettingKind {
+    /// Integer in inclusive range
+    Range { min: i64, max: i64 },
+    /// 0 or 1
+    Toggle,
+    /// One of the listed values with their labels
+    Choice(&'static [(i64, &'static str)]),
+}
+
+/// Name, description and valid values of a setting
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SettingInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub kind: SettingKind,
+    pub unit: &'static str,
+}
+
+/// Value that doesn't fit a setting
+#[derive(Debug, PartialEq, Eq)]
+pub enum SettingError {
+    NotANumber(String),
+    OutOfRange { value: i64, min: i64, max: i64 },
+    InvalidChoice(i64),
+}
+
+impl fmt::Display for SettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingError::NotANumber(text) => write!(f, "{text} is not a number"),
+            SettingError::OutOfRange { value, min, max } => {
+                write!(f, "{value} is not in range {min}..={max}")
+            }
+            SettingError::InvalidChoice(value) => write!(f, "{value} is not a valid choice"),
+        }
+    }
+}
+
+impl std::error::Error for SettingError {}
+
+const UNBOUNDED: SettingKind = SettingKind::Range {
+    min: i64::MIN,
+    max: i64::MAX,
+};
+
+const MIDI_CHANNELS: &[(i64, &str)] = &[
+    (-1, "Omni"),
+    (0, "1"),
+    (1, "2"),
+    (2, "3"),
+    (3, "4"),
+    (4, "5"),
+    (5, "6"),
+    (6, "7"),
+    (7, "8"),
+    (8, "9"),
+    (9, "10"),
+    (10, "11"),
+    (11, "12"),
+    (12, "13"),
+    (13, "14"),
+    (14, "15"),
+    (15, "16"),
+];
+
+const fn info(
+    name: &'static str,
+    description: &'static str,
+    kind: SettingKind,
+    unit: &'static str,
+) -> SettingInfo {
+    SettingInfo {
+        name,
+        description,
+        kind,
+        unit,
+    }
+}
+
+// Settings are identified by their 2 character code, these are defined by OpenWare firmware.
+// Ranges and choices are values offered for editing, devices can report and accept others.
+const SCHEMA: &[(&str, SettingInfo)] = &[
+    (
+        "FS",
+        info(
+            "Sample rate",
+            "Audio sampling rate",
+            SettingKind::Choice(&[
+                (8000, "8000"),
+                (16000, "16000"),
+                (32000, "32000"),
+                (44100, "44100"),
+                (48000, "48000"),
+                (96000, "96000"),
+            ]),
+            "Hz",
+        ),
+    ),
+    (
+        "AB",
+        info(
+            "Bit depth",
+            "Audio codec sample size",
+            SettingKind::Choice(&[(16, "16"), (24, "24"), (32, "32")]),
+            "bits",
+        ),
+    ),
+    (
+        "BS",
+        info(
+            "Block size",
+            "Number of samples processed by patch at once",
+            SettingKind::Choice(&[
+                (16, "16"),
+                (32, "32"),
+                (64, "64"),
+                (128, "128"),
+                (256, "256"),
+            ]),
+            "samples",
+        ),
+    ),
+    (
+        "DF",
+        info("Data format", "Audio sample data format", UNBOUNDED, ""),
+    ),
+    (
+        "CP",
+        info(
+            "Codec protocol",
+            "Serial audio protocol used by codec",
+            SettingKind::Choice(&[(0, "I2S"), (1, "PCM")]),
+            "",
+        ),
+    ),
+    (
+        "CM",
+        info(
+            "Codec master",
+            "Codec generates audio clocks",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    (
+        "CS",
+        info(
+            "Swap channels",
+            "Swap left and right audio channels",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    (
+        "CB",
+        info(
+            "Codec bypass",
+            "Pass audio input directly to output",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    (
+        "HS",
+        info(
+            "Half speed",
+            "Run codec at half sampling rate",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    (
+        "HP",
+        info(
+            "Highpass",
+            "Codec input DC blocking filter",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    ("IG", info("Input gain", "Codec input gain", UNBOUNDED, "")),
+    (
+        "OG",
+        info("Output gain", "Codec output gain", UNBOUNDED, ""),
+    ),
+    (
+        "PC",
+        info(
+            "Program change button",
+            "Change patches with device button",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    (
+        "OI",
+        info(
+            "Input offset",
+            "Calibration offset for CV input",
+            UNBOUNDED,
+            "",
+        ),
+    ),
+    (
+        "SI",
+        info(
+            "Input scalar",
+            "Calibration scale for CV input",
+            UNBOUNDED,
+            "",
+        ),
+    ),
+    (
+        "OO",
+        info(
+            "Output offset",
+            "Calibration offset for CV output",
+            UNBOUNDED,
+            "",
+        ),
+    ),
+    (
+        "SO",
+        info(
+            "Output scalar",
+            "Calibration scale for CV output",
+            UNBOUNDED,
+            "",
+        ),
+    ),
+    (
+        "MI",
+        info(
+            "MIDI input channel",
+            "Channel that device listens to",
+            SettingKind::Choice(MIDI_CHANNELS),
+            "",
+        ),
+    ),
+    (
+        "MO",
+        info(
+            "MIDI output channel",
+            "Channel that device sends on",
+            SettingKind::Choice(MIDI_CHANNELS),
+            "",
+        ),
+    ),
+    (
+        "BE",
+        info(
+            "Bus enable",
+            "Enable digital bus for connecting devices",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+    (
+        "BM",
+        info(
+            "Bus MIDI forwarding",
+            "Forward MIDI messages to digital bus",
+            SettingKind::Toggle,
+            "",
+        ),
+    ),
+];
+
+/// Two character code that device uses for a setting
+pub fn setting_code(config: SysexConfiguration) -> String {
+    let code = config as isize;
+    String::from_utf8_lossy(&[(code >> 8) as u8, (code & 0xff) as u8]).to_string()
+}
+
+/// Returns setting description, unknown settings accept any integer
+pub fn setting_info(config: SysexConfiguration) -> SettingInfo {
+    let code = setting_code(config);
+    SCHEMA
+        .iter()
+        .find(|(schema_code, _)| *schema_code == code)
+        .map(|(_, info)| *info)
+        .unwrap_or(SettingInfo {
+            name: "",
+            description: "",
+            kind: UNBOUNDED,
+            unit: "",
+        })
+}
+
+/// Readable setting name, protocol name is used for settings missing from schema
+pub fn setting_name(config: SysexConfiguration) -> String {
+    match setting_info(config).name {
+        "" => format!("{config:?}"),
+        name => name.to_string(),
+    }
+}
+
+/// Finds setting by its code, schema name or protocol name, ignoring case
+pub fn find_setting(name: &str) -> Option<SysexConfiguration> {
+    SYSEX_CONFIGURATIONS.iter().copied().find(|&config| {
+        let info = setting_info(config);
+        setting_code(config).eq_ignore_ascii_case(name)
+            || (!info.name.is_empty() && info.name.eq_ignore_ascii_case(name))
+            || format!("{config:?}").eq_ignore_ascii_case(name)
+    })
+}
+
+impl SettingInfo {
+    /// Checks that value is accepted by this setting
+    pub fn validate(&self, value: i64) -> Result<i64, SettingError> {
+        match self.kind {
+            SettingKind::Range { min, max } if value < min || value > max => {
+                Err(SettingError::OutOfRange { value, min, max })
+            }
+            SettingKind::Toggle if value != 0 && value != 1 => Err(SettingError::OutOfRange {
+                value,
+                min: 0,
+                max: 1,
+            }),
+            SettingKind::Choice(choices) if !choices.iter().any(|(choice, _)| *choice == value) => {
+                Err(SettingError::InvalidChoice(value))
+            }
+            _ => Ok(value),
+        }
+    }
+    /// Parses a decimal value, values outside schema are only reported by `validate`
+    pub fn parse(&self, text: &str) -> Result<i64, SettingError> {
+        text.trim()
+            .parse::<i64>()
+            .map_err(|_| SettingError::NotANumber(text.to_string()))
+    }
+    /// Value formatted with its choice label or unit
+    pub fn format(&self, value: i64) -> String {
+        let text = match self.kind {
+            SettingKind::Toggle => return if value != 0 { "On" } else { "Off" }.to_string(),
+            SettingKind::Choice(choices) => choices
+                .iter()
+                .find(|(choice, _)| *choice == value)
+                .map(|(_, label)| label.to_string())
+                .unwrap_or_else(|| value.to_string()),
+            SettingKind::Range { .. } => value.to_string(),
+        };
+        if self.unit.is_empty() {
+            text
+        } else {
+            format!("{text} {}", self.unit)
+        }
+    }
+}
+
+/// Setting edited by user that differs from device value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SettingChange {
+    pub config: SysexConfiguration,
+    /// Value reported by device, if it was received
+    pub device_value: Option<i64>,
+    pub value: i64,
+}
+
+impl SettingChange {
+    /// Checks edited value against schema, values outside it are still sent
+    pub fn validate(&self) -> Result<i64, SettingError> {
+        setting_info(self.config).validate(self.value)
+    }
+}
+
+/// Edits made to settings that haven't been sent to device yet
+#[derive(Default)]
+pub struct SettingsEdits {
+    values: HashMap<SysexConfiguration, i64>,
+}
+
+impl SettingsEdits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Edited value, or device value if setting wasn't changed
+    pub fn value(
+        &self,
+        device: &HashMap<SysexConfiguration, i64>,
+        config: SysexConfiguration,
+    ) -> Option<i64> {
+        self.values
+            .get(&config)
+            .or_else(|| device.get(&config))
+            .copied()
+    }
+    /// Stores edited value, edits equal to device value are dropped.
+    /// Values outside schema are kept, since devices can report them too.
+    pub fn set(
+        &mut self,
+        device: &HashMap<SysexConfiguration, i64>,
+        config: SysexConfiguration,
+        value: i64,
+    ) {
+        if device.get(&config) == Some(&value) {
+            self.values.remove(&config);
+        } else {
+            self.values.insert(config, value);
+        }
+    }
+    pub fn revert(&mut self, config: SysexConfiguration) {
+        self.values.remove(&config);
+    }
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+    /// Replaces edits with values from profile, nothing is changed if profile is invalid
+    pub fn load_profile(
+        &mut self,
+        device: &HashMap<SysexConfiguration, i64>,
+        profile: &SettingsProfile,
+    ) -> anyhow::Result<()> {
+        let values = profile.values()?;
+        self.clear();
+        for (config, value) in values {
+            self.set(device, config, value);
+        }
+        Ok(())
+    }
+    /// Edits that differ from device values, in the order settings are listed
+    pub fn changes(&self, device: &HashMap<SysexConfiguration, i64>) -> Vec<SettingChange> {
+        SYSEX_CONFIGURATIONS
+            .iter()
+            .filter_map(|config| {
+                let value = *self.values.get(config)?;
+                let device_value = device.get(config).copied();
+                (device_value != Some(value)).then_some(SettingChange {
+                    config: *config,
+                    device_value,
+                    value,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Extension used for settings profiles
+pub const PROFILE_EXTENSION: &str = "json";
+
+/// Settings saved to a file, so that units can be configured identically.
+/// Settings are stored by name, codes and protocol names are also accepted when loading.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, Eq)]
+pub struct SettingsProfile {
+    /// Device that settings were exported from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    pub settings: BTreeMap<String, i64>,
+}
+
+impl SettingsProfile {
+    pub fn new(device: Option<String>, settings: &HashMap<SysexConfiguration, i64>) -> Self {
+        SettingsProfile {
+            device,
+            settings: settings
+                .iter()
+                .map(|(&config, &value)| (setting_name(config), value))
+                .collect(),
+        }
+    }
+    pub fn to_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec_pretty(self)
+    }
+    pub fn from_json(data: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(data)
+    }
+    /// Resolves all settings, profile is rejected if any of them is unknown.
+    /// Values aren't checked against schema, since exported values come from device.
+    pub fn values(&self) -> anyhow::Result<Vec<(SysexConfiguration, i64)>> {
+        self.settings
+            .iter()
+            .map(|(name, &value)| {
+                let config = find_setting(name).ok_or_else(|| anyhow!("Unknown setting {name}"))?;
+                Ok((config, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config_with_code(code: &str) -> Option<SysexConfiguration> {
+        SYSEX_CONFIGURATIONS
+            .iter()
+            .copied()
+            .find(|&config| setting_code(config) == code)
+    }
+
+    #[test]
+    fn test_validate() {
+        let toggle = info("", "", SettingKind::Toggle, "");
+        assert_eq!(toggle.validate(1), Ok(1));
+        assert!(toggle.validate(2).is_err());
+        let range = info("", "", SettingKind::Range { min: 0, max: 127 }, "dB");
+        assert_eq!(range.parse(" 64 "), Ok(64));
+        assert_eq!(range.parse("128"), Ok(128));
+        assert_eq!(
+            range.validate(128),
+            Err(SettingError::OutOfRange {
+                value: 128,
+                min: 0,
+                max: 127
+            })
+        );
+        assert!(matches!(range.parse("x"), Err(SettingError::NotANumber(_))));
+        assert_eq!(range.format(3), "3 dB");
+        let choice = info("", "", SettingKind::Choice(MIDI_CHANNELS), "");
+        assert_eq!(choice.validate(-1), Ok(-1));
+        assert_eq!(choice.validate(16), Err(SettingError::InvalidChoice(16)));
+        assert_eq!(choice.format(0), "1");
+    }
+
+    #[test]
+    fn test_schema_codes() {
+        for (code, info) in SCHEMA {
+            assert_eq!(code.len(), 2, "{}", info.name);
+            let config =
+                config_with_code(code).unwrap_or_else(|| panic!("{code} is not a device setting"));
+            assert_eq!(setting_info(config), *info);
+            assert_eq!(find_setting(code), Some(config));
+        }
+        // Codes that are easy to confuse, names as defined by OpenWare
+        for (code, name) in [
+            ("PC", "SYSEX_CONFIGURATION_PC_BUTTON"),
+            ("CP", "SYSEX_CONFIGURATION_CODEC_PROTOCOL"),
+            ("CM", "SYSEX_CONFIGURATION_CODEC_MASTER"),
+            ("CS", "SYSEX_CONFIGURATION_CODEC_SWAP"),
+            ("CB", "SYSEX_CONFIGURATION_CODEC_BYPASS"),
+        ] {
+            assert_eq!(format!("{:?}", config_with_code(code).unwrap()), name);
+        }
+        for &config in SYSEX_CONFIGURATIONS.iter() {
+            assert_eq!(find_setting(&format!("{config:?}")), Some(config));
+        }
+    }
+
+    #[test]
+    fn test_edits() {
+        let config = SYSEX_CONFIGURATIONS[0];
+        let info = setting_info(config);
+        let value = match info.kind {
+            SettingKind::Range { min, .. } => min,
+            SettingKind::Toggle => 0,
+            SettingKind::Choice(choices) => choices[0].0,
+        };
+        let mut device = HashMap::new();
+        let mut edits = SettingsEdits::new();
+        assert_eq!(edits.value(&device, config), None);
+        edits.set(&device, config, value);
+        assert_eq!(
+            edits.changes(&device),
+            vec![SettingChange {
+                config,
+                device_value: None,
+                value
+            }]
+        );
+        device.insert(config, value);
+        assert!(edits.changes(&device).is_empty());
+        assert_eq!(edits.value(&device, config), Some(value));
+        edits.set(&device, config, value);
+        assert!(edits.values.is_empty());
+    }
+
+    #[test]
+    fn test_edits_outside_schema() {
+        let config = config_with_code("BS").unwrap();
+        // Device reported value that schema doesn't list can be kept and edited back to
+        let device = HashMap::from([(config, 512)]);
+        let mut edits = SettingsEdits::new();
+        edits.set(&device, config, 64);
+        edits.set(&device, config, 512);
+        assert!(edits.changes(&device).is_empty());
+        edits.set(&device, config, 1024);
+        let changes = edits.changes(&device);
+        assert_eq!(changes[0].value, 1024);
+        assert_eq!(
+            changes[0].validate(),
+            Err(SettingError::InvalidChoice(1024))
+        );
+    }
+
+    #[test]
+    fn test_profile() {
+        let config = SYSEX_CONFIGURATIONS[0];
+        let value = match setting_info(config).kind {
+            SettingKind::Range { max, .. } => max,
+            SettingKind::Toggle => 1,
+            SettingKind::Choice(choices) => choices[choices.len() - 1].0,
+        };
+        let device = HashMap::from([(config, value)]);
+        let profile = SettingsProfile::new(Some("OWL".to_string()), &device);
+        let json = profile.to_json().unwrap();
+        let loaded = SettingsProfile::from_json(&json).unwrap();
+        assert_eq!(loaded, profile);
+        assert_eq!(loaded.values().unwrap(), vec![(config, value)]);
+
+        let mut edits = SettingsEdits::new();
+        edits.load_profile(&HashMap::new(), &loaded).unwrap();
+        assert_eq!(edits.changes(&HashMap::new()).len(), 1);
+        edits.load_profile(&device, &loaded).unwrap();
+        assert!(edits.changes(&device).is_empty());
+
+        // Device values outside schema are exported and imported again
+        let block_size = config_with_code("BS").unwrap();
+        let device = HashMap::from([(block_size, 512)]);
+        let json = SettingsProfile::new(None, &device).to_json().unwrap();
+        let loaded = SettingsProfile::from_json(&json).unwrap();
+        assert_eq!(loaded.values().unwrap(), vec![(block_size, 512)]);
+        edits.load_profile(&HashMap::new(), &loaded).unwrap();
+        assert_eq!(edits.changes(&HashMap::new())[0].value, 512);
+        edits.load_profile(&device, &loaded).unwrap();
+        assert!(edits.changes(&device).is_empty());
+
+        let unknown = SettingsProfile::from_json(br#"{"settings": {"No such setting": 1}}"#);
+        assert!(unknown.unwrap().values().is_err());
+        let code = setting_code(config);
+        let invalid = format!(r#"{{"settings": {{"{code}": 1, "x": "y"}}}}"#);
+        assert!(SettingsProfile::from_json(invalid.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! Named parameter snapshots stored per patch and morphing between them.
+use super::parameter::{parameter_id, OwlParameter};
+use super::patch_collection::PatchCollection;
+use owl_midi::PatchParameterId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// Default number of parameter updates per second while morphing
+pub const DEFAULT_MORPH_RATE: f64 = 25.0;
+
+/// Parameter values saved under a name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    pub name: String,
+    pub values: BTreeMap<String, f32>,
+}
+
+impl Snapshot {
+    /// Stores current values of all parameters, outputs are skipped as patch controls them
+    pub fn capture(name: String, parameters: &HashMap<PatchParameterId, OwlParameter>) -> Self {
+        Snapshot {
+            name,
+            values: parameters
+                .iter()
+                .filter(|(_, parameter)| !parameter.output)
+                .map(|(pid, parameter)| (pid.string_id().to_string(), parameter.value))
+                .collect(),
+        }
+    }
+    /// Parameter values, unknown IDs are skipped
+    pub fn values(&self) -> impl Iterator<Item = (PatchParameterId, f32)> + '_ {
+        self.values
+            .iter()
+            .filter_map(|(id, &value)| parameter_id(id).map(|pid| (pid, value)))
+    }
+    /// Values interpolated between two snapshots, parameters missing from one of them keep
+    /// value from the other one
+    pub fn morph(&self, other: &Snapshot, amount: f32) -> Vec<(PatchParameterId, f32)> {
+        let amount = amount.clamp(0.0, 1.0);
+        let mut values: BTreeMap<String, f32> = self.values.clone();
+        for (id, &to) in other.values.iter() {
+            let from = *values.get(id).unwrap_or(&to);
+            values.insert(id.clone(), from + (to - from) * amount);
+        }
+        values
+            .iter()
+            .filter_map(|(id, &value)| parameter_id(id).map(|pid| (pid, value)))
+            .collect()
+    }
+}
+
+/// Sets parameter values, changed parameters are sent to device on next sync
+pub fn apply_values(
+    parameters: &mut HashMap<PatchParameterId, OwlParameter>,
+    values: impl IntoIterator<Item = (PatchParameterId, f32)>,
+) {
+    for (pid, value) in values {
+        if let Some(parameter) = parameters.get_mut(&pid) {
+            if !parameter.output {
+                parameter.value = value.clamp(0.0, 1.0);
+            }
+        }
+    }
+}
+
+/// Snapshots for every patch, stored by patch name
+pub type SnapshotLibrary = PatchCollection<Snapshot>;
+
+impl SnapshotLibrary {
+    pub fn snapshots(&self, patch: &str) -> &[Snapshot] {
+        self.get(patch)
+    }
+    /// Adds snapshot, replacing existing one with the same name
+    pub fn store(&mut self, patch: &str, snapshot: Snapshot) {
+        let name = snapshot.name.clone();
+        self.replace(patch, snapshot, |existing| existing.name == name);
+    }
+}
+
+/// Morph position between two snapshots, updates are limited to a number per second
+#[derive(Debug, PartialEq)]
+pub struct Morph {
+    pub from: usize,
+    pub to: usize,
+    pub amount: f32,
+    /// Maximum number of parameter updates per second
+    pub rate: f64,
+    applied: Option<f32>,
+    last_update: f64,
+}
+
+impl Default for Morph {
+    fn default() -> Self {
+        Morph {
+            from: 0,
+            to: 1,
+            amount: 0.0,
+            rate: DEFAULT_MORPH_RATE,
+            // Nothing is sent until amount is changed
+            applied: Some(0.0),
+            last_update: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl Morph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns amount that should be applied now, if it changed and rate limit allows it
+    pub fn poll(&mut self, now: f64) -> Option<f32> {
+        if self.applied == Some(self.amount) || now - self.last_update < 1.0 / self.rate {
+            return None;
+        }
+        self.applied = Some(self.amount);
+        self.last_update = now;
+        Some(self.amount)
+    }
+    /// Seconds until pending amount can be applied
+    pub fn pending(&self, now: f64) -> Option<f64> {
+        (self.applied != Some(self.amount))
+            .then(|| (self.last_update + 1.0 / self.rate - now).max(0.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parameters(values: &[(isize, f32)]) -> HashMap<PatchParameterId, OwlParameter> {
+        values
+            .iter()
+            .map(|&(i, value)| {
+                let pid = PatchParameterId::try_from(i).unwrap();
+                let mut parameter = OwlParameter::new(pid.string_id().to_string());
+                parameter.value = value;
+                (pid, parameter)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_morph() {
+        let mut params = parameters(&[(0, 0.0), (1, 1.0)]);
+        params.insert(
+            PatchParameterId::try_from(2).unwrap(),
+            OwlParameter::new("Out>".to_string()),
+        );
+        let a = Snapshot::capture("a".to_string(), &params);
+        assert_eq!(a.values.len(), 2);
+        let b = Snapshot::capture(
+            "b".to_string(),
+            &parameters(&[(0, 1.0), (1, 0.0), (3, 0.5)]),
+        );
+        let pid = |i| PatchParameterId::try_from(i).unwrap();
+        let values = a.morph(&b, 0.25);
+        assert!(values.contains(&(pid(0), 0.25)));
+        assert!(values.contains(&(pid(1), 0.75)));
+        assert!(values.contains(&(pid(3), 0.5)));
+
+        apply_values(&mut params, values);
+        assert_eq!(params[&pid(0)].value, 0.25);
+        assert_eq!(params[&pid(2)].value, 0.0);
+        assert!(!params.contains_key(&pid(3)));
+    }
+
+    #[test]
+    fn test_library() {
+        let mut library = SnapshotLibrary::new();
+        let params = parameters(&[(0, 0.5)]);
+        library.store("Patch", Snapshot::capture("a".to_string(), &params));
+        library.store("Patch", Snapshot::capture("b".to_string(), &params));
+        library.store("Patch", Snapshot::capture("a".to_string(), &HashMap::new()));
+        let snapshots = library.snapshots("Patch");
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots[0].values.is_empty());
+        assert!(library.snapshots("Other").is_empty());
+
+        let json = serde_json::to_string(&library).unwrap();
+        assert_eq!(
+            serde_json::from_str::<SnapshotLibrary>(&json).unwrap(),
+            library
+        );
+        library.remove("Patch", 0);
+        library.remove("Patch", 0);
+        assert!(library.patches.is_empty());
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut morph = Morph::new();
+        morph.rate = 10.0;
+        assert_eq!(morph.poll(0.0), None);
+        assert_eq!(morph.pending(0.0), None);
+        morph.amount = 0.5;
+        assert_eq!(morph.poll(1.0), Some(0.5));
+        morph.amount = 0.6;
+        assert_eq!(morph.poll(1.05), None);
+        assert!((morph.pending(1.05).unwrap() - 0.05).abs() < 1e-9);
+        assert_eq!(morph.poll(1.2), Some(0.6));
+        assert_eq!(morph.pending(1.2), None);
+    }
+}
@@ -1,5 +1,43 @@
+//! Encoding of values in 7-bit sysex data and protocol errors.
+use owl_midi::OpenWareMidiSysexCommand;
+use std::fmt;
 use wmidi::U7;
 
+/// Errors returned when OWL sysex data can't be decoded or encoded.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum SysexError {
+    /// Message is shorter than its command requires
+    NotEnoughData,
+    /// Output buffer doesn't have the size required by encoded value
+    InvalidBufferSize,
+    /// Output buffer is too small for decoded data
+    BufferTooSmall,
+    /// Command byte is not a known OWL sysex command
+    UnknownCommand(u8),
+    /// Parameter ID is out of range
+    InvalidParameter(u8),
+    /// Patch or resource slot is out of range
+    InvalidSlot(u8),
+    /// String field is not null-terminated
+    MissingTerminator,
+}
+
+impl fmt::Display for SysexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SysexError::NotEnoughData => write!(f, "Not enough data"),
+            SysexError::InvalidBufferSize => write!(f, "Invalid buffer size"),
+            SysexError::BufferTooSmall => write!(f, "Buffer too small"),
+            SysexError::UnknownCommand(cmd) => write!(f, "Unknown command 0x{cmd:02x}"),
+            SysexError::InvalidParameter(pid) => write!(f, "Invalid parameter ID {pid}"),
+            SysexError::InvalidSlot(slot) => write!(f, "Invalid slot {slot}"),
+            SysexError::MissingTerminator => write!(f, "Missing string terminator"),
+        }
+    }
+}
+
+impl std::error::Error for SysexError {}
+
 #[derive(PartialEq, Eq, Debug)]
 pub struct CodingResult {
     pub bytes_read: usize,
@@ -28,7 +66,7 @@ pub trait SysexData {
 }
 
 impl SysexData for u32 {
-    type Error = &'static str;
+    type Error = SysexError;
 
     fn decode(&mut self, data: &[U7]) -> Result<CodingResult, Self::Error> {
         if data.len() >= 5 {
@@ -52,13 +90,13 @@ impl SysexData for u32 {
             *self = y;
             Ok(CodingResult::new(5, 4))
         } else {
-            Err("Not enough data")
+            Err(SysexError::NotEnoughData)
         }
     }
 
     fn encode(&self, buf: &mut [U7]) -> Result<CodingResult, Self::Error> {
         if buf.len() != 5 {
-            Err("Invalid buffer size")
+            Err(SysexError::InvalidBufferSize)
         } else {
             let mut first: u8 = 0;
             let value = *self;
@@ -87,7 +125,7 @@ impl SysexData for u32 {
 
 impl SysexData for [u8] {
     //    impl SysexData for Vec<u8> {
-    type Error = &'static str;
+    type Error = SysexError;
 
     fn decode(&mut self, data: &[U7]) -> Result<CodingResult, Self::Error> {
         let own_len = self.len();
@@ -97,7 +135,7 @@ impl SysexData for [u8] {
             max_len += len % 8;
         }
         if own_len < max_len {
-            Err("Buffer too small")
+            Err(SysexError::BufferTooSmall)
         } else {
             let mut bitmask = 0;
             if !data.is_empty() {
@@ -123,6 +161,9 @@ impl SysexData for [u8] {
     }
 
     fn encode(&self, buf: &mut [U7]) -> Result<CodingResult, Self::Error> {
+        if buf.len() < self.len() + (self.len() + 6) / 7 {
+            return Err(SysexError::BufferTooSmall);
+        }
         let mut pos = 0;
         let mut bitmask = 0;
         let mut cnt7 = 0;
@@ -147,6 +188,28 @@ impl SysexData for [u8] {
     }
 }
 
+/// Command and string payload up to null terminator of a complete OWL sysex message,
+/// including start and end bytes
+pub fn string_message(data: &[u8]) -> Option<(OpenWareMidiSysexCommand, String)> {
+    match data {
+        [0xf0, manufacturer, device, command, payload @ .., 0xf7]
+            if *manufacturer as u32 == owl_midi::MIDI_SYSEX_MANUFACTURER
+                && *device as u32 == owl_midi::MIDI_SYSEX_OWL_DEVICE =>
+        {
+            let command = OpenWareMidiSysexCommand::try_from(*command as isize).ok()?;
+            let end = payload
+                .iter()
+                .position(|&x| x == 0)
+                .unwrap_or(payload.len());
+            Some((
+                command,
+                String::from_utf8_lossy(&payload[..end]).to_string(),
+            ))
+        }
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod test {
     use crate::owl_control::sysex::*;
@@ -158,7 +221,7 @@ mod test {
         assert_eq!(result.decode(data), Ok(CodingResult::new(5, 4)));
         assert_eq!(result, 1);
         let data = U7::try_from_bytes(&[0, 0, 0, 1]).unwrap();
-        assert_eq!(result.decode(data), Err("Not enough data"));
+        assert_eq!(result.decode(data), Err(SysexError::NotEnoughData));
         let data = U7::try_from_bytes(&[4, 0, 0, 0, 0x2c]).unwrap();
         assert_eq!(result.decode(data), Ok(CodingResult::new(5, 4)));
         assert_eq!(result, 32812);
@@ -176,6 +239,20 @@ mod test {
         assert_eq!(data, result.as_slice());
     }
     #[test]
+    fn test_encode_buffer_too_small() {
+        let mut result = [U7::MIN; 4];
+        assert_eq!(1u32.encode(&mut result), Err(SysexError::InvalidBufferSize));
+        let data = [1, 2, 3, 4, 5, 6, 7, 8].as_slice();
+        let buf = &mut [U7::MIN; 9];
+        assert_eq!(
+            data.encode(buf.as_mut_slice()),
+            Err(SysexError::BufferTooSmall)
+        );
+        let data = U7::try_from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7, 0, 8]).unwrap();
+        let mut result = [0; 4];
+        assert_eq!(result.decode(data), Err(SysexError::BufferTooSmall));
+    }
+    #[test]
     fn test_decode_u8_slice() {
         let data = U7::try_from_bytes(&[0, 0]).unwrap();
         let mut result = [0; 32];
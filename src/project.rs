@@ -0,0 +1,150 @@
+//! Project files that store wavetable grid for later editing.
+use crate::grid::Grid;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Extension used for project files
+pub const PROJECT_EXTENSION: &str = "owlwave";
+/// Largest number of waves in a project grid
+pub const MAX_WAVES: usize = 1024;
+/// Largest number of samples in a wave
+pub const MAX_SAMPLES: usize = 8192;
+
+/// Errors returned when a project file can't be opened
+#[derive(Debug)]
+pub enum ProjectError {
+    Json(serde_json::Error),
+    InvalidSize {
+        rows: usize,
+        cols: usize,
+        samples: usize,
+    },
+    InvalidActiveWave(usize),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProjectError::Json(err) => write!(f, "{err}"),
+            ProjectError::InvalidSize {
+                rows,
+                cols,
+                samples,
+            } => write!(
+                f,
+                "Invalid grid of {rows}x{cols} waves with {samples} samples"
+            ),
+            ProjectError::InvalidActiveWave(id) => write!(f, "Invalid active wave {id}"),
+        }
+    }
+}
+
+impl std::error::Error for ProjectError {}
+
+impl From<serde_json::Error> for ProjectError {
+    fn from(err: serde_json::Error) -> Self {
+        ProjectError::Json(err)
+    }
+}
+
+/// Saved wavetable grid, so that editing can be continued later
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Project {
+    pub rows: usize,
+    pub cols: usize,
+    pub samples: usize,
+    pub active_wave_id: usize,
+    pub waves: Vec<Vec<f64>>,
+}
+
+impl Project {
+    pub fn new(grid: &Grid, active_wave_id: usize) -> Self {
+        Project {
+            rows: grid.get_rows(),
+            cols: grid.get_cols(),
+            samples: grid.get_samples(),
+            active_wave_id,
+            waves: (0..grid.get_waves())
+                .map(|i| grid.get_wave_by_id(i).clone())
+                .collect(),
+        }
+    }
+    pub fn to_grid(&self) -> Grid {
+        Grid::from_waves(self.rows, self.cols, self.samples, &self.waves)
+    }
+    pub fn to_json(&self) -> Result<Vec<u8>, serde_json::Error> {
+        serde_json::to_vec_pretty(self)
+    }
+    /// Reads project and checks that its grid can be created
+    pub fn from_json(data: &[u8]) -> Result<Self, ProjectError> {
+        let project: Project = serde_json::from_slice(data)?;
+        let waves = project.rows.saturating_mul(project.cols);
+        if waves == 0 || waves > MAX_WAVES || !(1..=MAX_SAMPLES).contains(&project.samples) {
+            return Err(ProjectError::InvalidSize {
+                rows: project.rows,
+                cols: project.cols,
+                samples: project.samples,
+            });
+        }
+        if project.active_wave_id >= waves {
+            return Err(ProjectError::InvalidActiveWave(project.active_wave_id));
+        }
+        Ok(project)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_roundtrip() {
+        let project = Project {
+            rows: 1,
+            cols: 2,
+            samples: 4,
+            active_wave_id: 1,
+            waves: vec![vec![0.0, 1.0, 0.0, -1.0], vec![]],
+        };
+        let json = project.to_json().unwrap();
+        let loaded = Project::from_json(&json).unwrap();
+        assert_eq!(loaded, project);
+        let grid = loaded.to_grid();
+        assert_eq!(grid.get_wave_by_id(1), &vec![0.0; 4]);
+        assert!(matches!(
+            Project::from_json(b"{}"),
+            Err(ProjectError::Json(_))
+        ));
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut project = Project {
+            rows: 1 << 40,
+            cols: 1 << 40,
+            samples: 4,
+            active_wave_id: 0,
+            waves: Vec::new(),
+        };
+        let json = project.to_json().unwrap();
+        assert!(matches!(
+            Project::from_json(&json),
+            Err(ProjectError::InvalidSize { .. })
+        ));
+        project.rows = 2;
+        project.cols = 2;
+        project.samples = 0;
+        let json = project.to_json().unwrap();
+        assert!(matches!(
+            Project::from_json(&json),
+            Err(ProjectError::InvalidSize { .. })
+        ));
+        project.samples = 4;
+        project.active_wave_id = 4;
+        let json = project.to_json().unwrap();
+        assert!(matches!(
+            Project::from_json(&json),
+            Err(ProjectError::InvalidActiveWave(4))
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+//! Sysex librarian: messages from .syx files sent with a delay and capture of received sysex.
+use crate::log_view::{LogBuffer, LogFilter, LogLayer};
+use crate::owl_control::command_processor::OwlCommandProcessor;
+use std::collections::VecDeque;
+use tracing::{warn, Level};
+use tracing_subscriber::layer::SubscriberExt;
+use wmidi::U7;
+
+pub const SYSEX_EXTENSION: &str = "syx";
+/// Default seconds between sent messages, gives device time to handle each of them
+pub const DEFAULT_DELAY: f64 = 0.05;
+
+/// Splits file contents into complete sysex messages, bytes outside of messages are skipped
+pub fn split_syx(data: &[u8]) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    let mut message: Option<Vec<u8>> = None;
+    for &byte in data {
+        match byte {
+            0xf0 => message = Some(vec![byte]),
+            0xf7 => {
+                if let Some(mut complete) = message.take() {
+                    complete.push(byte);
+                    messages.push(complete);
+                }
+            }
+            // Realtime bytes may be interleaved, other status bytes end unterminated message
+            0xf8..=0xff => {}
+            0x80..=0xff => message = None,
+            _ => {
+                if let Some(message) = &mut message {
+                    message.push(byte);
+                }
+            }
+        }
+    }
+    messages
+}
+
+/// Decodes OWL messages the same way as received ones, returns log of decoded messages
+pub fn decode(messages: &[Vec<u8>]) -> String {
+    let log = LogBuffer::new(usize::MAX);
+    let subscriber = tracing_subscriber::registry().with(LogLayer::new(log.clone()));
+    tracing::subscriber::with_default(subscriber, || {
+        let mut processor = OwlCommandProcessor::new();
+        for message in messages {
+            // Received sysex is handled without start and end bytes
+            let payload = match message.len() {
+                len if len >= 2 => &message[1..len - 1],
+                _ => continue,
+            };
+            let result = match U7::try_from_bytes(payload) {
+                Ok(data) => processor.handle_sysex(data).map_err(|err| err.to_string()),
+                Err(_) => Err("Invalid data byte".to_string()),
+            };
+            if let Err(err) = result {
+                warn!(%err, data = ?message, "malformed sysex");
+            }
+        }
+    });
+    let filter = LogFilter {
+        level: Level::TRACE,
+        text: String::new(),
+    };
+    log.filtered(&filter)
+        .iter()
+        .map(|record| format!("{:>5} {}\n", record.level, record.describe()))
+        .collect()
+}
+
+/// Sysex messages loaded from a file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SysexFile {
+    pub name: String,
+    pub messages: Vec<Vec<u8>>,
+}
+
+impl SysexFile {
+    pub fn new(name: String, data: &[u8]) -> Self {
+        SysexFile {
+            name,
+            messages: split_syx(data),
+        }
+    }
+    /// Total size of messages in bytes
+    pub fn size(&self) -> usize {
+        self.messages.iter().map(Vec::len).sum()
+    }
+}
+
+/// Messages waiting to be sent with a delay between them
+#[derive(Debug)]
+pub struct SendQueue {
+    messages: VecDeque<Vec<u8>>,
+    /// Seconds between messages
+    pub delay: f64,
+    next_time: f64,
+    total: usize,
+}
+
+impl Default for SendQueue {
+    fn default() -> Self {
+        SendQueue {
+            messages: VecDeque::new(),
+            delay: DEFAULT_DELAY,
+            next_time: 0.0,
+            total: 0,
+        }
+    }
+}
+
+impl SendQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Replaces queued messages, first one is sent right away
+    pub fn start(&mut self, messages: impl IntoIterator<Item = Vec<u8>>, now: f64) {
+        self.messages = messages.into_iter().collect();
+        self.total = self.messages.len();
+        self.next_time = now;
+    }
+    pub fn cancel(&mut self) {
+        self.messages.clear();
+        self.total = 0;
+    }
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+    /// Number of sent messages and total number of messages
+    pub fn progress(&self) -> (usize, usize) {
+        (self.total - self.messages.len(), self.total)
+    }
+    /// Returns next message if it's time to send it
+    pub fn poll(&mut self, now: f64) -> Option<Vec<u8>> {
+        if now < self.next_time {
+            return None;
+        }
+        let message = self.messages.pop_front()?;
+        self.next_time = now + self.delay;
+        Some(message)
+    }
+    /// Seconds until next message should be sent
+    pub fn pending(&self, now: f64) -> Option<f64> {
+        (!self.messages.is_empty()).then(|| (self.next_time - now).max(0.0))
+    }
+}
+
+/// Received sysex messages, recorded while capture is active
+#[derive(Debug, Default)]
+pub struct SysexCapture {
+    pub active: bool,
+    messages: Vec<Vec<u8>>,
+}
+
+impl SysexCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Keeps a copy of complete sysex messages
+    pub fn record(&mut self, data: &[u8]) {
+        if self.active && data.first() == Some(&0xf0) && data.last() == Some(&0xf7) {
+            self.messages.push(data.to_vec());
+        }
+    }
+    pub fn messages(&self) -> &[Vec<u8>] {
+        &self.messages
+    }
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+    /// Captured messages concatenated, as stored in .syx files
+    pub fn to_syx(&self) -> Vec<u8> {
+        self.messages.concat()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use owl_midi::OpenWareMidiSysexCommand;
+
+    #[test]
+    fn test_split() {
+        let data = [
+            0x00, 0xf0, 0x7d, 0x01, 0xf7, 0xf0, 0x02, 0xf8, 0x03, 0xf7, 0xf0, 0x04, 0x90, 0xf7,
+            0xf0, 0x05,
+        ];
+        assert_eq!(
+            split_syx(&data),
+            vec![vec![0xf0, 0x7d, 0x01, 0xf7], vec![0xf0, 0x02, 0x03, 0xf7]]
+        );
+        let file = SysexFile::new("a.syx".to_string(), &data);
+        assert_eq!(file.size(), 8);
+    }
+
+    #[test]
+    fn test_decode() {
+        let message = [
+            0xf0,
+            owl_midi::MIDI_SYSEX_MANUFACTURER as u8,
+            owl_midi::MIDI_SYSEX_OWL_DEVICE as u8,
+            OpenWareMidiSysexCommand::SYSEX_FIRMWARE_VERSION as u8,
+            b'v',
+            b'1',
+            0xf7,
+        ];
+        let log = decode(&[message.to_vec(), vec![0xf0, 0x7e, 0xf7]]);
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines[0],
+            " INFO response{command=SYSEX_FIRMWARE_VERSION}: version=v1"
+        );
+        assert!(lines[1].starts_with(" WARN "));
+    }
+
+    #[test]
+    fn test_queue() {
+        let mut queue = SendQueue::new();
+        queue.delay = 0.5;
+        assert_eq!(queue.poll(0.0), None);
+        queue.start(vec![vec![1], vec![2]], 10.0);
+        assert_eq!(queue.pending(10.0), Some(0.0));
+        assert_eq!(queue.poll(10.0), Some(vec![1]));
+        assert_eq!(queue.poll(10.25), None);
+        assert_eq!(queue.pending(10.25), Some(0.25));
+        assert_eq!(queue.progress(), (1, 2));
+        assert_eq!(queue.poll(10.5), Some(vec![2]));
+        assert!(queue.is_empty());
+        assert_eq!(queue.pending(11.0), None);
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut capture = SysexCapture::new();
+        capture.record(&[0xf0, 1, 0xf7]);
+        capture.active = true;
+        capture.record(&[0xf0, 2, 0xf7]);
+        capture.record(&[0xb0, 1, 2]);
+        capture.record(&[0xf0, 3, 0xf7]);
+        assert_eq!(capture.messages().len(), 2);
+        assert_eq!(capture.to_syx(), vec![0xf0, 2, 0xf7, 0xf0, 3, 0xf7]);
+        capture.clear();
+        assert!(capture.messages().is_empty());
+    }
+}
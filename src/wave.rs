@@ -1,3 +1,4 @@
+//! Time and frequency domain representations of a single wave.
 use rustfft::num_complex::Complex;
 use rustfft::{num_traits::Zero, FftPlanner};
 use wavetable::{Float, Harmonic};
@@ -0,0 +1,236 @@
+//! Wavetable captured from audio input: pitch detection and slicing into single cycle waves.
+use std::fmt;
+
+/// Lowest pitch found by detection
+pub const MIN_FREQUENCY: f64 = 30.0;
+/// Highest pitch found by detection
+pub const MAX_FREQUENCY: f64 = 2000.0;
+/// Threshold for normalized difference that is accepted as a period
+const PITCH_THRESHOLD: f32 = 0.15;
+/// Signals quieter than this RMS level aren't captured
+const SILENCE_LEVEL: f32 = 1e-4;
+
+/// Errors returned when waves can't be captured
+#[derive(Debug, PartialEq, Eq)]
+pub enum CaptureError {
+    Silent,
+    NoPitch,
+    NotEnoughSamples,
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CaptureError::Silent => write!(f, "Input is silent"),
+            CaptureError::NoPitch => write!(f, "No pitch detected"),
+            CaptureError::NotEnoughSamples => write!(f, "Not enough samples for a cycle"),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Pitch in Hz of the last samples, found with YIN difference function.
+///
+/// Returns `None` if there aren't enough samples for two periods of the lowest frequency.
+pub fn detect_pitch(samples: &[f32], sample_rate: u32, min: f64, max: f64) -> Option<f64> {
+    let min_lag = ((sample_rate as f64 / max) as usize).max(2);
+    let max_lag = (sample_rate as f64 / min).ceil() as usize;
+    if min_lag >= max_lag || samples.len() < 2 * max_lag + 1 {
+        return None;
+    }
+    let samples = &samples[samples.len() - 2 * max_lag - 1..];
+    let window = max_lag;
+    let difference: Vec<f32> = (0..=max_lag)
+        .map(|lag| {
+            (0..window)
+                .map(|i| samples[i] - samples[i + lag])
+                .map(|delta| delta * delta)
+                .sum()
+        })
+        .collect();
+    // Cumulative mean normalized difference
+    let mut normalized = vec![1.0; max_lag + 1];
+    let mut sum = 0.0;
+    for lag in 1..=max_lag {
+        sum += difference[lag];
+        normalized[lag] = if sum > 0.0 {
+            difference[lag] * lag as f32 / sum
+        } else {
+            1.0
+        };
+    }
+    let mut lag = (min_lag..max_lag).find(|&lag| normalized[lag] < PITCH_THRESHOLD)?;
+    while lag + 1 < max_lag && normalized[lag + 1] < normalized[lag] {
+        lag += 1;
+    }
+    // Parabolic interpolation between neighbouring lags
+    let (before, at, after) = (
+        normalized[lag - 1] as f64,
+        normalized[lag] as f64,
+        normalized[lag + 1] as f64,
+    );
+    let curvature = before - 2.0 * at + after;
+    let offset = if curvature > 0.0 {
+        (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+    } else {
+        0.0
+    };
+    Some(sample_rate as f64 / (lag as f64 + offset))
+}
+
+/// Position of the rising zero crossing closest to `position`, at most `radius` samples away
+fn zero_crossing(samples: &[f32], position: f64, radius: f64) -> Option<f64> {
+    let start = ((position - radius).floor().max(1.0)) as usize;
+    let end = ((position + radius).ceil() as usize).min(samples.len().saturating_sub(1));
+    (start..=end)
+        .filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+        .map(|i| {
+            let (previous, current) = (samples[i - 1], samples[i]);
+            (i - 1) as f64 + (previous / (previous - current)) as f64
+        })
+        .min_by(|a, b| (a - position).abs().total_cmp(&(b - position).abs()))
+}
+
+/// Linearly interpolated sample at fractional position
+fn interpolate(samples: &[f32], position: f64) -> f64 {
+    let index = position.floor() as usize;
+    let fraction = position - index as f64;
+    let current = samples[index] as f64;
+    match samples.get(index + 1) {
+        Some(&next) => current + (next as f64 - current) * fraction,
+        None => current,
+    }
+}
+
+/// Slices up to `cycles` consecutive periods into waves of `size` samples.
+///
+/// Every cycle starts at a rising zero crossing near the expected position, so that waves are
+/// phase aligned even if pitch drifts a little. DC offset is removed before slicing.
+pub fn slice_cycles(samples: &[f32], period: f64, cycles: usize, size: usize) -> Vec<Vec<f64>> {
+    let mean = samples.iter().sum::<f32>() / samples.len().max(1) as f32;
+    let samples: Vec<f32> = samples.iter().map(|sample| sample - mean).collect();
+    let mut waves = Vec::new();
+    let mut start = match zero_crossing(&samples, period / 2.0, period / 2.0) {
+        Some(start) => start,
+        None => return waves,
+    };
+    while waves.len() < cycles && start + period < (samples.len() - 1) as f64 {
+        waves.push(
+            (0..size)
+                .map(|i| interpolate(&samples, start + i as f64 * period / size as f64))
+                .collect(),
+        );
+        let expected = start + period;
+        start = zero_crossing(&samples, expected, period / 4.0).unwrap_or(expected);
+    }
+    waves
+}
+
+/// Captures a number of cycles at detected or given pitch
+#[derive(Clone, Debug, PartialEq)]
+pub struct WaveCapture {
+    pub cycles: usize,
+    /// Pitch in Hz, detected from input when it isn't set
+    pub frequency: Option<f64>,
+}
+
+impl Default for WaveCapture {
+    fn default() -> Self {
+        WaveCapture {
+            cycles: 8,
+            frequency: None,
+        }
+    }
+}
+
+impl WaveCapture {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Returns pitch that was used and waves of `size` samples from the last captured cycles
+    pub fn capture(
+        &self,
+        samples: &[f32],
+        sample_rate: u32,
+        size: usize,
+    ) -> Result<(f64, Vec<Vec<f64>>), CaptureError> {
+        if rms(samples) < SILENCE_LEVEL {
+            return Err(CaptureError::Silent);
+        }
+        let frequency = match self.frequency {
+            Some(frequency) => frequency,
+            None => detect_pitch(samples, sample_rate, MIN_FREQUENCY, MAX_FREQUENCY)
+                .ok_or(CaptureError::NoPitch)?,
+        };
+        let period = sample_rate as f64 / frequency;
+        // One extra period leaves room to find the first zero crossing
+        let len = ((self.cycles + 1) as f64 * period).ceil() as usize + 2;
+        let samples = &samples[samples.len().saturating_sub(len)..];
+        let waves = slice_cycles(samples, period, self.cycles, size);
+        if waves.is_empty() {
+            return Err(CaptureError::NotEnoughSamples);
+        }
+        Ok((frequency, waves))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::PI;
+
+    fn sine(frequency: f32, sample_rate: u32, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| 0.2 + 0.5 * (2.0 * PI * frequency * i as f32 / sample_rate as f32 + 1.0).sin())
+            .collect()
+    }
+
+    #[test]
+    fn test_detect_pitch() {
+        let samples = sine(440.0, 48000, 8192);
+        let pitch = detect_pitch(&samples, 48000, MIN_FREQUENCY, MAX_FREQUENCY).unwrap();
+        assert!((pitch - 440.0).abs() < 0.5, "{pitch}");
+        assert_eq!(detect_pitch(&samples[..100], 48000, 30.0, 2000.0), None);
+    }
+
+    #[test]
+    fn test_slice() {
+        let samples = sine(100.0, 48000, 4800);
+        let waves = slice_cycles(&samples, 480.0, 16, 64);
+        assert_eq!(waves.len(), 9);
+        for wave in waves.iter() {
+            assert_eq!(wave.len(), 64);
+            assert!(wave[0].abs() < 0.01);
+            assert!((wave[16] - 0.5).abs() < 0.01);
+            assert!((wave[48] + 0.5).abs() < 0.01);
+        }
+    }
+
+    #[test]
+    fn test_capture() {
+        let mut capture = WaveCapture::new();
+        capture.cycles = 4;
+        let samples = sine(250.0, 48000, 8192);
+        let (frequency, waves) = capture.capture(&samples, 48000, 256).unwrap();
+        assert!((frequency - 250.0).abs() < 0.5);
+        assert_eq!(waves.len(), 4);
+        capture.frequency = Some(250.0);
+        assert_eq!(capture.capture(&samples, 48000, 256).unwrap().0, 250.0);
+        assert_eq!(
+            capture.capture(&[0.0; 1000], 48000, 256),
+            Err(CaptureError::Silent)
+        );
+        assert_eq!(
+            capture.capture(&samples[..100], 48000, 256),
+            Err(CaptureError::NotEnoughSamples)
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Web MIDI backend for the wasm build.
+//!
+//! Mirrors the parts of midir API used by `midi_devices`, so that port handles work the same
+//! way in the browser. Access is requested with sysex permission on first use and granted
+//! asynchronously, ports show up on the next rescan after user allows it.
+use crate::midi_transport::{MidiTransport, TransportError};
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{MidiAccess, MidiMessageEvent, MidiOptions};
+
+enum Access {
+    Pending,
+    Granted(MidiAccess),
+    Failed(String),
+}
+
+thread_local! {
+    static ACCESS: RefCell<Option<Access>> = RefCell::new(None);
+}
+
+fn error_message(err: &JsValue) -> String {
+    err.as_string()
+        .or_else(|| {
+            err.dyn_ref::<js_sys::Error>()
+                .map(|err| String::from(err.message()))
+        })
+        .unwrap_or_else(|| format!("{err:?}"))
+}
+
+// Requests access on first call, permission prompt is shown by the browser
+fn request_access() {
+    ACCESS.with(|access| {
+        if access.borrow().is_some() {
+            return;
+        }
+        let promise = web_sys::window()
+            .ok_or_else(|| JsValue::from_str("No window"))
+            .and_then(|window| {
+                window
+                    .navigator()
+                    .request_midi_access_with_options(MidiOptions::new().sysex(true))
+            });
+        match promise {
+            Ok(promise) => {
+                *access.borrow_mut() = Some(Access::Pending);
+                spawn_local(async move {
+                    let result = match JsFuture::from(promise).await {
+                        Ok(value) => Access::Granted(value.unchecked_into()),
+                        Err(err) => Access::Failed(error_message(&err)),
+                    };
+                    ACCESS.with(|access| *access.borrow_mut() = Some(result));
+                });
+            }
+            Err(err) => *access.borrow_mut() = Some(Access::Failed(error_message(&err))),
+        }
+    });
+}
+
+/// Returns the reason why MIDI is not available, e.g. permission was denied
+pub fn access_error() -> Option<String> {
+    ACCESS.with(|access| match &*access.borrow() {
+        Some(Access::Failed(err)) => Some(err.clone()),
+        _ => None,
+    })
+}
+
+// Values of a MIDIInputMap or MIDIOutputMap, empty until access is granted
+fn ports<T: JsCast>(map: fn(&MidiAccess) -> JsValue) -> Vec<T> {
+    ACCESS.with(|access| match &*access.borrow() {
+        Some(Access::Granted(access)) => js_sys::Array::from(&map(access))
+            .iter()
+            .map(|entry| js_sys::Array::from(&entry).get(1).unchecked_into())
+            .collect(),
+        _ => Vec::new(),
+    })
+}
+
+#[derive(Clone)]
+pub struct MidiInputPort(web_sys::MidiInput);
+
+pub type MidiInputPorts = Vec<MidiInputPort>;
+
+pub struct MidiInput;
+
+impl MidiInput {
+    pub fn new(_client_name: &str) -> Result<Self, String> {
+        request_access();
+        Ok(MidiInput)
+    }
+    pub fn ports(&self) -> MidiInputPorts {
+        ports(|access| access.inputs().into())
+            .into_iter()
+            .map(MidiInputPort)
+            .collect()
+    }
+    pub fn port_name(&self, port: &MidiInputPort) -> Result<String, String> {
+        port.0.name().ok_or_else(|| "Unnamed port".to_string())
+    }
+    pub fn connect<F, T>(
+        self,
+        port: &MidiInputPort,
+        _port_name: &str,
+        mut callback: F,
+        mut data: T,
+    ) -> Result<MidiInputConnection<T>, String>
+    where
+        T: Send + 'static,
+        F: FnMut(u64, &[u8], &mut T) + Send + 'static,
+    {
+        let handler = Closure::wrap(Box::new(move |event: MidiMessageEvent| {
+            if let Ok(message) = event.data() {
+                // Event time is in milliseconds, midir uses microseconds
+                callback((event.time_stamp() * 1000.0) as u64, &message, &mut data);
+            }
+        }) as Box<dyn FnMut(MidiMessageEvent)>);
+        port.0
+            .set_onmidimessage(Some(handler.as_ref().unchecked_ref()));
+        Ok(MidiInputConnection {
+            port: port.0.clone(),
+            handler,
+            _data: PhantomData,
+        })
+    }
+}
+
+/// Receives messages until dropped
+pub struct MidiInputConnection<T> {
+    port: web_sys::MidiInput,
+    handler: Closure<dyn FnMut(MidiMessageEvent)>,
+    _data: PhantomData<T>,
+}
+
+impl<T> Drop for MidiInputConnection<T> {
+    fn drop(&mut self) {
+        // Port objects are shared, so a new connection to the same port may already be set
+        if let Some(current) = self.port.onmidimessage() {
+            if JsValue::from(current) == *self.handler.as_ref() {
+                self.port.set_onmidimessage(None);
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct MidiOutputPort(web_sys::MidiOutput);
+
+pub type MidiOutputPorts = Vec<MidiOutputPort>;
+
+pub struct MidiOutput;
+
+impl MidiOutput {
+    pub fn new(_client_name: &str) -> Result<Self, String> {
+        request_access();
+        Ok(MidiOutput)
+    }
+    pub fn ports(&self) -> MidiOutputPorts {
+        ports(|access| access.outputs().into())
+            .into_iter()
+            .map(MidiOutputPort)
+            .collect()
+    }
+    pub fn port_name(&self, port: &MidiOutputPort) -> Result<String, String> {
+        port.0.name().ok_or_else(|| "Unnamed port".to_string())
+    }
+    pub fn connect(
+        self,
+        port: &MidiOutputPort,
+        _port_name: &str,
+    ) -> Result<MidiOutputConnection, String> {
+        Ok(MidiOutputConnection {
+            port: port.0.clone(),
+        })
+    }
+}
+
+pub struct MidiOutputConnection {
+    port: web_sys::MidiOutput,
+}
+
+// JS objects can't be shared between threads, but wasm build runs on a single thread
+unsafe impl Send for MidiOutputConnection {}
+
+impl MidiTransport for MidiOutputConnection {
+    fn send(&mut self, message: &[u8]) -> Result<(), TransportError> {
+        self.port
+            .send(&js_sys::Uint8Array::from(message))
+            .map_err(|err| TransportError::Send(error_message(&err)))
+    }
+}